@@ -0,0 +1,85 @@
+//! Throughput of `send` against a local mock of the messages API.
+//!
+//! Run with `cargo bench`, comparing against a saved baseline with
+//! `cargo bench -- --save-baseline main` and `cargo bench -- --baseline main`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_util::future::join_all;
+use mailgun46::{Email, EmailBuilder, Mailer, MailerBuilder};
+use tokio::runtime::Runtime;
+use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+async fn mock_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(matchers::method("POST"))
+        .and(matchers::path("/v3/fakedomain/messages"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": "<20210224131116.1.E5C867B3818DC87B@fakedomain>",
+            "message": "Queued. Thank you."
+        })))
+        .mount(&server)
+        .await;
+    server
+}
+
+fn email() -> Email {
+    EmailBuilder::default()
+        .to("someone@example.com")
+        .subject("Your receipt")
+        .text_body("Thanks for your order")
+        .html_body("<p>Thanks for your order</p>")
+        .build()
+        .expect("Building email")
+}
+
+fn mailer(server: &MockServer, keep_alive: bool) -> Mailer {
+    let builder = MailerBuilder::new("fakedomain", "tomatotoken").base_url(server.uri());
+    // Keeping no idle connections makes every request connect again.
+    let builder = if keep_alive {
+        builder
+    } else {
+        builder.pool_max_idle_per_host(0)
+    };
+    builder.build().expect("Creating Mailer")
+}
+
+fn send(c: &mut Criterion) {
+    let rt = Runtime::new().expect("Starting runtime");
+    let server = rt.block_on(mock_server());
+
+    let mut group = c.benchmark_group("send");
+    group.throughput(Throughput::Elements(1));
+    for keep_alive in [true, false] {
+        let mailer = mailer(&server, keep_alive);
+        let name = if keep_alive {
+            "keep_alive"
+        } else {
+            "reconnect"
+        };
+        group.bench_function(name, |b| {
+            b.to_async(&rt)
+                .iter(|| async { email().send(&mailer).await.expect("Sending email") })
+        });
+    }
+    group.finish();
+}
+
+fn send_concurrent(c: &mut Criterion) {
+    let rt = Runtime::new().expect("Starting runtime");
+    let server = rt.block_on(mock_server());
+    let mailer = mailer(&server, true);
+
+    let mut group = c.benchmark_group("send_concurrent");
+    for sends in [10, 100] {
+        group.throughput(Throughput::Elements(sends));
+        group.bench_with_input(BenchmarkId::from_parameter(sends), &sends, |b, &sends| {
+            b.to_async(&rt).iter(|| async {
+                let results = join_all((0..sends).map(|_| email().send(&mailer))).await;
+                assert!(results.iter().all(Result::is_ok));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, send, send_concurrent);
+criterion_main!(benches);
@@ -0,0 +1,175 @@
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+use crate::AddressError;
+
+/// An email address, optionally with a display name: `"Niclas" <niclas@x.se>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EmailAddress {
+    name: Option<String>,
+    email: String,
+}
+
+impl EmailAddress {
+    /// Creates an address without display name, validating it.
+    pub fn new(email: impl Into<String>) -> Result<Self, AddressError> {
+        let email = email.into();
+        validate_email(&email)?;
+        Ok(Self { name: None, email })
+    }
+
+    /// Creates an address with a display name, validating the address part.
+    pub fn with_name(
+        name: impl Into<String>,
+        email: impl Into<String>,
+    ) -> Result<Self, AddressError> {
+        let mut address = Self::new(email)?;
+        address.name = Some(name.into());
+        Ok(address)
+    }
+
+    /// The display name, if present.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The bare address without display name.
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// The domain part of the address.
+    pub fn domain(&self) -> &str {
+        self.email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or_default()
+    }
+}
+
+fn validate_email(email: &str) -> Result<(), AddressError> {
+    let (local, domain) = email
+        .rsplit_once('@')
+        .ok_or_else(|| AddressError::new(email, "missing `@`"))?;
+
+    if local.is_empty() {
+        return Err(AddressError::new(email, "empty local part"));
+    }
+    if domain.is_empty() || domain.starts_with('.') || domain.ends_with('.') {
+        return Err(AddressError::new(email, "invalid domain"));
+    }
+    if email
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | ',' | '"'))
+    {
+        return Err(AddressError::new(email, "invalid character"));
+    }
+    #[cfg(feature = "email-address")]
+    if !email_address::EmailAddress::is_valid(email) {
+        return Err(AddressError::new(email, "not an RFC 5322 address"));
+    }
+
+    Ok(())
+}
+
+impl FromStr for EmailAddress {
+    type Err = AddressError;
+
+    /// Parses `addr@domain`, `Name <addr@domain>` and `"Name" <addr@domain>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, email) = match s.strip_suffix('>').and_then(|s| s.rsplit_once('<')) {
+            Some((name, email)) => (name.trim(), email.trim()),
+            None => return Self::new(s),
+        };
+
+        let name = match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+            Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+            None => name.to_string(),
+        };
+
+        if name.is_empty() {
+            Self::new(email)
+        } else {
+            Self::with_name(name, email)
+        }
+    }
+}
+
+impl TryFrom<&str> for EmailAddress {
+    type Error = AddressError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for EmailAddress {
+    type Error = AddressError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(
+                f,
+                "\"{}\" <{}>",
+                name.replace('\\', "\\\\").replace('"', "\\\""),
+                self.email
+            ),
+            None => f.write_str(&self.email),
+        }
+    }
+}
+
+impl From<EmailAddress> for String {
+    fn from(address: EmailAddress) -> Self {
+        address.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let address: EmailAddress = "\"Niclas\" <niclas@x.se>".parse().unwrap();
+        assert_eq!(address.name(), Some("Niclas"));
+        assert_eq!(address.email(), "niclas@x.se");
+        assert_eq!(address.domain(), "x.se");
+        assert_eq!(address.to_string(), "\"Niclas\" <niclas@x.se>");
+
+        let address: EmailAddress = "Niclas R <niclas@x.se>".parse().unwrap();
+        assert_eq!(address.name(), Some("Niclas R"));
+
+        let address: EmailAddress = "niclas@x.se".parse().unwrap();
+        assert_eq!(address.name(), None);
+        assert_eq!(address.to_string(), "niclas@x.se");
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        for bad in [
+            "niclas",
+            "@x.se",
+            "niclas@",
+            "nic las@x.se",
+            "Niclas <niclas>",
+        ] {
+            assert!(bad.parse::<EmailAddress>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[cfg(feature = "email-address")]
+    #[test]
+    fn rejects_non_rfc_addresses() {
+        for bad in ["nic..las@x.se", ".niclas@x.se", "niclas.@x.se"] {
+            assert!(bad.parse::<EmailAddress>().is_err(), "{} parsed", bad);
+        }
+        assert!("niclas+tag@x.se".parse::<EmailAddress>().is_ok());
+    }
+}
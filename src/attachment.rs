@@ -0,0 +1,152 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use futures_util::stream;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{BuildError, SendError};
+
+/// Streamed attachments are read in chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A file attached to an email, sent as an `attachment` part.
+#[derive(Clone)]
+pub(crate) struct Attachment {
+    pub(crate) name: String,
+    pub(crate) content_type: String,
+    data: AttachmentData,
+}
+
+#[derive(Clone)]
+enum AttachmentData {
+    Bytes(Bytes),
+    /// Taken by the first send, clones of the email share it.
+    Stream(Arc<Mutex<Option<reqwest::Body>>>),
+}
+
+impl Attachment {
+    pub(crate) fn bytes(name: String, content_type: String, data: Bytes) -> Self {
+        Self {
+            name,
+            content_type,
+            data: AttachmentData::Bytes(data),
+        }
+    }
+
+    pub(crate) fn stream<R>(name: String, content_type: String, reader: R) -> Self
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        Self {
+            name,
+            content_type,
+            data: AttachmentData::Stream(Arc::new(Mutex::new(Some(reader_body(reader))))),
+        }
+    }
+
+    /// Size in bytes, unknown for streams and counted as 0.
+    pub(crate) fn size(&self) -> usize {
+        match &self.data {
+            AttachmentData::Bytes(data) => data.len(),
+            AttachmentData::Stream(_) => 0,
+        }
+    }
+
+    /// The contents of in memory attachments, streams can only be read once when sending.
+    pub(crate) fn data(&self) -> Option<&Bytes> {
+        match &self.data {
+            AttachmentData::Bytes(data) => Some(data),
+            AttachmentData::Stream(_) => None,
+        }
+    }
+
+    /// In memory attachments can be sent again, for retries and batches.
+    pub(crate) fn is_replayable(&self) -> bool {
+        matches!(self.data, AttachmentData::Bytes(_))
+    }
+
+    pub(crate) fn part(&self) -> Result<reqwest::multipart::Part, SendError> {
+        let part = match &self.data {
+            AttachmentData::Bytes(data) => reqwest::multipart::Part::stream_with_length(
+                reqwest::Body::from(data.clone()),
+                data.len() as u64,
+            ),
+            AttachmentData::Stream(body) => {
+                let body = body
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .take()
+                    .ok_or_else(|| {
+                        SendError::InvalidEmail(BuildError::InvalidField(
+                            "attachment",
+                            format!("stream for `{}` was already sent", self.name),
+                        ))
+                    })?;
+                reqwest::multipart::Part::stream(body)
+            }
+        };
+        part.file_name(self.name.clone())
+            .mime_str(&self.content_type)
+            .map_err(SendError::from)
+    }
+
+    #[cfg(feature = "blocking")]
+    pub(crate) fn blocking_part(&self) -> Result<reqwest::blocking::multipart::Part, SendError> {
+        match &self.data {
+            AttachmentData::Bytes(data) => reqwest::blocking::multipart::Part::bytes(data.to_vec())
+                .file_name(self.name.clone())
+                .mime_str(&self.content_type)
+                .map_err(SendError::from),
+            AttachmentData::Stream(_) => Err(SendError::InvalidEmail(BuildError::InvalidField(
+                "attachment",
+                "streamed attachments need the async Mailer".into(),
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for Attachment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Attachment")
+            .field("name", &self.name)
+            .field("content_type", &self.content_type)
+            .field("streamed", &!self.is_replayable())
+            .finish()
+    }
+}
+
+/// Content types are checked when adding the attachment, rather than on send.
+pub(crate) fn validate_content_type(content_type: &str) -> Result<(), BuildError> {
+    reqwest::multipart::Part::bytes(Vec::new())
+        .mime_str(content_type)
+        .map(drop)
+        .map_err(|_| {
+            BuildError::InvalidField(
+                "attachment",
+                format!("invalid content type `{}`", content_type),
+            )
+        })
+}
+
+/// Reads the reader chunk by chunk while the request is sent, ending at the first error.
+fn reader_body<R>(reader: R) -> reqwest::Body
+where
+    R: AsyncRead + Send + Sync + Unpin + 'static,
+{
+    let chunks = stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        match reader.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(buf), Some(reader)))
+            }
+            Err(err) => Some((Err(err), None)),
+        }
+    });
+    reqwest::Body::wrap_stream(chunks)
+}
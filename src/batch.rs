@@ -0,0 +1,607 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures_util::{stream, Stream, StreamExt};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::{
+    email::{bare_address, split_recipients},
+    queue::ShutdownReport,
+    Email, Mailer, MessageId, SendError,
+};
+
+/// Mailgun accepts at most this many recipients per message.
+pub const MAX_BATCH_RECIPIENTS: usize = 1000;
+
+/// Outcome of sending one chunk of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchResult {
+    pub recipients: Vec<String>,
+    pub result: Result<MessageId, SendError>,
+}
+
+/// A batch sending in the background, started by `Mailer::spawn_batch` or
+/// `Mailer::spawn_all`, for reporting the progress of long running campaigns.
+///
+/// ```
+/// use std::{sync::Arc, time::Duration};
+/// use mailgun46::{EmailBuilder, Mailer};
+/// # async fn example(mailer: Arc<Mailer>) -> Result<(), Box<dyn std::error::Error + 'static>> {
+/// let email = EmailBuilder::default().to("placeholder").subject("Spring sale").build()?;
+/// let recipients = (0..50_000).map(|i| format!("customer{}@example.com", i));
+/// let batch = mailer.spawn_batch(email, recipients);
+/// while !batch.is_finished() {
+///     let progress = batch.progress();
+///     println!("{} sent, {} failed of {}", progress.succeeded, progress.failed, progress.total());
+///     tokio::time::sleep(Duration::from_secs(10)).await;
+/// }
+/// for (recipient, err) in batch.failures() {
+///     println!("{}: {}", recipient, err);
+/// }
+/// let results = batch.await_completion().await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct BatchHandle {
+    progress: Arc<Progress>,
+    task: JoinHandle<()>,
+    /// Notified by `shutdown` to stop taking chunks into the batch.
+    stop: Arc<Notify>,
+}
+
+/// Counts of recipients in a batch, see `BatchHandle::progress`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchProgress {
+    /// Waiting to be sent or in flight.
+    pub queued: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl BatchProgress {
+    /// Recipients taken into the batch so far. Known up front for `spawn_batch`,
+    /// growing as the stream is read for `spawn_all`.
+    pub fn total(&self) -> usize {
+        self.queued + self.succeeded + self.failed
+    }
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    queued: AtomicUsize,
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    failures: Mutex<Vec<(String, SendError)>>,
+    /// Results of the finished messages, in batch order.
+    results: Mutex<Vec<BatchResult>>,
+}
+
+impl Progress {
+    fn record(&self, result: BatchResult) {
+        let recipients = result.recipients.len();
+        match &result.result {
+            Ok(_) => self.succeeded.fetch_add(recipients, Ordering::SeqCst),
+            Err(err) => {
+                self.lock_failures().extend(
+                    result
+                        .recipients
+                        .iter()
+                        .map(|recipient| (recipient.clone(), err.clone())),
+                );
+                self.failed.fetch_add(recipients, Ordering::SeqCst)
+            }
+        };
+        self.queued.fetch_sub(recipients, Ordering::SeqCst);
+        self.lock_results().push(result);
+    }
+
+    fn lock_failures(&self) -> std::sync::MutexGuard<'_, Vec<(String, SendError)>> {
+        self.failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_results(&self) -> std::sync::MutexGuard<'_, Vec<BatchResult>> {
+        self.results
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl BatchHandle {
+    pub fn progress(&self) -> BatchProgress {
+        BatchProgress {
+            queued: self.progress.queued.load(Ordering::SeqCst),
+            succeeded: self.progress.succeeded.load(Ordering::SeqCst),
+            failed: self.progress.failed.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Each recipient whose message failed so far, with the error of the message.
+    pub fn failures(&self) -> Vec<(String, SendError)> {
+        self.progress.lock_failures().clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits until every message is sent or has failed, returning the results in the
+    /// order the messages were taken into the batch.
+    pub async fn await_completion(self) -> Vec<BatchResult> {
+        if let Err(err) = self.task.await {
+            std::panic::resume_unwind(err.into_panic());
+        }
+        std::mem::take(&mut *self.progress.lock_results())
+    }
+
+    /// Stops taking messages into the batch and lets those in flight finish until
+    /// `deadline` has passed, for shutting down cleanly. Sends still in flight then are
+    /// cancelled. The report counts recipients: those finished after the call are
+    /// flushed, those still queued at the deadline are abandoned. For `spawn_all`,
+    /// emails never read from the stream are not counted.
+    pub async fn shutdown(self, deadline: Duration) -> ShutdownReport {
+        let Self {
+            progress,
+            mut task,
+            stop,
+        } = self;
+        let pending = progress.queued.load(Ordering::SeqCst);
+        stop.notify_one();
+
+        match tokio::time::timeout(deadline, &mut task).await {
+            Ok(Err(err)) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Ok(_) => {}
+            Err(_) => {
+                task.abort();
+                let _ = task.await;
+            }
+        }
+        let abandoned = progress.queued.load(Ordering::SeqCst);
+        ShutdownReport {
+            flushed: pending.saturating_sub(abandoned),
+            abandoned,
+        }
+    }
+
+    /// Stops the batch at once, returning the results of the messages finished so far.
+    /// Messages in flight are abandoned and may or may not have been sent,
+    /// later ones are never sent. Dropping the handle instead lets the batch finish,
+    /// see `shutdown` for letting sends in flight finish.
+    pub async fn cancel(self) -> Vec<BatchResult> {
+        self.task.abort();
+        if let Err(err) = self.task.await {
+            if err.is_panic() {
+                std::panic::resume_unwind(err.into_panic());
+            }
+        }
+        std::mem::take(&mut *self.progress.lock_results())
+    }
+}
+
+impl Mailer {
+    /// Sends `email` to all `recipients`, split into chunks of at most 1000 recipients.
+    /// The recipients of `email` itself are replaced, recipient variables are kept for
+    /// the recipients of each chunk. Chunks are sent concurrently, limited by
+    /// `MailerBuilder::batch_concurrency`, and results are returned in chunk order.
+    ///
+    /// Recipient variables are always sent, empty for recipients without any, so each
+    /// recipient gets a message of their own and never sees the others of the chunk.
+    pub async fn send_batch<I, R>(&self, email: Email, recipients: I) -> Vec<BatchResult>
+    where
+        I: IntoIterator<Item = R>,
+        R: Into<String>,
+    {
+        let recipients: Vec<String> = recipients.into_iter().map(Into::into).collect();
+        let chunks = chunk_email(&email, &recipients);
+
+        stream::iter(chunks)
+            .map(|(recipients, email)| async move {
+                BatchResult {
+                    recipients,
+                    result: self.send(email).await,
+                }
+            })
+            .buffered(self.batch_concurrency)
+            .collect()
+            .await
+    }
+
+    /// Sends every email of the stream, yielding the results in the order of the emails.
+    /// Emails are taken from the stream as sends complete, at most
+    /// `MailerBuilder::batch_concurrency` at the same time, so the stream is never
+    /// collected up front. Each send is retried according to `MailerBuilder::retry`,
+    /// a failed email does not stop the others.
+    ///
+    /// ```
+    /// use futures_util::{stream, StreamExt};
+    /// use mailgun46::{EmailBuilder, Mailer};
+    /// # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+    /// let emails = stream::iter(["a@example.com", "b@example.com"])
+    ///     .map(|to| EmailBuilder::default().to(to).build().unwrap());
+    /// let mut results = Box::pin(mailer.send_all(emails));
+    /// while let Some(result) = results.next().await {
+    ///     println!("{:?}", result);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn send_all<'a, S>(
+        &'a self,
+        emails: S,
+    ) -> impl Stream<Item = Result<MessageId, SendError>> + 'a
+    where
+        S: Stream<Item = Email> + 'a,
+    {
+        emails
+            .map(move |email| self.send(email))
+            .buffered(self.batch_concurrency)
+    }
+
+    /// Same as `send_batch`, sending in a spawned task and returning a handle reporting
+    /// its progress. Must be called within a tokio runtime.
+    pub fn spawn_batch<I, R>(self: &Arc<Self>, email: Email, recipients: I) -> BatchHandle
+    where
+        I: IntoIterator<Item = R>,
+        R: Into<String>,
+    {
+        let recipients: Vec<String> = recipients.into_iter().map(Into::into).collect();
+        let progress = Arc::new(Progress::default());
+        progress.queued.store(recipients.len(), Ordering::SeqCst);
+        let chunks = chunk_email(&email, &recipients);
+        self.spawn_chunks(stream::iter(chunks), progress)
+    }
+
+    /// Same as `send_all`, sending in a spawned task and returning a handle reporting
+    /// its progress. Must be called within a tokio runtime.
+    pub fn spawn_all<S>(self: &Arc<Self>, emails: S) -> BatchHandle
+    where
+        S: Stream<Item = Email> + Send + 'static,
+    {
+        let progress = Arc::new(Progress::default());
+        let queued = progress.clone();
+        let chunks = emails.map(move |email| {
+            let recipients = split_recipients(&email.to);
+            queued.queued.fetch_add(recipients.len(), Ordering::SeqCst);
+            (recipients, email)
+        });
+        self.spawn_chunks(chunks, progress)
+    }
+
+    fn spawn_chunks<S>(self: &Arc<Self>, chunks: S, progress: Arc<Progress>) -> BatchHandle
+    where
+        S: Stream<Item = (Vec<String>, Email)> + Send + 'static,
+    {
+        let mailer = self.clone();
+        let recorded = progress.clone();
+        let stop = Arc::new(Notify::new());
+        let stopped = stop.clone();
+        let task = tokio::spawn(async move {
+            let mailer = &mailer;
+            chunks
+                .take_until(async move { stopped.notified().await })
+                .map(|(recipients, email)| async move {
+                    BatchResult {
+                        recipients,
+                        result: mailer.send(email).await,
+                    }
+                })
+                .buffered(mailer.batch_concurrency)
+                .for_each(|result| {
+                    recorded.record(result);
+                    async {}
+                })
+                .await
+        });
+        BatchHandle {
+            progress,
+            task,
+            stop,
+        }
+    }
+}
+
+/// Splits the recipients into Mailgun sized chunks, each with its own copy of the email.
+/// An idempotency key gets the chunk index appended, so chunks are not taken as duplicates.
+/// Every recipient of a chunk has recipient variables, as Mailgun would otherwise send
+/// one message listing all of them.
+pub(crate) fn chunk_email(email: &Email, recipients: &[String]) -> Vec<(Vec<String>, Email)> {
+    recipients
+        .chunks(MAX_BATCH_RECIPIENTS)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut email = email.clone();
+            email.to = chunk.join(",");
+            if let Some(key) = &email.idempotency_key {
+                email.set_idempotency_key(format!("{}-{}", key, i));
+            }
+            email.recipient_variables.retain(|address, _| {
+                chunk
+                    .iter()
+                    .any(|recipient| bare_address(recipient) == address)
+            });
+            for recipient in chunk {
+                email
+                    .recipient_variables
+                    .entry(bare_address(recipient).to_string())
+                    .or_default();
+            }
+            (chunk.to_vec(), email)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn sends_in_chunks() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .batch_concurrency(2)
+            .build()
+            .expect("Creating Mailer");
+
+        let recipients: Vec<String> = (0..2500).map(|i| format!("r{}@example.com", i)).collect();
+        let email = recipients
+            .iter()
+            .fold(EmailBuilder::default().to("placeholder"), |b, r| {
+                b.recipient_variable(r.as_str(), "id", r.as_str())
+            })
+            .recipient_variable("placeholder", "id", "none")
+            .build()
+            .unwrap();
+
+        let chunks = chunk_email(&email, &recipients);
+        assert_eq!(chunks[2].0.len(), 500);
+        assert_eq!(chunks[2].1.recipient_variables.len(), 500);
+        assert!(chunks[0].1.to.starts_with("r0@example.com,r1@example.com"));
+
+        let without_variables = EmailBuilder::default().to("placeholder").build().unwrap();
+        let chunks = chunk_email(
+            &without_variables,
+            &["a@example.com".into(), "B <b@example.com>".into()],
+        );
+        let json = serde_json::to_value(&chunks[0].1).unwrap();
+        assert_eq!(
+            json["recipient-variables"],
+            r#"{"a@example.com":{},"b@example.com":{}}"#
+        );
+
+        let results = mailer.send_batch(email, recipients).await;
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].recipients[0], "r0@example.com");
+        assert!(results.iter().all(|r| r.result.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_all_in_order() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("to=bad%40example.com"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("Invalid recipient"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(9)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .batch_concurrency(3)
+            .build()
+            .expect("Creating Mailer");
+
+        let emails = stream::iter(0..10).map(|i| {
+            let to = if i == 4 {
+                "bad@example.com".to_string()
+            } else {
+                format!("r{}@example.com", i)
+            };
+            EmailBuilder::default().to(to).build().unwrap()
+        });
+        let results: Vec<_> = mailer.send_all(emails).collect().await;
+
+        assert_eq!(results.len(), 10);
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result.is_err(), i == 4, "{}: {:?}", i, result);
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_progress() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("bad%40example.com"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("Invalid recipient"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(4)
+            .mount(&server)
+            .await;
+
+        let mailer = Arc::new(
+            Mailer::builder("fakedomain", "tomatotoken")
+                .base_url(server.uri())
+                .build()
+                .expect("Creating Mailer"),
+        );
+
+        let mut recipients: Vec<String> =
+            (0..2000).map(|i| format!("r{}@example.com", i)).collect();
+        recipients.push("bad@example.com".into());
+        let email = EmailBuilder::default().to("placeholder").build().unwrap();
+        let batch = mailer.spawn_batch(email, recipients);
+        assert_eq!(batch.progress().total(), 2001);
+        while !batch.is_finished() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            batch.progress(),
+            BatchProgress {
+                queued: 0,
+                succeeded: 2000,
+                failed: 1
+            }
+        );
+        let failures = batch.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "bad@example.com");
+        let results = batch.await_completion().await;
+        assert_eq!(results.len(), 3);
+        assert!(results[2].result.is_err());
+
+        let emails = stream::iter(["a@example.com", "b@example.com, c@example.com"])
+            .map(|to| EmailBuilder::default().to(to).build().unwrap());
+        let results = mailer.spawn_all(emails).await_completion().await;
+        assert_eq!(results[1].recipients.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn shuts_down_batch() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"}))
+                    .set_delay(std::time::Duration::from_millis(300)),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+        let mailer = Arc::new(
+            Mailer::builder("fakedomain", "tomatotoken")
+                .base_url(server.uri())
+                .batch_concurrency(2)
+                .build()
+                .expect("Creating Mailer"),
+        );
+
+        let emails = stream::iter(0..10).map(|i| {
+            EmailBuilder::default()
+                .to(format!("r{}@example.com", i))
+                .build()
+                .unwrap()
+        });
+        let batch = mailer.spawn_all(emails);
+        while batch.progress().queued < 2 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            batch.shutdown(Duration::from_secs(5)).await,
+            ShutdownReport {
+                flushed: 2,
+                abandoned: 0
+            }
+        );
+
+        let slow = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+            .mount(&slow)
+            .await;
+        let mailer = Arc::new(
+            Mailer::builder("fakedomain", "tomatotoken")
+                .base_url(slow.uri())
+                .batch_concurrency(1)
+                .build()
+                .expect("Creating Mailer"),
+        );
+        let email = EmailBuilder::default().to("placeholder").build().unwrap();
+        let recipients = (0..2500).map(|i| format!("r{}@example.com", i));
+        let batch = mailer.spawn_batch(email, recipients);
+        assert_eq!(
+            batch.shutdown(Duration::from_millis(200)).await,
+            ShutdownReport {
+                flushed: 0,
+                abandoned: 2500
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn cancels_batch() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("r0%40example.com"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"}))
+                    .set_delay(std::time::Duration::from_secs(30)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Arc::new(
+            Mailer::builder("fakedomain", "tomatotoken")
+                .base_url(server.uri())
+                .batch_concurrency(1)
+                .build()
+                .expect("Creating Mailer"),
+        );
+
+        let emails = stream::iter(0..10).map(|i| {
+            EmailBuilder::default()
+                .to(format!("r{}@example.com", i))
+                .build()
+                .unwrap()
+        });
+        let batch = mailer.spawn_all(emails);
+        while batch.progress().succeeded == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let results = tokio::time::timeout(std::time::Duration::from_secs(5), batch.cancel())
+            .await
+            .expect("Cancelling batch");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].recipients, ["r0@example.com"]);
+    }
+}
@@ -0,0 +1,288 @@
+//! A Mailer for programs without an async runtime, built on `reqwest::blocking`.
+//!
+//! ```no_run
+//! use mailgun46::{blocking::Mailer, EmailBuilder};
+//! # fn example() -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let mailer = Mailer::from_env()?;
+//! let email = EmailBuilder::default()
+//!     .to("someone@example.com")
+//!     .subject("Hello")
+//!     .text_body("Sent without async")
+//!     .build()?;
+//! mailer.send(email)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::thread;
+
+use crate::{
+    batch::chunk_email, email::Source, rate_limit::RateLimiter, retry, transport::FileTransport,
+    BatchResult, BuildError, Email, MailReply, MailerBuilder, MessageId, RecipientPolicy, Region,
+    RetryPolicy, SendError, SetupError, SubjectPolicy, TlsPolicy,
+};
+
+/// Blocking counterpart of `mailgun46::Mailer`, sending emails through the messages API.
+/// Must not be used from within an async runtime.
+#[derive(Debug)]
+pub struct Mailer {
+    pub(crate) from: String,
+    pub(crate) messages_url: reqwest::Url,
+    pub(crate) auth: reqwest::header::HeaderValue,
+    pub(crate) on_behalf_of: Option<reqwest::header::HeaderValue>,
+    pub(crate) client: reqwest::blocking::Client,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) batch_concurrency: usize,
+    pub(crate) sandbox: bool,
+    pub(crate) tls: TlsPolicy,
+    pub(crate) subject_policy: SubjectPolicy,
+    pub(crate) recipient_policy: RecipientPolicy,
+    pub(crate) strict_status: bool,
+    pub(crate) max_message_size: usize,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+}
+
+impl Mailer {
+    /// Creates a new Mailer from the same environment variables as `mailgun46::Mailer::from_env`.
+    pub fn from_env() -> Result<Self, SetupError> {
+        Self::from_env_with_prefix("MAILER46")
+    }
+
+    /// Same as `from_env`, reading `<prefix>_DOMAIN`, `<prefix>_TOKEN` and so on.
+    /// Fails when `<prefix>_TRANSPORT` selects a file, rather than sending real emails.
+    pub fn from_env_with_prefix(prefix: &str) -> Result<Self, SetupError> {
+        let name = format!("{}_TRANSPORT", prefix.trim_end_matches('_'));
+        if let Ok(transport) = std::env::var(&name) {
+            match FileTransport::from_var(&transport) {
+                Ok(None) => {}
+                Ok(Some(_)) => {
+                    let msg = "the file transport needs the async Mailer".into();
+                    return Err(SetupError::InvalidEnvVar(name, msg));
+                }
+                Err(msg) => return Err(SetupError::InvalidEnvVar(name, msg)),
+            }
+        }
+        MailerBuilder::from_env_with_prefix(prefix)?.build_blocking()
+    }
+
+    /// Creates a new client operating against the given domain.
+    pub fn new(domain: impl AsRef<str>, token: impl AsRef<str>) -> Result<Self, SetupError> {
+        MailerBuilder::new(domain.as_ref(), token.as_ref()).build_blocking()
+    }
+
+    /// Creates a new client operating against the given domain in the given region.
+    pub fn new_with_region(
+        region: Region,
+        domain: impl AsRef<str>,
+        token: impl AsRef<str>,
+    ) -> Result<Self, SetupError> {
+        MailerBuilder::new(domain.as_ref(), token.as_ref())
+            .region(region)
+            .build_blocking()
+    }
+
+    pub fn new_with_mg_url(
+        mg_url: impl AsRef<str>,
+        domain: impl AsRef<str>,
+        token: impl AsRef<str>,
+    ) -> Result<Self, SetupError> {
+        MailerBuilder::new(domain.as_ref(), token.as_ref())
+            .base_url(mg_url.as_ref())
+            .build_blocking()
+    }
+
+    /// Creates a builder for configuring the Mailer further, finish with `build_blocking`.
+    pub fn builder(domain: impl Into<String>, token: impl Into<String>) -> MailerBuilder {
+        MailerBuilder::new(domain, token)
+    }
+
+    /// Sends the email, blocking until Mailgun has replied.
+    pub fn send(&self, mut email: Email) -> Result<MessageId, SendError> {
+        email
+            .apply_defaults(&self.from, self.sandbox, self.tls, &self.subject_policy)
+            .map_err(SendError::InvalidEmail)?;
+        self.recipient_policy.apply(&mut email)?;
+        email.encode()?;
+        email.check_size(self.max_message_size)?;
+        if let Some(limiter) = &self.rate_limiter {
+            thread::sleep(limiter.reserve());
+        }
+
+        let mut attempt = 1;
+        loop {
+            let url = match &email.source {
+                Source::Fields => self.messages_url.clone(),
+                Source::Mime(_) => self
+                    .messages_url
+                    .join("messages.mime")
+                    .expect("messages url validated on construction"),
+                Source::Stored(_) => {
+                    return Err(SendError::InvalidEmail(BuildError::InvalidField(
+                        "message",
+                        "resending a stored message needs the async Mailer".into(),
+                    )))
+                }
+            };
+            let mut request = self
+                .client
+                .post(url)
+                .header(reqwest::header::AUTHORIZATION, self.auth.clone());
+            if let Some(subaccount) = &self.on_behalf_of {
+                request = request.header(crate::ON_BEHALF_OF, subaccount.clone());
+            }
+            let result = if email.is_form() {
+                request.form(&email.form()?.fields).send()
+            } else {
+                request.multipart(email.blocking_multipart()?).send()
+            };
+
+            let policy = self.retry.as_ref().filter(|p| p.should_retry(attempt));
+            let delay = match (&result, policy) {
+                (Ok(res), Some(policy)) if retry::is_retryable_status(res.status()) => {
+                    policy.delay(attempt, retry::retry_after(res.headers()))
+                }
+                (Err(err), Some(policy)) if retry::is_retryable_error(err) => {
+                    policy.delay(attempt, None)
+                }
+                _ => {
+                    let reply: MailReply = read_reply(result?, self.strict_status)?;
+                    return Ok(MessageId(reply.id));
+                }
+            };
+
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// Same as the async `send_batch`, sending up to `batch_concurrency` chunks at a
+    /// time on scoped threads.
+    pub fn send_batch<I, R>(&self, email: Email, recipients: I) -> Vec<BatchResult>
+    where
+        I: IntoIterator<Item = R>,
+        R: Into<String>,
+    {
+        let recipients: Vec<String> = recipients.into_iter().map(Into::into).collect();
+        let chunks = chunk_email(&email, &recipients);
+
+        let mut results = Vec::with_capacity(chunks.len());
+        let mut chunks = chunks.into_iter().peekable();
+        while chunks.peek().is_some() {
+            let group: Vec<_> = chunks.by_ref().take(self.batch_concurrency).collect();
+            thread::scope(|scope| {
+                let handles: Vec<_> = group
+                    .into_iter()
+                    .map(|(recipients, email)| {
+                        scope.spawn(move || BatchResult {
+                            recipients,
+                            result: self.send(email),
+                        })
+                    })
+                    .collect();
+                results.extend(
+                    handles
+                        .into_iter()
+                        .map(|handle| handle.join().expect("Batch thread panicked")),
+                );
+            });
+        }
+        results
+    }
+}
+
+/// Deserializes the JSON reply, non 2xx replies are errors.
+fn read_reply<T>(res: reqwest::blocking::Response, strict: bool) -> Result<T, SendError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    if !crate::error::is_success(res.status(), strict) {
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body_bs = res.bytes()?;
+        let body = String::from_utf8_lossy(&body_bs);
+        return Err(SendError::from_reply(status, &headers, body.into()));
+    }
+
+    Ok(res.json::<T>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sends_blocking() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::header(
+                "Authorization",
+                "Basic YXBpOnRvbWF0b3Rva2Vu",
+            ))
+            .and(matchers::body_string_contains("o%3Atestmode=yes"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        let (sent, batch) = tokio::task::spawn_blocking(move || {
+            let mailer = Mailer::builder("fakedomain", "tomatotoken")
+                .base_url(uri)
+                .sandbox(true)
+                .batch_concurrency(1)
+                .build_blocking()
+                .expect("Creating Mailer");
+            let email = EmailBuilder::default().to("someone").build().unwrap();
+            let recipients = (0..1500).map(|i| format!("r{}@example.com", i));
+            (
+                mailer.send(email.clone()),
+                mailer.send_batch(email, recipients),
+            )
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(sent, Ok(MessageId("<id@fakedomain>".into())));
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[1].recipients[0], "r1000@example.com");
+        assert!(batch.iter().all(|r| r.result.is_ok()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blocking_errors() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("Forbidden"))
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        let result = tokio::task::spawn_blocking(move || {
+            let mailer =
+                Mailer::new_with_mg_url(uri, "fakedomain", "tomatotoken").expect("Creating Mailer");
+            mailer.send(EmailBuilder::default().to("someone").build().unwrap())
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(result, Err(SendError::Unauthorized { .. })));
+    }
+
+    #[test]
+    fn refuses_suppression_guard() {
+        let err = Mailer::builder("fakedomain", "tomatotoken")
+            .with_suppression_guard(std::time::Duration::from_secs(60))
+            .build_blocking()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SetupError::InvalidVar("suppression_guard", _)
+        ));
+    }
+}
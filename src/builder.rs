@@ -0,0 +1,657 @@
+use std::{collections::HashMap, env, fmt, sync::Arc, time::Duration};
+
+use crate::{
+    idempotency::IdempotencyCache,
+    rate_limit::RateLimiter,
+    suppression_guard::SuppressionGuard,
+    transport::{FileTransport, HttpTransport, Transport},
+    EmailAddress, Mailer, MailerPool, Observer, RecipientPolicy, Region, RetryPolicy,
+    SendMiddleware, SetupError, SubjectPolicy, TlsPolicy, MAX_MESSAGE_SIZE, USER_AGENT,
+};
+
+/// Builds a client with the timeouts, proxy and pool settings of a `MailerBuilder`.
+/// A macro, as the async and blocking client builders share these methods but no trait.
+macro_rules! build_client {
+    ($settings:expr, $builder:expr) => {{
+        let settings: &MailerBuilder = &$settings;
+        // Following a redirect would turn a POST into a GET, so it becomes an error.
+        let mut builder = $builder
+            .user_agent(USER_AGENT)
+            .redirect(reqwest::redirect::Policy::none());
+        if let Some(timeout) = settings.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = settings.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(proxy) = settings.proxy.clone() {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(timeout) = settings.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(max) = settings.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if settings.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        builder
+            .build()
+            .map_err(|err| SetupError::Build(err.to_string()))
+    }};
+}
+
+/// Required variables with the default prefix, reported as `SetupError::EnvVarMissing`.
+const DEFAULT_REQUIRED: [&str; 2] = ["MAILER46_DOMAIN", "MAILER46_TOKEN"];
+
+/// Configures a Mailer beyond what `Mailer::new` offers.
+///
+/// ```
+/// use mailgun46::{MailerBuilder, RetryPolicy};
+/// # fn example() -> Result<(), mailgun46::SetupError> {
+/// let mailer = MailerBuilder::new("example.com", "token")
+///     .from("support@example.com")
+///     .timeout(std::time::Duration::from_secs(10))
+///     .retry(RetryPolicy::default().max_attempts(5))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct MailerBuilder {
+    domain: String,
+    token: String,
+    region: Region,
+    base_url: Option<String>,
+    from: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<reqwest::Proxy>,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    http2_prior_knowledge: bool,
+    client: Option<reqwest::Client>,
+    retry: Option<RetryPolicy>,
+    batch_concurrency: usize,
+    sandbox: bool,
+    tls: TlsPolicy,
+    subject_policy: SubjectPolicy,
+    recipient_policy: RecipientPolicy,
+    strict_status: bool,
+    on_behalf_of: Option<String>,
+    max_message_size: usize,
+    rate_limit: Option<u32>,
+    send_timeout: Option<Duration>,
+    transport: Arc<dyn Transport>,
+    idempotency_ttl: Duration,
+    observers: Vec<Arc<dyn Observer>>,
+    middleware: Vec<Arc<dyn SendMiddleware>>,
+    suppression_ttl: Option<Duration>,
+    identities: Vec<(String, String)>,
+}
+
+/// Leaves out the token, which also keeps it out of the Debug output of a `MailerPool`.
+impl fmt::Debug for MailerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MailerBuilder")
+            .field("domain", &self.domain)
+            .field("token", &"***")
+            .field("region", &self.region)
+            .field("base_url", &self.base_url)
+            .field("from", &self.from)
+            .field("timeout", &self.timeout)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("proxy", &self.proxy)
+            .field("pool_idle_timeout", &self.pool_idle_timeout)
+            .field("pool_max_idle_per_host", &self.pool_max_idle_per_host)
+            .field("http2_prior_knowledge", &self.http2_prior_knowledge)
+            .field("client", &self.client)
+            .field("retry", &self.retry)
+            .field("batch_concurrency", &self.batch_concurrency)
+            .field("sandbox", &self.sandbox)
+            .field("tls", &self.tls)
+            .field("subject_policy", &self.subject_policy)
+            .field("recipient_policy", &self.recipient_policy)
+            .field("strict_status", &self.strict_status)
+            .field("on_behalf_of", &self.on_behalf_of)
+            .field("max_message_size", &self.max_message_size)
+            .field("rate_limit", &self.rate_limit)
+            .field("send_timeout", &self.send_timeout)
+            .field("transport", &self.transport)
+            .field("idempotency_ttl", &self.idempotency_ttl)
+            .field("observers", &self.observers)
+            .field("middleware", &self.middleware)
+            .field("suppression_ttl", &self.suppression_ttl)
+            .field("identities", &self.identities)
+            .finish()
+    }
+}
+
+impl MailerBuilder {
+    /// Notice that the token must be the one provided by Mailgun, Mailer46 turns it into base64.
+    pub fn new(domain: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            domain: domain.into(),
+            token: token.into(),
+            region: Region::default(),
+            base_url: None,
+            from: None,
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            pool_idle_timeout: None,
+            pool_max_idle_per_host: None,
+            http2_prior_knowledge: false,
+            client: None,
+            retry: None,
+            batch_concurrency: 4,
+            sandbox: false,
+            tls: TlsPolicy::default(),
+            subject_policy: SubjectPolicy::default(),
+            recipient_policy: RecipientPolicy::default(),
+            strict_status: false,
+            on_behalf_of: None,
+            max_message_size: MAX_MESSAGE_SIZE,
+            rate_limit: None,
+            send_timeout: None,
+            transport: Arc::new(HttpTransport),
+            idempotency_ttl: Duration::from_secs(24 * 60 * 60),
+            observers: Vec::new(),
+            middleware: Vec::new(),
+            suppression_ttl: None,
+            identities: Vec::new(),
+        }
+    }
+
+    /// The region of the domain, defaults to `Region::Eu`.
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self.base_url = None;
+        self
+    }
+
+    /// Reads the settings from the environment, see `Mailer::from_env`.
+    pub fn from_env() -> Result<Self, SetupError> {
+        Self::from_env_with_prefix("MAILER46")
+    }
+
+    /// Same as `from_env`, with the variables named `<prefix>_DOMAIN` and so on.
+    /// A missing variable is reported as `SetupError::InvalidEnvVar` with its name,
+    /// unless the prefix is the default `MAILER46`.
+    pub fn from_env_with_prefix(prefix: &str) -> Result<Self, SetupError> {
+        Self::from_vars(prefix, |name| env::var(name).ok())
+    }
+
+    /// Same as `from_env_with_prefix`, also reading variables from a `.env` file in the
+    /// current directory or its parents. Variables set in the environment take precedence,
+    /// the environment itself is left unchanged.
+    #[cfg(feature = "dotenv")]
+    pub fn from_dotenv(prefix: &str) -> Result<Self, SetupError> {
+        let file: HashMap<String, String> = match dotenvy::dotenv_iter() {
+            Ok(vars) => vars
+                .collect::<Result<_, _>>()
+                .map_err(|err| SetupError::InvalidEnvVar(".env".into(), err.to_string()))?,
+            Err(err) if err.not_found() => HashMap::new(),
+            Err(err) => return Err(SetupError::InvalidEnvVar(".env".into(), err.to_string())),
+        };
+        Self::from_vars(prefix, |name| {
+            env::var(name).ok().or_else(|| file.get(name).cloned())
+        })
+    }
+
+    fn from_vars(prefix: &str, var: impl Fn(&str) -> Option<String>) -> Result<Self, SetupError> {
+        let prefix = prefix.trim_end_matches('_');
+        let name = |suffix: &str| format!("{}_{}", prefix, suffix);
+        let required = |suffix: &str| {
+            let name = name(suffix);
+            var(&name).ok_or_else(|| match DEFAULT_REQUIRED.iter().find(|var| **var == name) {
+                Some(var) => SetupError::EnvVarMissing(var),
+                None => SetupError::InvalidEnvVar(name, "not set".into()),
+            })
+        };
+
+        let mut builder = Self::new(required("DOMAIN")?, required("TOKEN")?);
+        if let Some(region) = var(&name("REGION")) {
+            let region = region
+                .parse()
+                .map_err(|err| SetupError::InvalidEnvVar(name("REGION"), err))?;
+            builder = builder.region(region);
+        }
+        if let Some(url) = var(&name("BASE_URL")) {
+            builder = builder.base_url(url);
+        }
+        if let Some(from) = var(&name("FROM")) {
+            builder = builder.from(from);
+        }
+        if let Some(transport) = var(&name("TRANSPORT")) {
+            if let Some(file) = FileTransport::from_var(&transport)
+                .map_err(|err| SetupError::InvalidEnvVar(name("TRANSPORT"), err))?
+            {
+                builder = builder.transport(file);
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Base url to Mailgun, validated on build. Same as `Region::Custom`.
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    /// Default from address for emails without one, defaults to `noreply@<domain>`.
+    pub fn from(mut self, from: impl Into<String>) -> Self {
+        self.from = Some(from.into());
+        self
+    }
+
+    /// Timeout for each request, from connecting until the reply body is read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for a whole `send`, including retries and waiting for the rate limit.
+    /// Sends taking longer fail with `SendError::Timeout`. Only applies to the async Mailer.
+    pub fn send_timeout(mut self, timeout: Duration) -> Self {
+        self.send_timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// How long idle connections are kept open for reuse, saving a new TLS handshake
+    /// for the next request. reqwest defaults to 90 seconds.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// How many idle connections to Mailgun are kept open, unlimited by default.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Speaks HTTP/2 from the start instead of negotiating it, multiplexing concurrent
+    /// requests over one connection. Only for endpoints known to support it.
+    pub fn http2_prior_knowledge(mut self, enabled: bool) -> Self {
+        self.http2_prior_knowledge = enabled;
+        self
+    }
+
+    /// Uses the given client instead of creating one. Timeouts, proxy and pool
+    /// settings on this builder are then ignored in favor of the client's own.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Shares an existing client and its connection pool, same as `client`.
+    /// The token is sent with each request rather than set as a default header, so
+    /// other users of the client never see it and several Mailers may share one client.
+    pub fn with_client(self, client: reqwest::Client) -> Self {
+        self.client(client)
+    }
+
+    /// Retries transient failures when sending, off by default.
+    /// A `QueueConfig::retry` of a mail queue repeats these retries in each of its attempts.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Number of chunks `send_batch`, or emails `send_all`, sends at the same time,
+    /// defaults to 4.
+    pub fn batch_concurrency(mut self, concurrency: usize) -> Self {
+        self.batch_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sends every message in test mode, for staging environments that should
+    /// exercise the API without delivering mail.
+    pub fn sandbox(mut self, enabled: bool) -> Self {
+        self.sandbox = enabled;
+        self
+    }
+
+    /// What to do with emails without a subject, they are sent without one by default.
+    pub fn subject_policy(mut self, policy: SubjectPolicy) -> Self {
+        self.subject_policy = policy;
+        self
+    }
+
+    /// Restricts who emails are sent to, or sends them all to one address, for staging
+    /// environments. Also applies to the blocking Mailer.
+    pub fn recipient_policy(mut self, policy: RecipientPolicy) -> Self {
+        self.recipient_policy = policy;
+        self
+    }
+
+    /// Sends and queries as the subaccount with this id, using a primary account's
+    /// token. The id is validated on build.
+    pub fn on_behalf_of(mut self, subaccount_id: impl Into<String>) -> Self {
+        self.on_behalf_of = Some(subaccount_id.into());
+        self
+    }
+
+    /// Only counts 200 replies as success, as Mailgun itself replies, instead of any 2xx.
+    /// Gateways in front of Mailgun may reply 202 instead.
+    pub fn strict_status(mut self, strict: bool) -> Self {
+        self.strict_status = strict;
+        self
+    }
+
+    /// Requiring TLS applies `o:require-tls` to every message, for compliance.
+    pub fn tls_policy(mut self, policy: TlsPolicy) -> Self {
+        self.tls = policy;
+        self
+    }
+
+    /// Emails larger than this fail with `SendError::TooLarge` before being uploaded,
+    /// defaults to Mailgun's limit of 25MB.
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = bytes;
+        self
+    }
+
+    /// Spaces out sends to stay within the given number of messages per minute,
+    /// `send` waits for its turn. Up to a minute worth of sends may go out at once.
+    pub fn rate_limit(mut self, per_minute: u32) -> Self {
+        self.rate_limit = Some(per_minute);
+        self
+    }
+
+    /// Delivers emails through the given transport instead of posting them to Mailgun.
+    /// The other APIs still talk to Mailgun.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// How long a successful send is remembered per idempotency key, defaults to 24 hours.
+    /// Keys are remembered in memory, per Mailer.
+    pub fn idempotency_ttl(mut self, ttl: Duration) -> Self {
+        self.idempotency_ttl = ttl;
+        self
+    }
+
+    /// Adds an observer notified around every send, may be called several times.
+    pub fn observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Adds middleware running around every send, may be called several times.
+    /// See `SendMiddleware` for the order they run in.
+    pub fn middleware(mut self, middleware: impl SendMiddleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Checks recipients against a copy of the domain's bounces, complaints and
+    /// unsubscribes before sending, failing with `SendError::Suppressed` instead.
+    /// The copy is fetched on the first send and refreshed once older than `ttl`, so
+    /// recently suppressed addresses may still be sent to. A failed refresh keeps the
+    /// previous copy and is retried after ten seconds at the earliest.
+    /// Only supported by the async Mailer, `build_blocking` fails when it is set.
+    pub fn with_suppression_guard(mut self, ttl: Duration) -> Self {
+        self.suppression_ttl = Some(ttl);
+        self
+    }
+
+    /// Registers a from address under a name, see `Mailer::identity`.
+    /// The address is validated on build.
+    pub fn identity(mut self, name: impl Into<String>, from: impl Into<String>) -> Self {
+        self.identities.push((name.into(), from.into()));
+        self
+    }
+
+    pub fn build(self) -> Result<Mailer, SetupError> {
+        let urls = self.urls()?;
+        let auth = self.auth()?;
+        let on_behalf_of = self.on_behalf_of_header()?;
+        let identities = self
+            .identities
+            .iter()
+            .map(|(name, from)| {
+                let from = from.parse::<EmailAddress>().map_err(|err| {
+                    SetupError::InvalidVar("identity", format!("{}: {}", name, err))
+                })?;
+                Ok((name.clone(), from.to_string()))
+            })
+            .collect::<Result<HashMap<_, _>, SetupError>>()?;
+
+        let client = self.http_client()?;
+
+        Ok(Mailer {
+            from: urls.from,
+            domain: self.domain,
+            base_url: urls.base_url,
+            messages_url: urls.messages_url,
+            auth,
+            on_behalf_of,
+            client,
+            retry: self.retry,
+            batch_concurrency: self.batch_concurrency,
+            sandbox: self.sandbox,
+            tls: self.tls,
+            subject_policy: self.subject_policy.clone(),
+            recipient_policy: self.recipient_policy.clone(),
+            strict_status: self.strict_status,
+            max_message_size: self.max_message_size,
+            rate_limiter: self.rate_limit.map(RateLimiter::per_minute),
+            send_timeout: self.send_timeout,
+            transport: self.transport,
+            idempotency: IdempotencyCache::new(self.idempotency_ttl),
+            observers: self.observers,
+            middleware: self.middleware,
+            suppression_guard: self.suppression_ttl.map(SuppressionGuard::new),
+            identities,
+        })
+    }
+
+    /// Builds a pool of Mailers for many domains, sharing one client and the settings of
+    /// this builder. The from address set on this builder only applies to its own domain,
+    /// see `MailerPool::default_from` for the others.
+    pub fn build_pool(mut self) -> Result<MailerPool, SetupError> {
+        self.client = Some(self.http_client()?);
+        let mut from = HashMap::new();
+        if let Some(address) = self.from.take() {
+            from.insert(self.domain.clone(), address);
+        }
+        let pool = MailerPool::new(self, from);
+        pool.validate()?;
+        Ok(pool)
+    }
+
+    /// A copy of this builder targeting another domain.
+    pub(crate) fn for_domain(&self, domain: &str, from: Option<String>) -> Self {
+        let mut builder = self.clone();
+        builder.domain = domain.to_string();
+        builder.from = from;
+        builder
+    }
+
+    /// Builds a blocking Mailer, for programs without an async runtime.
+    /// Custom clients, transports, middleware, observers, identities, the send timeout
+    /// and idempotency deduplication only apply to the async Mailer and are ignored.
+    /// Fails with `SetupError::InvalidVar` when a suppression guard is set, rather than
+    /// sending to suppressed addresses.
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(self) -> Result<crate::blocking::Mailer, SetupError> {
+        if self.suppression_ttl.is_some() {
+            return Err(SetupError::InvalidVar(
+                "suppression_guard",
+                "only supported by the async Mailer".into(),
+            ));
+        }
+        let urls = self.urls()?;
+        let auth = self.auth()?;
+        let on_behalf_of = self.on_behalf_of_header()?;
+        let client = build_client!(self, reqwest::blocking::Client::builder())?;
+
+        Ok(crate::blocking::Mailer {
+            from: urls.from,
+            messages_url: urls.messages_url,
+            auth,
+            on_behalf_of,
+            client,
+            retry: self.retry,
+            batch_concurrency: self.batch_concurrency,
+            sandbox: self.sandbox,
+            tls: self.tls,
+            subject_policy: self.subject_policy.clone(),
+            recipient_policy: self.recipient_policy.clone(),
+            strict_status: self.strict_status,
+            max_message_size: self.max_message_size,
+            rate_limiter: self.rate_limit.map(RateLimiter::per_minute),
+        })
+    }
+
+    /// The client given to the builder, or a new one with its timeouts, proxy and pool settings.
+    fn http_client(&self) -> Result<reqwest::Client, SetupError> {
+        if let Some(client) = &self.client {
+            return Ok(client.clone());
+        }
+        build_client!(self, reqwest::Client::builder())
+    }
+
+    fn urls(&self) -> Result<Urls, SetupError> {
+        let from = self
+            .from
+            .clone()
+            .unwrap_or_else(|| format!("noreply@{}", self.domain));
+
+        let base_url = match &self.base_url {
+            Some(url) => url
+                .parse::<reqwest::Url>()
+                .map_err(|err| SetupError::InvalidVar("mg_url", err.to_string()))?,
+            None => self.region.base_url(),
+        };
+        if base_url.cannot_be_a_base() {
+            return Err(SetupError::InvalidVar("mg_url", "not a base url".into()));
+        }
+
+        if self.domain.is_empty() || self.domain.contains('/') {
+            return Err(SetupError::InvalidVar(
+                "domain",
+                format!("`{}` is not a domain", self.domain),
+            ));
+        }
+
+        let mut messages_url = base_url.clone();
+        messages_url
+            .path_segments_mut()
+            .expect("checked above")
+            .pop_if_empty()
+            .extend(["v3", &self.domain, "messages"]);
+
+        Ok(Urls {
+            from,
+            base_url,
+            messages_url,
+        })
+    }
+
+    fn auth(&self) -> Result<reqwest::header::HeaderValue, SetupError> {
+        let token = base64::encode(format!("api:{}", self.token));
+        let mut auth = reqwest::header::HeaderValue::from_str(&format!("Basic {}", token))
+            .map_err(|err| SetupError::Build(err.to_string()))?;
+        auth.set_sensitive(true);
+        Ok(auth)
+    }
+
+    fn on_behalf_of_header(&self) -> Result<Option<reqwest::header::HeaderValue>, SetupError> {
+        self.on_behalf_of
+            .as_deref()
+            .map(|subaccount| match subaccount.trim() {
+                "" => Err(SetupError::InvalidVar("on_behalf_of", "empty".into())),
+                subaccount => reqwest::header::HeaderValue::from_str(subaccount)
+                    .map_err(|err| SetupError::InvalidVar("on_behalf_of", err.to_string())),
+            })
+            .transpose()
+    }
+}
+
+struct Urls {
+    from: String,
+    base_url: reqwest::Url,
+    messages_url: reqwest::Url,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_hides_token() {
+        let builder = MailerBuilder::new("fakedomain", "tomatotoken");
+        let debug = format!("{:?}", builder);
+        assert!(debug.contains(r#"token: "***""#), "{}", debug);
+        assert!(!debug.contains("tomatotoken"));
+
+        let pool = builder.build_pool().expect("Creating pool");
+        assert!(!format!("{:?}", pool).contains("tomatotoken"));
+    }
+
+    #[test]
+    fn from_vars_with_prefix() {
+        let vars: HashMap<&str, &str> = [
+            ("MYAPP_DOMAIN", "mg.example.com"),
+            ("MYAPP_TOKEN", "tomatotoken"),
+            ("MYAPP_REGION", "us"),
+            ("MYAPP_BASE_URL", "https://mailgun.internal"),
+            ("MYAPP_FROM", "support@example.com"),
+            ("MYAPP_TRANSPORT", "file:/tmp/outbox"),
+        ]
+        .into();
+        let builder =
+            MailerBuilder::from_vars("MYAPP_", |name| vars.get(name).map(|v| v.to_string()))
+                .expect("Reading variables");
+        assert_eq!(builder.domain, "mg.example.com");
+        assert_eq!(builder.region, Region::Us);
+        assert_eq!(
+            builder.base_url.as_deref(),
+            Some("https://mailgun.internal")
+        );
+        assert_eq!(builder.from.as_deref(), Some("support@example.com"));
+        assert!(format!("{:?}", builder.transport).contains("/tmp/outbox"));
+
+        let err = MailerBuilder::from_vars("OTHER", |name| vars.get(name).map(|v| v.to_string()))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            SetupError::InvalidEnvVar("OTHER_DOMAIN".into(), "not set".into())
+        );
+        let err = MailerBuilder::from_vars("MAILER46", |name| match name {
+            "MAILER46_DOMAIN" => Some("mg.example.com".into()),
+            _ => None,
+        })
+        .unwrap_err();
+        assert_eq!(err, SetupError::EnvVarMissing("MAILER46_TOKEN"));
+
+        let err = MailerBuilder::from_vars("MYAPP", |name| match name {
+            "MYAPP_REGION" => Some("mars".into()),
+            _ => vars.get(name).map(|v| v.to_string()),
+        })
+        .unwrap_err();
+        assert!(matches!(err, SetupError::InvalidEnvVar(var, _) if var == "MYAPP_REGION"));
+
+        let err = MailerBuilder::from_vars("MYAPP", |name| match name {
+            "MYAPP_TRANSPORT" => Some("smtp".into()),
+            _ => vars.get(name).map(|v| v.to_string()),
+        })
+        .unwrap_err();
+        assert!(matches!(err, SetupError::InvalidEnvVar(var, _) if var == "MYAPP_TRANSPORT"));
+    }
+}
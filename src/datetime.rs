@@ -0,0 +1,84 @@
+//! Serde helpers for the date formats used by Mailgun.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub(crate) fn serialize_rfc2822<S>(
+    time: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match time {
+        Some(time) => serializer.serialize_str(&time.to_rfc2822()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Mailgun writes RFC 2822 dates with a `UTC` zone, which chrono only accepts as `GMT`.
+pub(crate) fn parse_rfc2822(s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    let s = match s.strip_suffix(" UTC") {
+        Some(prefix) => format!("{} GMT", prefix),
+        None => s.to_string(),
+    };
+    DateTime::parse_from_rfc2822(&s).map(|time| time.with_timezone(&Utc))
+}
+
+pub(crate) fn deserialize_rfc2822<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_rfc2822(&s).map_err(serde::de::Error::custom)
+}
+
+pub(crate) fn deserialize_optional_rfc2822<'de, D>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse_rfc2822(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Newer endpoints write ISO 8601 dates instead of RFC 2822, either is accepted.
+pub(crate) fn deserialize_optional_any<'de, D>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| {
+            DateTime::parse_from_rfc3339(&s)
+                .map(|time| time.with_timezone(&Utc))
+                .or_else(|_| parse_rfc2822(&s))
+                .map_err(serde::de::Error::custom)
+        })
+        .transpose()
+}
+
+pub(crate) fn serialize_timestamp<S>(
+    time: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match time {
+        Some(time) => serializer.serialize_i64(time.timestamp()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Mailgun reports event timestamps as fractional seconds since the epoch.
+pub(crate) fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let secs = f64::deserialize(deserializer)?;
+    DateTime::from_timestamp(secs.trunc() as i64, (secs.fract() * 1e9) as u32)
+        .ok_or_else(|| serde::de::Error::custom(format!("timestamp out of range: {}", secs)))
+}
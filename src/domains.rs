@@ -0,0 +1,806 @@
+//! Managing sending domains, their DNS records, tracking settings and SMTP credentials,
+//! `/v3/domains`.
+//!
+//! ```
+//! use mailgun46::{Mailer, domains::{DnsRecordKind, NewDomain}};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let created = mailer.create_domain(&NewDomain::new("mg.example.com")).await?;
+//! for record in &created.sending_dns_records {
+//!     if record.kind() == DnsRecordKind::Dkim {
+//!         println!("{} TXT {}", record.name, record.value);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use chrono::{DateTime, Utc};
+use serde::de::IgnoredAny;
+
+use crate::{Mailer, SendError};
+
+/// Mailgun lists at most this many domains per request.
+const MAX_DOMAINS: &str = "1000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainState {
+    Active,
+    Unverified,
+    Disabled,
+    #[serde(other)]
+    Other,
+}
+
+/// What Mailgun does with messages it considers spam.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpamAction {
+    Disabled,
+    Block,
+    Tag,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct Domain {
+    pub name: String,
+    pub state: DomainState,
+
+    /// `custom` or `sandbox`.
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    #[serde(default)]
+    pub smtp_login: Option<String>,
+
+    #[serde(default)]
+    pub spam_action: Option<SpamAction>,
+
+    #[serde(default)]
+    pub wildcard: bool,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A domain along with the DNS records Mailgun expects for it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct DomainDetails {
+    pub domain: Domain,
+
+    #[serde(default)]
+    pub receiving_dns_records: Vec<DnsRecord>,
+
+    #[serde(default)]
+    pub sending_dns_records: Vec<DnsRecord>,
+}
+
+/// A DNS record to publish for a domain, along with whether Mailgun found it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct DnsRecord {
+    /// `TXT`, `MX` or `CNAME`.
+    pub record_type: String,
+
+    /// Receiving records have no name, they apply to the domain itself.
+    #[serde(default)]
+    pub name: String,
+
+    pub value: String,
+
+    #[serde(default)]
+    pub priority: Option<String>,
+
+    /// `valid` once Mailgun has seen the record, else `unknown` or `invalid`.
+    pub valid: String,
+
+    /// The values Mailgun found when last checking DNS.
+    #[serde(default)]
+    pub cached: Vec<String>,
+}
+
+/// What a DnsRecord is for, derived from its type and contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsRecordKind {
+    Spf,
+    Dkim,
+    Mx,
+    /// The CNAME used for open and click tracking.
+    Tracking,
+    Other,
+}
+
+impl DnsRecord {
+    pub fn kind(&self) -> DnsRecordKind {
+        match self.record_type.to_ascii_uppercase().as_str() {
+            "TXT" if self.value.starts_with("v=spf1") => DnsRecordKind::Spf,
+            "TXT" if self.name.contains("._domainkey.") => DnsRecordKind::Dkim,
+            "MX" => DnsRecordKind::Mx,
+            "CNAME" => DnsRecordKind::Tracking,
+            _ => DnsRecordKind::Other,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.valid == "valid"
+    }
+
+    /// Whether a published value satisfies this record. SPF records may include other
+    /// senders as well, as long as they include every mechanism Mailgun asks for.
+    pub(crate) fn is_satisfied_by(&self, found: &str) -> bool {
+        let normalize = |value: &str| {
+            value
+                .trim()
+                .trim_matches('"')
+                .trim_end_matches('.')
+                .to_ascii_lowercase()
+        };
+        let (expected, found) = (normalize(&self.value), normalize(found));
+        if self.kind() != DnsRecordKind::Spf {
+            return expected == found;
+        }
+        let mechanisms: Vec<_> = found.split_whitespace().collect();
+        found.starts_with("v=spf1")
+            && expected
+                .split_whitespace()
+                .filter(|term| term.starts_with("include:") || term.starts_with("ip4:"))
+                .all(|term| mechanisms.contains(&term))
+    }
+}
+
+/// The outcome of `Mailer::verify_domain_setup`, one check per DNS record Mailgun expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainSetupReport {
+    pub domain: Domain,
+    pub checks: Vec<RecordCheck>,
+}
+
+impl DomainSetupReport {
+    /// Every record is published as expected.
+    pub fn is_ok(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status == RecordStatus::Valid)
+    }
+
+    /// The records still to publish or correct.
+    pub fn problems(&self) -> impl Iterator<Item = &RecordCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.status != RecordStatus::Valid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordCheck {
+    /// The record as Mailgun expects it.
+    pub record: DnsRecord,
+    pub status: RecordStatus,
+
+    /// The values published under the record's name and type. Looked up live with the
+    /// `dns` feature, else the values Mailgun found when verifying.
+    pub found: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordStatus {
+    /// Published with the expected value.
+    Valid,
+    /// Nothing is published under the record's name and type.
+    Missing,
+    /// Only other values are published, see `RecordCheck::found`.
+    Mismatch,
+    /// The DNS lookup failed, such as on a timeout, so the record is unknown.
+    LookupFailed(String),
+}
+
+impl RecordCheck {
+    #[cfg(not(feature = "dns"))]
+    fn from_mailgun(record: DnsRecord) -> Self {
+        let found = record.cached.clone();
+        let status = if record.is_valid() {
+            RecordStatus::Valid
+        } else {
+            Self::status(&record, &found)
+        };
+        Self {
+            record,
+            status,
+            found,
+        }
+    }
+
+    #[cfg(feature = "dns")]
+    fn from_lookup(record: DnsRecord, lookup: Result<Vec<String>, String>) -> Self {
+        match lookup {
+            Ok(found) => Self {
+                status: Self::status(&record, &found),
+                record,
+                found,
+            },
+            Err(err) => Self {
+                record,
+                status: RecordStatus::LookupFailed(err),
+                found: Vec::new(),
+            },
+        }
+    }
+
+    fn status(record: &DnsRecord, found: &[String]) -> RecordStatus {
+        if found.iter().any(|value| record.is_satisfied_by(value)) {
+            RecordStatus::Valid
+        } else if found.is_empty() {
+            RecordStatus::Missing
+        } else {
+            RecordStatus::Mismatch
+        }
+    }
+}
+
+/// A domain to create, only the name is required.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct NewDomain {
+    pub name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_password: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spam_action: Option<SpamAction>,
+
+    /// Accepts mail for subdomains as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wildcard: Option<bool>,
+
+    /// 1024 or 2048 bits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dkim_key_size: Option<u32>,
+}
+
+impl NewDomain {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            smtp_password: None,
+            spam_action: None,
+            wildcard: None,
+            dkim_key_size: None,
+        }
+    }
+}
+
+/// An SMTP login for sending through Mailgun's SMTP servers.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SmtpCredential {
+    /// The full login, such as `postmaster@mg.example.com`.
+    pub login: String,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Which opens, clicks and unsubscribes Mailgun tracks for a domain, unless overridden
+/// per message.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct TrackingSettings {
+    pub open: Tracking,
+    pub click: Tracking,
+    pub unsubscribe: UnsubscribeTracking,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub struct Tracking {
+    /// Html only click tracking is reported as active.
+    #[serde(deserialize_with = "deserialize_active")]
+    pub active: bool,
+}
+
+/// Unsubscribe tracking adds a footer with an unsubscribe link to every message.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UnsubscribeTracking {
+    #[serde(deserialize_with = "deserialize_active")]
+    pub active: bool,
+
+    /// The footer of html bodies, `%unsubscribe_url%` is replaced with the link.
+    #[serde(default)]
+    pub html_footer: String,
+
+    #[serde(default)]
+    pub text_footer: String,
+}
+
+/// Mailgun reports tracking as a bool, or as a string for html only click tracking.
+fn deserialize_active<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Active {
+        Bool(bool),
+        Text(String),
+    }
+
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        Active::Bool(active) => active,
+        Active::Text(text) => matches!(text.as_str(), "yes" | "true" | "htmlonly"),
+    })
+}
+
+#[derive(serde::Deserialize)]
+struct TrackingReply {
+    tracking: TrackingSettings,
+}
+
+#[derive(serde::Deserialize)]
+struct DomainsReply {
+    items: Vec<Domain>,
+}
+
+#[derive(serde::Deserialize)]
+struct CredentialsReply {
+    items: Vec<SmtpCredential>,
+}
+
+impl Mailer {
+    /// Lists the domains on the account, up to 1000.
+    pub async fn domains(&self) -> Result<Vec<Domain>, SendError> {
+        let reply: DomainsReply = self
+            .execute(
+                self.get(self.api_url(&["v3", "domains"]))
+                    .query(&[("limit", MAX_DOMAINS)]),
+            )
+            .await?;
+        Ok(reply.items)
+    }
+
+    pub async fn domain_details(&self, name: &str) -> Result<DomainDetails, SendError> {
+        self.execute(self.get(self.api_url(&["v3", "domains", name])))
+            .await
+    }
+
+    /// Asks Mailgun to check the DNS records of the Mailer's domain again, and reports
+    /// which records are published as expected. With the `dns` feature the records are
+    /// also looked up live, so changes show before Mailgun's cache catches up.
+    ///
+    /// ```
+    /// use mailgun46::Mailer;
+    /// # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+    /// let report = mailer.verify_domain_setup().await?;
+    /// for check in report.problems() {
+    ///     let record = &check.record;
+    ///     println!("Publish {} {} {}", record.name, record.record_type, record.value);
+    ///     println!("  {:?}, found {:?}", check.status, check.found);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn verify_domain_setup(&self) -> Result<DomainSetupReport, SendError> {
+        let details: DomainDetails = self
+            .execute(self.put(self.api_url(&["v3", "domains", &self.domain, "verify"])))
+            .await?;
+        let records = details
+            .sending_dns_records
+            .into_iter()
+            .chain(details.receiving_dns_records);
+
+        #[cfg(feature = "dns")]
+        let checks = lookup::check_records(&details.domain.name, records).await;
+        #[cfg(not(feature = "dns"))]
+        let checks = records.map(RecordCheck::from_mailgun).collect();
+
+        Ok(DomainSetupReport {
+            domain: details.domain,
+            checks,
+        })
+    }
+
+    /// Creates the domain, the reply holds the DNS records to publish for it.
+    pub async fn create_domain(&self, domain: &NewDomain) -> Result<DomainDetails, SendError> {
+        self.execute(self.post(self.api_url(&["v3", "domains"])).form(domain))
+            .await
+    }
+
+    pub async fn delete_domain(&self, name: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.api_url(&["v3", "domains", name])))
+            .await?;
+        Ok(())
+    }
+
+    /// The tracking settings of the Mailer's domain.
+    pub async fn tracking_settings(&self) -> Result<TrackingSettings, SendError> {
+        let reply: TrackingReply = self
+            .execute(self.get(self.api_url(&["v3", "domains", &self.domain, "tracking"])))
+            .await?;
+        Ok(reply.tracking)
+    }
+
+    pub async fn update_open_tracking(&self, active: bool) -> Result<(), SendError> {
+        self.update_tracking("open", &[("active", active)]).await
+    }
+
+    pub async fn update_click_tracking(&self, active: bool) -> Result<(), SendError> {
+        self.update_tracking("click", &[("active", active)]).await
+    }
+
+    /// Enables or disables the unsubscribe footer, replacing both footers.
+    pub async fn update_unsubscribe_tracking(
+        &self,
+        settings: &UnsubscribeTracking,
+    ) -> Result<(), SendError> {
+        self.update_tracking("unsubscribe", settings).await
+    }
+
+    async fn update_tracking<T>(&self, kind: &str, form: &T) -> Result<(), SendError>
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        let _: IgnoredAny = self
+            .execute(
+                self.put(self.api_url(&["v3", "domains", &self.domain, "tracking", kind]))
+                    .form(form),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Lists the SMTP logins of the Mailer's domain.
+    pub async fn smtp_credentials(&self) -> Result<Vec<SmtpCredential>, SendError> {
+        let reply: CredentialsReply = self
+            .execute(self.get(self.api_url(&["v3", "domains", &self.domain, "credentials"])))
+            .await?;
+        Ok(reply.items)
+    }
+
+    /// Creates an SMTP login, `login` is either the full address or the part before `@`.
+    pub async fn create_smtp_credential(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(
+                self.post(self.api_url(&["v3", "domains", &self.domain, "credentials"]))
+                    .form(&[("login", login), ("password", password)]),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Changes the password of an SMTP login, for rotating secrets.
+    pub async fn update_smtp_password(&self, login: &str, password: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(
+                self.put(self.api_url(&["v3", "domains", &self.domain, "credentials", login]))
+                    .form(&[("password", password)]),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_smtp_credential(&self, login: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.api_url(&[
+                "v3",
+                "domains",
+                &self.domain,
+                "credentials",
+                login,
+            ])))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Live DNS lookups of the records Mailgun expects.
+#[cfg(feature = "dns")]
+mod lookup {
+    use hickory_resolver::{
+        config::{ResolverConfig, ResolverOpts},
+        error::{ResolveError, ResolveErrorKind},
+        proto::rr::{RData, RecordType},
+        TokioAsyncResolver,
+    };
+
+    use super::{DnsRecord, RecordCheck};
+
+    pub(super) async fn check_records(
+        domain: &str,
+        records: impl Iterator<Item = DnsRecord>,
+    ) -> Vec<RecordCheck> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|_| {
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
+        let mut checks = Vec::new();
+        for record in records {
+            let lookup = lookup(&resolver, domain, &record).await;
+            checks.push(RecordCheck::from_lookup(record, lookup));
+        }
+        checks
+    }
+
+    async fn lookup(
+        resolver: &TokioAsyncResolver,
+        domain: &str,
+        record: &DnsRecord,
+    ) -> Result<Vec<String>, String> {
+        // Receiving records have no name of their own.
+        let name = match record.name.as_str() {
+            "" => domain,
+            name => name,
+        };
+        let record_type = match record.record_type.to_ascii_uppercase().as_str() {
+            "TXT" => RecordType::TXT,
+            "MX" => RecordType::MX,
+            "CNAME" => RecordType::CNAME,
+            other => return Err(format!("unsupported record type `{}`", other)),
+        };
+        let lookup = match resolver.lookup(name, record_type).await {
+            Ok(lookup) => lookup,
+            Err(err) if is_not_found(&err) => return Ok(Vec::new()),
+            Err(err) => return Err(err.to_string()),
+        };
+        Ok(lookup
+            .record_iter()
+            .filter_map(|record| match record.data()? {
+                RData::TXT(txt) => Some(
+                    txt.txt_data()
+                        .iter()
+                        .map(|chunk| String::from_utf8_lossy(chunk))
+                        .collect(),
+                ),
+                RData::MX(mx) => Some(mx.exchange().to_utf8()),
+                RData::CNAME(cname) => Some(cname.0.to_utf8()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn is_not_found(err: &ResolveError) -> bool {
+        matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn create_domain() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/domains"))
+            .and(matchers::body_string_contains("name=mg.example.com"))
+            .and(matchers::body_string_contains("spam_action=tag"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "domain": {
+                    "created_at": "Sun, 19 Oct 2014 18:49:36 GMT",
+                    "name": "mg.example.com",
+                    "smtp_login": "postmaster@mg.example.com",
+                    "spam_action": "tag",
+                    "state": "unverified",
+                    "type": "custom",
+                    "wildcard": false
+                },
+                "message": "Domain has been created",
+                "receiving_dns_records": [
+                    {"priority": "10", "record_type": "MX", "valid": "unknown", "value": "mxa.mailgun.org"}
+                ],
+                "sending_dns_records": [
+                    {"record_type": "TXT", "valid": "unknown", "name": "mg.example.com", "value": "v=spf1 include:mailgun.org ~all"},
+                    {"record_type": "TXT", "valid": "valid", "name": "k1._domainkey.mg.example.com", "value": "k=rsa; p=MIGf"},
+                    {"record_type": "CNAME", "valid": "unknown", "name": "email.mg.example.com", "value": "mailgun.org"}
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let mut domain = NewDomain::new("mg.example.com");
+        domain.spam_action = Some(SpamAction::Tag);
+        let created = mailer
+            .create_domain(&domain)
+            .await
+            .expect("Creating domain");
+
+        assert_eq!(created.domain.state, DomainState::Unverified);
+        assert_eq!(created.receiving_dns_records[0].kind(), DnsRecordKind::Mx);
+        let kinds: Vec<_> = created
+            .sending_dns_records
+            .iter()
+            .map(DnsRecord::kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                DnsRecordKind::Spf,
+                DnsRecordKind::Dkim,
+                DnsRecordKind::Tracking
+            ]
+        );
+        assert!(created.sending_dns_records[1].is_valid());
+    }
+
+    // Checks live DNS instead with the feature.
+    #[cfg(not(feature = "dns"))]
+    #[tokio::test]
+    async fn verify_domain_setup() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path("/v3/domains/fakedomain/verify"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "domain": {"name": "fakedomain", "state": "unverified", "type": "custom"},
+                "message": "Domain DNS records have been updated",
+                "receiving_dns_records": [
+                    {"priority": "10", "record_type": "MX", "valid": "unknown", "value": "mxa.mailgun.org", "cached": []}
+                ],
+                "sending_dns_records": [
+                    {"record_type": "TXT", "valid": "invalid", "name": "fakedomain", "value": "v=spf1 include:mailgun.org ~all", "cached": ["v=spf1 -all"]},
+                    {"record_type": "TXT", "valid": "valid", "name": "k1._domainkey.fakedomain", "value": "k=rsa; p=MIGf", "cached": ["k=rsa; p=MIGf"]}
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let report = mailer
+            .verify_domain_setup()
+            .await
+            .expect("Verifying domain");
+
+        assert!(!report.is_ok());
+        let statuses: Vec<_> = report.checks.iter().map(|check| &check.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                &RecordStatus::Mismatch,
+                &RecordStatus::Valid,
+                &RecordStatus::Missing
+            ]
+        );
+        assert_eq!(report.problems().count(), 2);
+        assert_eq!(report.checks[0].found, ["v=spf1 -all"]);
+    }
+
+    #[test]
+    fn published_values() {
+        let record = |record_type: &str, value: &str| DnsRecord {
+            record_type: record_type.into(),
+            name: "mg.example.com".into(),
+            value: value.into(),
+            priority: None,
+            valid: "unknown".into(),
+            cached: Vec::new(),
+        };
+
+        let spf = record("TXT", "v=spf1 include:mailgun.org ~all");
+        assert!(spf.is_satisfied_by("v=spf1 include:_spf.google.com include:mailgun.org -all"));
+        assert!(!spf.is_satisfied_by("v=spf1 include:_spf.google.com ~all"));
+
+        let tracking = record("CNAME", "mailgun.org");
+        assert!(tracking.is_satisfied_by("Mailgun.org."));
+        assert!(!tracking.is_satisfied_by("eu.mailgun.org"));
+
+        let dkim = record("TXT", "k=rsa; p=MIGf");
+        assert!(dkim.is_satisfied_by("\"k=rsa; p=MIGf\""));
+    }
+
+    #[tokio::test]
+    async fn rotate_smtp_password() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path(
+                "/v3/domains/fakedomain/credentials/postmaster@fakedomain",
+            ))
+            .and(matchers::body_string("password=n3w-s3cret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "message": "Password changed"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/domains/fakedomain/credentials"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "total_count": 1,
+                "items": [{
+                    "size_bytes": 0,
+                    "created_at": "Tue, 27 Sep 2011 20:24:22 GMT",
+                    "mailbox": "postmaster@fakedomain",
+                    "login": "postmaster@fakedomain"
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let credentials = mailer
+            .smtp_credentials()
+            .await
+            .expect("Listing credentials");
+        assert_eq!(credentials[0].login, "postmaster@fakedomain");
+        mailer
+            .update_smtp_password(&credentials[0].login, "n3w-s3cret")
+            .await
+            .expect("Changing password");
+    }
+
+    #[tokio::test]
+    async fn tracking_settings() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/domains/fakedomain/tracking"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracking": {
+                    "click": {"active": "htmlonly"},
+                    "open": {"active": false},
+                    "unsubscribe": {
+                        "active": true,
+                        "html_footer": "<a href=\"%unsubscribe_url%\">Unsubscribe</a>",
+                        "text_footer": "Unsubscribe: %unsubscribe_url%"
+                    }
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path("/v3/domains/fakedomain/tracking/open"))
+            .and(matchers::body_string("active=true"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "message": "Domain tracking settings have been updated"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path(
+                "/v3/domains/fakedomain/tracking/unsubscribe",
+            ))
+            .and(matchers::body_string(
+                "active=false&html_footer=&text_footer=Unsubscribe%3A+%25unsubscribe_url%25",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "message": "Domain tracking settings have been updated"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let mut settings = mailer
+            .tracking_settings()
+            .await
+            .expect("Fetching tracking settings");
+        assert!(settings.click.active);
+        assert!(!settings.open.active);
+        assert!(settings.unsubscribe.active);
+
+        mailer
+            .update_open_tracking(true)
+            .await
+            .expect("Enabling open tracking");
+
+        settings.unsubscribe.active = false;
+        settings.unsubscribe.html_footer.clear();
+        mailer
+            .update_unsubscribe_tracking(&settings.unsubscribe)
+            .await
+            .expect("Updating unsubscribe tracking");
+    }
+}
@@ -1,38 +1,900 @@
-use crate::{BuildError, Mailer, MessageId, SendError};
+use std::{collections::BTreeMap, convert::TryInto, fmt, sync::Arc};
 
+use bytes::Bytes;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use tokio::io::AsyncRead;
+
+use crate::{
+    attachment::{validate_content_type, Attachment},
+    idempotency::IDEMPOTENCY_HEADER,
+    BuildError, DeliveryWindow, EmailAddress, Mailer, MessageId, Priority, Recipients, SendError,
+    SendOptions, SendReceipt, SubjectPolicy, TlsPolicy,
+};
+
+/// Variables substituted per recipient, keyed on recipient address.
+pub type RecipientVariables = BTreeMap<String, BTreeMap<String, serde_json::Value>>;
+
+/// Serializes into the form fields sent to Mailgun, and deserializes from them,
+/// so emails can be stored as for instance JSON and sent later. Tags are a list under
+/// `o:tag`, sent as one field per tag. Attachments are left out.
 #[derive(Clone, Debug, serde::Serialize)]
 pub struct Email {
     /// Optional, only used if set. If None the from is taken from Mailer.
     pub(crate) from: Option<String>,
     pub(crate) to: String,
-    pub(crate) subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) subject: Option<String>,
+
+    /// Shared between clones, so copies of a prototype email do not copy the body.
+    #[serde(flatten)]
+    pub(crate) body: Option<Arc<EmailBody>>,
+
+    #[serde(
+        rename = "recipient-variables",
+        skip_serializing_if = "BTreeMap::is_empty",
+        serialize_with = "serialize_json_string"
+    )]
+    pub(crate) recipient_variables: RecipientVariables,
+
+    #[serde(
+        rename = "o:deliverytime",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::datetime::serialize_rfc2822"
+    )]
+    pub(crate) deliver_at: Option<DateTime<Utc>>,
+
+    #[serde(rename = "o:tag", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) tags: Vec<String>,
+
+    #[serde(
+        rename = "o:tracking",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_yes_no"
+    )]
+    pub(crate) tracking: Option<bool>,
+
+    #[serde(
+        rename = "o:tracking-opens",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_yes_no"
+    )]
+    pub(crate) track_opens: Option<bool>,
+
+    #[serde(
+        rename = "o:tracking-clicks",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_yes_no"
+    )]
+    pub(crate) track_clicks: Option<bool>,
+
+    /// Custom MIME headers, sent as `h:<Name>` fields.
+    #[serde(flatten, serialize_with = "serialize_headers")]
+    pub(crate) headers: BTreeMap<String, String>,
+
+    /// Custom data sent as `v:<key>` fields, echoed back in events and webhooks.
+    #[serde(flatten, serialize_with = "serialize_variables")]
+    pub(crate) variables: BTreeMap<String, String>,
+
+    #[serde(
+        rename = "o:testmode",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_yes_no"
+    )]
+    pub(crate) test_mode: Option<bool>,
 
     #[serde(flatten)]
-    pub(crate) body: Option<EmailBody>,
+    pub(crate) options: SendOptions,
+
+    /// Name of a template stored in Mailgun, used instead of the body.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) template: Option<String>,
+
+    #[serde(rename = "t:version", skip_serializing_if = "Option::is_none")]
+    pub(crate) template_version: Option<String>,
+
+    /// Sent as multipart parts, switching the request from a plain form.
+    #[serde(skip)]
+    pub(crate) attachments: Vec<Attachment>,
+
+    /// Also sent as a header, see `EmailBuilder::idempotency_key`.
+    #[serde(skip)]
+    pub(crate) idempotency_key: Option<String>,
+
+    #[serde(skip)]
+    pub(crate) source: Source,
+
+    #[serde(skip)]
+    pub(crate) form: FormCache,
+}
+
+/// What the message is made of, besides the fields.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// Built by Mailgun from the subject, bodies and attachments.
+    #[default]
+    Fields,
+    /// Already rendered, posted to `messages.mime`, see `Mailer::send_mime`.
+    Mime(Bytes),
+    /// Kept by Mailgun at this storage url, see `Mailer::resend`.
+    Stored(String),
+}
+
+/// The form fields an email is sent as, with the size of the request.
+pub(crate) struct Form {
+    pub(crate) fields: Vec<(String, String)>,
+    /// Size in bytes, leaving out streamed attachments.
+    pub(crate) size: usize,
+}
+
+/// The form of an email encoded by `Email::encode`, so a send encodes it only once.
+/// Clones start without it, as they may be changed.
+#[derive(Default)]
+pub(crate) struct FormCache(pub(crate) Option<Arc<Form>>);
+
+impl Clone for FormCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for FormCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Some(form) => write!(f, "FormCache({} bytes)", form.size),
+            None => f.write_str("FormCache(None)"),
+        }
+    }
+}
+
+/// Mailgun refuses to schedule messages further ahead than this.
+const MAX_DELIVERY_DELAY_DAYS: i64 = 3;
+
+/// Mailgun allows at most this many tags per message.
+const MAX_TAGS: usize = 3;
+
+/// Mailgun truncates custom variables beyond this many bytes.
+const MAX_VARIABLES_SIZE: usize = 4 * 1024;
+
+/// Mailgun expects some fields as a JSON document inside a single form field.
+pub(crate) fn serialize_json_string<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: serde::Serialize,
+{
+    let json = serde_json::to_string(value).map_err(serde::ser::Error::custom)?;
+    serializer.serialize_str(&json)
+}
+
+pub(crate) fn serialize_yes_no<S>(flag: &Option<bool>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match flag {
+        Some(true) => serializer.serialize_str("yes"),
+        Some(false) => serializer.serialize_str("no"),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Same as `serialize_yes_no`, for flags that are always sent.
+pub(crate) fn serialize_yes_no_flag<S>(flag: &bool, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(if *flag { "yes" } else { "no" })
+}
+
+fn serialize_headers<S>(
+    headers: &BTreeMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_map(
+        headers
+            .iter()
+            .map(|(name, value)| (format!("h:{}", name), value)),
+    )
+}
+
+fn serialize_variables<S>(
+    variables: &BTreeMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_map(
+        variables
+            .iter()
+            .map(|(key, value)| (format!("v:{}", key), value)),
+    )
+}
+
+/// Header names are tokens, while values may not contain line breaks.
+fn validate_header(name: &str, value: &str) -> Result<(), BuildError> {
+    if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+        return Err(BuildError::InvalidField(
+            "header",
+            format!("invalid header name `{}`", name),
+        ));
+    }
+    if value.contains(['\r', '\n']) {
+        return Err(BuildError::InvalidField(
+            "header",
+            format!("value for `{}` contains a line break", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Header names are case-insensitive, so a header replaces any other spelling of
+/// its name instead of being sent twice.
+pub(crate) fn insert_header(headers: &mut BTreeMap<String, String>, name: String, value: String) {
+    headers.retain(|existing, _| !existing.eq_ignore_ascii_case(&name));
+    headers.insert(name, value);
+}
+
+fn angle_id(id: &str) -> String {
+    match id.parse::<MessageId>() {
+        Ok(id) => id.into_inner(),
+        Err(never) => match never {},
+    }
+}
+
+/// Serializes the `X-Mailgun-Variables` value, which must be a JSON object.
+fn mailgun_variables_header<T>(variables: &T) -> Result<String, BuildError>
+where
+    T: serde::Serialize + ?Sized,
+{
+    let invalid = |reason: String| BuildError::InvalidField("mailgun_variables", reason);
+    let value = serde_json::to_value(variables).map_err(|err| invalid(err.to_string()))?;
+    if !value.is_object() {
+        return Err(invalid("must serialize to a JSON object".into()));
+    }
+    let json = value.to_string();
+    if json.len() > MAX_VARIABLES_SIZE {
+        return Err(invalid(format!(
+            "{} bytes, at most {} allowed",
+            json.len(),
+            MAX_VARIABLES_SIZE
+        )));
+    }
+    Ok(json)
+}
+
+/// Formats the RFC 8058 `List-Unsubscribe` value, one-click unsubscribing requires https.
+fn list_unsubscribe_header(mailto: &str, url: &str) -> Result<String, BuildError> {
+    let invalid = |msg: String| BuildError::InvalidField("list_unsubscribe", msg);
+
+    let mailto = if mailto.starts_with("mailto:") {
+        mailto.to_owned()
+    } else {
+        format!("mailto:{}", mailto)
+    };
+    let address = mailto["mailto:".len()..]
+        .split('?')
+        .next()
+        .unwrap_or_default();
+    if address.is_empty() || !address.contains('@') {
+        return Err(invalid(format!("`{}` has no address", mailto)));
+    }
+
+    let https = url
+        .parse::<reqwest::Url>()
+        .map_err(|err| invalid(format!("`{}`: {}", url, err)))?;
+    if https.scheme() != "https" {
+        return Err(invalid(format!("`{}` is not an https url", url)));
+    }
+
+    for uri in [mailto.as_str(), url] {
+        if uri.contains(['<', '>', ',', ' ', '\r', '\n']) {
+            return Err(invalid(format!("`{}` must be percent-encoded", uri)));
+        }
+    }
+
+    Ok(format!("<{}>, <{}>", mailto, url))
+}
+
+/// The fields `Email` serializes into besides `h:<Name>` headers and `v:<key>` variables.
+const EMAIL_FIELDS: &[&str] = &[
+    "from",
+    "to",
+    "subject",
+    "html",
+    "text",
+    "amp-html",
+    "recipient-variables",
+    "o:deliverytime",
+    "o:tag",
+    "o:tracking",
+    "o:tracking-opens",
+    "o:tracking-clicks",
+    "o:testmode",
+    "o:dkim",
+    "o:require-tls",
+    "o:skip-verification",
+    "o:secondary-dkim",
+    "o:sending-ip-pool",
+    "template",
+    "t:version",
+];
+
+impl<'de> serde::Deserialize<'de> for Email {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(EmailVisitor)
+    }
+}
+
+/// Reads the fields one by one, as tags repeat and headers have dynamic names.
+struct EmailVisitor;
+
+/// Tags are a list in JSON, while a form repeats the field for each tag.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum FieldValue {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl<'de> serde::de::Visitor<'de> for EmailVisitor {
+    type Value = Email;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("the form fields of an email")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Email, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        use serde::de::Error;
+
+        let mut email = Email {
+            from: None,
+            to: String::new(),
+            subject: None,
+            body: None,
+            recipient_variables: RecipientVariables::new(),
+            deliver_at: None,
+            tags: Vec::new(),
+            tracking: None,
+            track_opens: None,
+            track_clicks: None,
+            headers: BTreeMap::new(),
+            variables: BTreeMap::new(),
+            test_mode: None,
+            options: SendOptions::default(),
+            template: None,
+            template_version: None,
+            attachments: Vec::new(),
+            idempotency_key: None,
+            source: Source::Fields,
+            form: FormCache::default(),
+        };
+        let mut to = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            let value = match map.next_value::<Option<FieldValue>>()? {
+                Some(FieldValue::Many(tags)) if key == "o:tag" => {
+                    email.tags.extend(tags);
+                    continue;
+                }
+                Some(FieldValue::Many(_)) => {
+                    return Err(A::Error::custom(format!("expected a string for `{}`", key)))
+                }
+                Some(FieldValue::One(value)) => Some(value),
+                None => None,
+            };
+            match key.as_str() {
+                "html" => body_mut(&mut email.body).html = value,
+                "text" => body_mut(&mut email.body).text = value,
+                "amp-html" => body_mut(&mut email.body).amp = value,
+                _ => {
+                    let value = match value {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    let yes_no = |value: &str| match value {
+                        "yes" | "true" => Ok(Some(true)),
+                        "no" | "false" => Ok(Some(false)),
+                        _ => Err(A::Error::custom(format!(
+                            "expected yes or no for `{}`, got `{}`",
+                            key, value
+                        ))),
+                    };
+                    match key.as_str() {
+                        "from" => email.from = Some(value),
+                        "to" => to = Some(value),
+                        "subject" => email.subject = Some(value),
+                        "recipient-variables" => {
+                            email.recipient_variables =
+                                serde_json::from_str(&value).map_err(A::Error::custom)?
+                        }
+                        "o:deliverytime" => {
+                            email.deliver_at = Some(
+                                crate::datetime::parse_rfc2822(&value).map_err(A::Error::custom)?,
+                            )
+                        }
+                        "o:tag" => email.tags.push(value),
+                        "o:tracking" => email.tracking = yes_no(&value)?,
+                        "o:tracking-opens" => email.track_opens = yes_no(&value)?,
+                        "o:tracking-clicks" => email.track_clicks = yes_no(&value)?,
+                        "o:testmode" => email.test_mode = yes_no(&value)?,
+                        "o:dkim" => email.options.dkim = yes_no(&value)?,
+                        "o:require-tls" => email.options.require_tls = yes_no(&value)?,
+                        "o:skip-verification" => email.options.skip_verification = yes_no(&value)?,
+                        "o:secondary-dkim" => email.options.dkim_signature = Some(value),
+                        "o:sending-ip-pool" => email.options.ip_pool = Some(value),
+                        "template" => email.template = Some(value),
+                        "t:version" => email.template_version = Some(value),
+                        _ => {
+                            if let Some(name) = key.strip_prefix("h:") {
+                                insert_header(&mut email.headers, name.to_string(), value);
+                            } else if let Some(name) = key.strip_prefix("v:") {
+                                email.variables.insert(name.to_string(), value);
+                            } else {
+                                return Err(A::Error::unknown_field(&key, EMAIL_FIELDS));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        email.to = to.ok_or_else(|| A::Error::missing_field("to"))?;
+        email.idempotency_key = email.header(IDEMPOTENCY_HEADER).map(String::from);
+        Ok(email)
+    }
+}
+
+/// Splits a `to` field on the commas between recipients, keeping quoted display names.
+pub(crate) fn split_recipients(to: &str) -> Vec<String> {
+    let mut recipients = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in to.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                recipients.push(to[start..i].to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    recipients.push(to[start..].to_string());
+    recipients.retain(|r| !r.trim().is_empty());
+    recipients
+}
+
+#[cfg(feature = "tracing")]
+fn redact(name: &str, value: &str) -> String {
+    match name.to_ascii_lowercase().as_str() {
+        "to" | "cc" | "bcc" | "h:to" | "h:cc" | "h:reply-to" => split_recipients(value)
+            .iter()
+            .map(|recipient| match bare_address(recipient).rsplit_once('@') {
+                Some((_, domain)) => format!("***@{}", domain),
+                None => "***".into(),
+            })
+            .collect::<Vec<_>>()
+            .join(","),
+        "html" | "text" | "amp-html" => format!("<{} bytes>", value.len()),
+        "recipient-variables" | "h:list-unsubscribe" => "<redacted>".into(),
+        _ => value.to_string(),
+    }
+}
+
+/// Length of the text once form urlencoded, without encoding it.
+fn urlencoded_len(text: &str) -> usize {
+    text.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' | b' ' => 1,
+            _ => 3,
+        })
+        .sum()
+}
+
+/// The body for changing, copied first if it is shared.
+fn body_mut(body: &mut Option<Arc<EmailBody>>) -> &mut EmailBody {
+    Arc::make_mut(body.get_or_insert_with(Default::default))
+}
+
+/// Recipient variables are keyed on the address without display name.
+pub(crate) fn bare_address(recipient: &str) -> &str {
+    match recipient
+        .trim()
+        .strip_suffix('>')
+        .and_then(|r| r.rsplit_once('<'))
+    {
+        Some((_, email)) => email.trim(),
+        None => recipient,
+    }
 }
 
 impl Email {
-    pub async fn send(mut self, mailer: &Mailer) -> Result<MessageId, SendError> {
-        if self.from.is_none() {
-            self.from.replace(mailer.from.clone());
+    /// A copy of the email sent to `to` instead, for sending one prototype to many
+    /// recipients. The body and attachments are shared with the prototype rather than
+    /// copied, recipient variables are kept for `to` only. The idempotency key is left
+    /// out, as every copy would be taken as a duplicate of the first.
+    ///
+    /// ```
+    /// use mailgun46::EmailBuilder;
+    /// # fn example() -> Result<(), mailgun46::BuildError> {
+    /// let prototype = EmailBuilder::default()
+    ///     .to("placeholder@example.com")
+    ///     .subject("Spring sale")
+    ///     .html_body("<p>Everything must go</p>")
+    ///     .build()?;
+    /// let emails: Vec<_> = ["a@example.com", "b@example.com"]
+    ///     .into_iter()
+    ///     .map(|to| prototype.with_recipient(to))
+    ///     .collect();
+    /// # assert_eq!(emails.len(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_recipient(&self, to: impl Into<String>) -> Email {
+        let to = to.into();
+        let mut headers = self.headers.clone();
+        headers.retain(|name, _| !name.eq_ignore_ascii_case(IDEMPOTENCY_HEADER));
+        let recipient_variables = self
+            .recipient_variables
+            .get(bare_address(&to))
+            .map(|vars| [(bare_address(&to).to_string(), vars.clone())].into())
+            .unwrap_or_default();
+
+        Email {
+            from: self.from.clone(),
+            to,
+            subject: self.subject.clone(),
+            body: self.body.clone(),
+            recipient_variables,
+            deliver_at: self.deliver_at,
+            tags: self.tags.clone(),
+            tracking: self.tracking,
+            track_opens: self.track_opens,
+            track_clicks: self.track_clicks,
+            headers,
+            variables: self.variables.clone(),
+            test_mode: self.test_mode,
+            options: self.options.clone(),
+            template: self.template.clone(),
+            template_version: self.template_version.clone(),
+            attachments: self.attachments.clone(),
+            idempotency_key: None,
+            source: self.source.clone(),
+            form: FormCache::default(),
+        }
+    }
+
+    /// The recipients, comma separated as sent to Mailgun.
+    pub fn to(&self) -> &str {
+        &self.to
+    }
+
+    /// Number of recipients, each gets its own copy when recipient variables are set.
+    pub fn recipient_count(&self) -> usize {
+        split_recipients(&self.to).len()
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    /// The custom header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// None for emails without a body, such as those sent with a template.
+    pub fn body(&self) -> Option<&EmailBody> {
+        self.body.as_deref()
+    }
+
+    /// The rendered message of an email sent with `Mailer::send_mime`.
+    pub fn mime(&self) -> Option<&[u8]> {
+        match &self.source {
+            Source::Mime(mime) => Some(mime),
+            Source::Fields | Source::Stored(_) => None,
         }
+    }
 
+    /// The storage url of a message sent again with `Mailer::resend`.
+    pub fn stored_url(&self) -> Option<&str> {
+        match &self.source {
+            Source::Stored(url) => Some(url),
+            Source::Fields | Source::Mime(_) => None,
+        }
+    }
+
+    /// Size in bytes of the request sending this email, including attachments held in
+    /// memory. Streamed attachments are not counted, as their size is unknown.
+    ///
+    /// ```
+    /// use mailgun46::EmailBuilder;
+    /// # fn example() -> Result<(), mailgun46::BuildError> {
+    /// let email = EmailBuilder::default()
+    ///     .to("a@example.com, b@example.com")
+    ///     .text_body("Hello")
+    ///     .attachment("report.csv", "text/csv", vec![0; 2048])
+    ///     .build()?;
+    /// assert_eq!(email.recipient_count(), 2);
+    /// assert!(email.estimated_size() > 2048);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn estimated_size(&self) -> usize {
+        self.form().map_or(0, |form| form.size)
+    }
+
+    /// Turns the email back into a builder, for editing a stored email before sending it.
+    /// Nothing is lost, building it again without changes gives the same email.
+    pub fn into_builder(self) -> EmailBuilder {
+        self.into()
+    }
+
+    pub async fn send(self, mailer: &Mailer) -> Result<MessageId, SendError> {
         mailer.send(self).await
     }
+
+    /// Sends the email, failing with `SendError::Timeout` if it takes longer than
+    /// `timeout` including retries. Overrides `MailerBuilder::send_timeout`.
+    pub async fn send_with_timeout(
+        self,
+        mailer: &Mailer,
+        timeout: std::time::Duration,
+    ) -> Result<MessageId, SendError> {
+        mailer
+            .send_within(self, Some(timeout))
+            .await
+            .map(|receipt| receipt.id)
+    }
+
+    /// Sends the email like `send`, returning Mailgun's reply along with the message id.
+    pub async fn send_with_receipt(self, mailer: &Mailer) -> Result<SendReceipt, SendError> {
+        mailer.send_within(self, mailer.send_timeout).await
+    }
+
+    /// Encodes the form of the email once it is final, for the rest of the send to
+    /// reuse. Changing the email afterwards requires a clone, which encodes again.
+    pub(crate) fn encode(&mut self) -> Result<Arc<Form>, SendError> {
+        let form = self.form()?;
+        self.form = FormCache(Some(form.clone()));
+        Ok(form)
+    }
+
+    /// The form encoded by `encode`, or encoded now.
+    pub(crate) fn form(&self) -> Result<Arc<Form>, SendError> {
+        if let Some(form) = &self.form.0 {
+            return Ok(form.clone());
+        }
+        let value = serde_json::to_value(self).map_err(|err| SendError::Encode(err.to_string()))?;
+        let mut fields = Vec::new();
+        if let serde_json::Value::Object(map) = value {
+            for (name, value) in map {
+                match value {
+                    serde_json::Value::Null => {}
+                    serde_json::Value::String(value) => fields.push((name, value)),
+                    serde_json::Value::Array(values) => {
+                        for value in values {
+                            let value = match value {
+                                serde_json::Value::String(value) => value,
+                                value => value.to_string(),
+                            };
+                            fields.push((name.clone(), value));
+                        }
+                    }
+                    value => fields.push((name, value.to_string())),
+                }
+            }
+        }
+        let size = fields
+            .iter()
+            .map(|(name, value)| urlencoded_len(name) + urlencoded_len(value) + 2)
+            .sum::<usize>()
+            .saturating_sub(1)
+            + self.attachments.iter().map(Attachment::size).sum::<usize>()
+            + self.mime().map_or(0, <[u8]>::len);
+        Ok(Arc::new(Form { fields, size }))
+    }
+
+    /// Whether the email is posted as a plain form rather than multipart.
+    pub(crate) fn is_form(&self) -> bool {
+        self.attachments.is_empty() && !matches!(self.source, Source::Mime(_))
+    }
+
+    /// The fields as text parts followed by the attachments, or the rendered message.
+    pub(crate) fn multipart(&self) -> Result<reqwest::multipart::Form, SendError> {
+        let mut form = reqwest::multipart::Form::new();
+        for (name, value) in &self.form()?.fields {
+            form = form.text(name.clone(), value.clone());
+        }
+        for attachment in &self.attachments {
+            form = form.part("attachment", attachment.part()?);
+        }
+        if let Some(mime) = self.mime() {
+            let message = reqwest::multipart::Part::bytes(mime.to_vec())
+                .file_name("message.mime")
+                .mime_str("message/rfc822")?;
+            form = form.part("message", message);
+        }
+        Ok(form)
+    }
+
+    #[cfg(feature = "blocking")]
+    pub(crate) fn blocking_multipart(
+        &self,
+    ) -> Result<reqwest::blocking::multipart::Form, SendError> {
+        let mut form = reqwest::blocking::multipart::Form::new();
+        for (name, value) in &self.form()?.fields {
+            form = form.text(name.clone(), value.clone());
+        }
+        for attachment in &self.attachments {
+            form = form.part("attachment", attachment.blocking_part()?);
+        }
+        if let Some(mime) = self.mime() {
+            let message = reqwest::blocking::multipart::Part::bytes(mime.to_vec())
+                .file_name("message.mime")
+                .mime_str("message/rfc822")?;
+            form = form.part("message", message);
+        }
+        Ok(form)
+    }
+
+    /// The form fields with recipient addresses, bodies and variables redacted,
+    /// safe for debug logs.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn redacted_fields(&self) -> Vec<(String, String)> {
+        self.form().map_or_else(
+            |_| Vec::new(),
+            |form| {
+                form.fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), redact(name, value)))
+                    .collect()
+            },
+        )
+    }
+
+    /// Fails locally instead of uploading a message Mailgun would reject.
+    pub(crate) fn check_size(&self, limit: usize) -> Result<(), SendError> {
+        let size = self.form()?.size;
+        if size > limit {
+            return Err(SendError::TooLarge { size, limit });
+        }
+        Ok(())
+    }
+
+    /// Replaces the idempotency key, keeping its header in sync.
+    pub(crate) fn set_idempotency_key(&mut self, key: String) {
+        insert_header(&mut self.headers, IDEMPOTENCY_HEADER.into(), key.clone());
+        self.idempotency_key = Some(key);
+    }
+
+    /// Applies the Mailer level from address, sandbox setting, TLS and subject policy.
+    pub(crate) fn apply_defaults(
+        &mut self,
+        from: &str,
+        sandbox: bool,
+        tls: TlsPolicy,
+        subject: &SubjectPolicy,
+    ) -> Result<(), BuildError> {
+        // A rendered message has its own from address and subject.
+        let rendered = self.source != Source::Fields;
+        if self.from.is_none() && !rendered {
+            self.from.replace(from.to_string());
+        }
+        if self.subject.is_none() && !rendered {
+            match subject {
+                SubjectPolicy::Omit => {}
+                SubjectPolicy::Reject => return Err(BuildError::MissingField("subject")),
+                SubjectPolicy::Fallback(subject) => self.subject = Some(subject.clone()),
+            }
+        }
+        if sandbox {
+            self.test_mode = Some(true);
+        }
+        if tls == TlsPolicy::Required {
+            if self.options.require_tls == Some(false) {
+                return Err(BuildError::InvalidField(
+                    "require_tls",
+                    "disabled while the Mailer requires TLS".into(),
+                ));
+            }
+            self.options.require_tls = Some(true);
+        }
+        Ok(())
+    }
 }
 
-#[derive(Clone, Debug, Default, serde::Serialize)]
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct EmailBody {
-    html: Option<String>,
-    text: Option<String>,
+    pub(crate) html: Option<String>,
+    pub(crate) text: Option<String>,
+    /// Shown by clients supporting AMP for Email, others fall back to the html body.
+    #[serde(rename = "amp-html", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) amp: Option<String>,
 }
 
+impl EmailBody {
+    pub fn html(&self) -> Option<&str> {
+        self.html.as_deref()
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.text.as_deref()
+    }
+
+    pub fn amp(&self) -> Option<&str> {
+        self.amp.as_deref()
+    }
+}
+
+/// Builds emails, start from `EmailBuilder::default()` or from an existing `Email`.
 #[derive(Debug, Default)]
 pub struct EmailBuilder {
     from: Option<String>,
     recipients: Vec<String>,
     subject: Option<String>,
-    body: Option<EmailBody>,
+    body: Option<Arc<EmailBody>>,
+    recipient_variables: RecipientVariables,
+    deliver_at: Option<DateTime<Utc>>,
+    tags: Vec<String>,
+    tracking: Option<bool>,
+    track_opens: Option<bool>,
+    track_clicks: Option<bool>,
+    headers: BTreeMap<String, String>,
+    variables: BTreeMap<String, String>,
+    test_mode: Option<bool>,
+    options: SendOptions,
+    template: Option<String>,
+    template_version: Option<String>,
+    attachments: Vec<Attachment>,
+    idempotency_key: Option<String>,
+    /// Kept from the email the builder was made from.
+    source: Source,
+    require_subject: bool,
+    require_body: bool,
+    /// First error from a fallible builder method, reported by build.
+    error: Option<BuildError>,
+}
+
+/// Same as `Email::into_builder`, which avoids the clash with `EmailBuilder::from`.
+impl From<Email> for EmailBuilder {
+    fn from(email: Email) -> Self {
+        Self {
+            from: email.from,
+            recipients: split_recipients(&email.to),
+            subject: email.subject,
+            body: email.body,
+            recipient_variables: email.recipient_variables,
+            deliver_at: email.deliver_at,
+            tags: email.tags,
+            tracking: email.tracking,
+            track_opens: email.track_opens,
+            track_clicks: email.track_clicks,
+            headers: email.headers,
+            variables: email.variables,
+            test_mode: email.test_mode,
+            options: email.options,
+            template: email.template,
+            template_version: email.template_version,
+            attachments: email.attachments,
+            idempotency_key: email.idempotency_key,
+            source: email.source,
+            require_subject: false,
+            require_body: false,
+            error: None,
+        }
+    }
 }
 
 impl EmailBuilder {
@@ -46,40 +908,447 @@ impl EmailBuilder {
         self
     }
 
+    /// Adds parsed recipients, leaving out those already added.
+    /// Entries of the list that failed to parse fail `build` with `BuildError::InvalidRecipient`.
+    pub fn recipients(mut self, recipients: Recipients) -> Self {
+        let (addresses, invalid) = recipients.into_parts();
+        if let Some(err) = invalid.into_iter().next() {
+            self.error.get_or_insert(BuildError::InvalidRecipient(err));
+        }
+        for address in addresses {
+            let duplicate = self
+                .recipients
+                .iter()
+                .any(|r| bare_address(r).eq_ignore_ascii_case(address.email()));
+            if !duplicate {
+                self.recipients.push(address.to_string());
+            }
+        }
+        self
+    }
+
     pub fn subject(mut self, subject: impl Into<String>) -> Self {
         self.subject = Some(subject.into());
         self
     }
 
-    pub fn body(mut self, body: EmailBody) -> Self {
-        self.body = Some(body);
+    /// Fails `build` with `BuildError::MissingField("subject")` unless a subject is set.
+    /// Otherwise emails without one are left to the `SubjectPolicy` of the Mailer.
+    pub fn require_subject(mut self) -> Self {
+        self.require_subject = true;
+        self
+    }
+
+    /// Fails `build` with `BuildError::MissingField("body")` unless a text or html body,
+    /// or a stored template, is set. Mailgun rejects such emails with a 400 otherwise.
+    pub fn require_body(mut self) -> Self {
+        self.require_body = true;
+        self
+    }
+
+    /// Sets the body, replacing any set before. Pass an `Arc<EmailBody>` to share one
+    /// body between many emails without copying it.
+    pub fn body(mut self, body: impl Into<Arc<EmailBody>>) -> Self {
+        self.body = Some(body.into());
         self
     }
 
     pub fn text_body(mut self, text: impl Into<String>) -> Self {
-        let mut body = self.body.unwrap_or_default();
-        body.text = Some(text.into());
-        self.body = Some(body);
+        body_mut(&mut self.body).text = Some(text.into());
         self
     }
 
     pub fn html_body(mut self, html: impl Into<String>) -> Self {
-        let mut body = self.body.unwrap_or_default();
-        body.html = Some(html.into());
-        self.body = Some(body);
+        body_mut(&mut self.body).html = Some(html.into());
+        self
+    }
+
+    /// Sets the html body along with a text body derived from it, for clients and spam
+    /// filters preferring text. A text body set before is kept.
+    #[cfg(feature = "text-fallback")]
+    pub fn html_body_with_text_fallback(mut self, html: impl Into<String>) -> Self {
+        let html = html.into();
+        let body = body_mut(&mut self.body);
+        body.text
+            .get_or_insert_with(|| crate::text_fallback::html_to_text(&html));
+        body.html = Some(html);
+        self
+    }
+
+    /// Renders a handlebars template with the given context as the html body,
+    /// escaping the values. Variables missing from the context fail `build`.
+    ///
+    /// ```
+    /// use mailgun46::EmailBuilder;
+    ///
+    /// #[derive(serde::Serialize)]
+    /// struct Welcome<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// let welcome = Welcome { name: "Niclas" };
+    /// let email = EmailBuilder::default()
+    ///     .to("someone@example.com")
+    ///     .render_html("<h1>Welcome {{name}}</h1>", &welcome)
+    ///     .render_text("Welcome {{name}}", &welcome)
+    ///     .build()?;
+    /// # Ok::<(), mailgun46::BuildError>(())
+    /// ```
+    #[cfg(feature = "render")]
+    pub fn render_html<T>(mut self, template: &str, context: &T) -> Self
+    where
+        T: serde::Serialize,
+    {
+        match crate::render::render("render_html", template, context, true) {
+            Ok(html) => self.html_body(html),
+            Err(err) => {
+                self.error.get_or_insert(err);
+                self
+            }
+        }
+    }
+
+    /// Renders a handlebars template with the given context as the text body.
+    #[cfg(feature = "render")]
+    pub fn render_text<T>(mut self, template: &str, context: &T) -> Self
+    where
+        T: serde::Serialize,
+    {
+        match crate::render::render("render_text", template, context, false) {
+            Ok(text) => self.text_body(text),
+            Err(err) => {
+                self.error.get_or_insert(err);
+                self
+            }
+        }
+    }
+
+    /// Adds an AMP part, requires an html body as well for clients without AMP support.
+    pub fn amp_body(mut self, amp: impl Into<String>) -> Self {
+        body_mut(&mut self.body).amp = Some(amp.into());
+        self
+    }
+
+    /// Attaches a file held in memory.
+    pub fn attachment(
+        mut self,
+        name: impl Into<String>,
+        content_type: impl Into<String>,
+        data: impl Into<Bytes>,
+    ) -> Self {
+        let content_type = content_type.into();
+        if let Err(err) = validate_content_type(&content_type) {
+            self.error.get_or_insert(err);
+        }
+        self.attachments
+            .push(Attachment::bytes(name.into(), content_type, data.into()));
+        self
+    }
+
+    /// Attaches a file read while sending, so it is never held in memory as a whole.
+    ///
+    /// A stream can only be sent once: sends are not retried, and sending the email
+    /// again, such as through `send_batch`, fails with `SendError::InvalidEmail`.
+    /// Streamed attachments do not count towards `MailerBuilder::max_message_size`.
+    pub fn attachment_stream<R>(
+        mut self,
+        name: impl Into<String>,
+        content_type: impl Into<String>,
+        reader: R,
+    ) -> Self
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        let content_type = content_type.into();
+        if let Err(err) = validate_content_type(&content_type) {
+            self.error.get_or_insert(err);
+        }
+        self.attachments
+            .push(Attachment::stream(name.into(), content_type, reader));
+        self
+    }
+
+    /// Sets a variable for a single recipient, used by Mailgun to substitute
+    /// `%recipient.<key>%` in batch sends.
+    /// When used, every recipient must have at least one variable.
+    pub fn recipient_variable(
+        mut self,
+        recipient: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Self {
+        self.recipient_variables
+            .entry(recipient.into())
+            .or_default()
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// Schedules the message for delivery at the given time, at most 3 days ahead.
+    /// Accepts anything convertible into a `DateTime<Utc>`, such as a `SystemTime`.
+    pub fn deliver_at(mut self, time: impl Into<DateTime<Utc>>) -> Self {
+        self.deliver_at = Some(time.into());
+        self
+    }
+
+    /// Schedules the message for the next time the recipient's local clock is within
+    /// the window, or sends it right away when it already is.
+    pub fn deliver_during<Tz: TimeZone>(mut self, window: DeliveryWindow<Tz>) -> Self {
+        self.deliver_at = window.next_delivery(Utc::now());
         self
     }
 
+    /// Tags the message for segmenting analytics in Mailgun, at most 3 tags.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Enables or disables all tracking for this message.
+    pub fn tracking(mut self, enabled: bool) -> Self {
+        self.tracking = Some(enabled);
+        self
+    }
+
+    /// Enables or disables open tracking for this message.
+    pub fn track_opens(mut self, enabled: bool) -> Self {
+        self.track_opens = Some(enabled);
+        self
+    }
+
+    /// Enables or disables click tracking for this message.
+    pub fn track_clicks(mut self, enabled: bool) -> Self {
+        self.track_clicks = Some(enabled);
+        self
+    }
+
+    /// Adds a custom MIME header, replacing any previous value with the same name
+    /// in any case, such as one set by `reply_to`.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        insert_header(&mut self.headers, name.into(), value.into());
+        self
+    }
+
+    /// Marks the message as a reply, so mail clients show it in the same thread.
+    /// Takes a `MessageId` or a raw id, `<>` are added when missing.
+    pub fn in_reply_to(self, id: impl AsRef<str>) -> Self {
+        self.header("In-Reply-To", angle_id(id.as_ref()))
+    }
+
+    /// Lists the ids of earlier messages in the thread, oldest first. For a reply this
+    /// is the `References` of the message replied to followed by its id.
+    /// No header is added for an empty list.
+    pub fn references<I>(self, ids: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let ids: Vec<_> = ids.into_iter().map(|id| angle_id(id.as_ref())).collect();
+        if ids.is_empty() {
+            return self;
+        }
+        self.header("References", ids.join(" "))
+    }
+
+    /// Marks the message as urgent or unimportant in mail clients.
+    pub fn priority(self, priority: Priority) -> Self {
+        self.header("X-Priority", priority.x_priority())
+            .header("Importance", priority.importance())
+    }
+
+    /// Attaches custom data, such as an order id, sent as a `v:<key>` field and reported
+    /// back in the `user_variables` of events and webhooks. Values holding JSON are
+    /// reported back parsed. Replaces any previous value for the key.
+    pub fn variable(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.variables.insert(key.into(), value.into());
+        self
+    }
+
+    /// Attaches custom data as a JSON object in the `X-Mailgun-Variables` header, reported
+    /// back in the `user_variables` of events and webhooks. `variables` must serialize to
+    /// an object of at most 4 KiB, as Mailgun truncates larger ones.
+    pub fn mailgun_variables<T>(mut self, variables: &T) -> Self
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        match mailgun_variables_header(variables) {
+            Ok(json) => self.header("X-Mailgun-Variables", json),
+            Err(err) => {
+                self.error.get_or_insert(err);
+                self
+            }
+        }
+    }
+
+    /// In test mode Mailgun accepts the message without delivering it.
+    pub fn test_mode(mut self, enabled: bool) -> Self {
+        self.test_mode = Some(enabled);
+        self
+    }
+
+    /// Renders a template stored in Mailgun as the body, see `Mailer::create_template`.
+    pub fn template(mut self, name: impl Into<String>) -> Self {
+        self.template = Some(name.into());
+        self
+    }
+
+    /// Uses the given version of the template instead of the active one.
+    pub fn template_version(mut self, tag: impl Into<String>) -> Self {
+        self.template_version = Some(tag.into());
+        self
+    }
+
+    /// Only delivers the message over TLS, same as `SendOptions::require_tls(true)`.
+    pub fn require_tls(mut self) -> Self {
+        self.options.require_tls = Some(true);
+        self
+    }
+
+    /// Sends from the dedicated IP pool with this id, same as `SendOptions::ip_pool`.
+    pub fn ip_pool(mut self, id: impl Into<String>) -> Self {
+        self.options.ip_pool = Some(id.into());
+        self
+    }
+
+    /// Sets the delivery options, replacing any set before.
+    pub fn options(mut self, options: SendOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sends the email at most once per key: a Mailer repeating a send with the same key
+    /// within its idempotency ttl returns the first message id, without submitting again.
+    /// The key is also sent as the `X-Idempotency-Key` header.
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        let key = key.into();
+        self.idempotency_key = Some(key.clone());
+        self.header(IDEMPOTENCY_HEADER, key)
+    }
+
+    /// Lets recipients unsubscribe by email or with one click from their mail client,
+    /// as RFC 8058 and the big mailbox providers expect of bulk mail.
+    ///
+    /// `mailto` is an address, optionally prefixed with `mailto:` and with a query such as
+    /// `?subject=unsubscribe`. `url` must be https and unsubscribe on a POST without
+    /// further confirmation, it usually identifies the recipient.
+    pub fn list_unsubscribe(self, mailto: impl AsRef<str>, url: impl AsRef<str>) -> Self {
+        match list_unsubscribe_header(mailto.as_ref(), url.as_ref()) {
+            Ok(value) => self
+                .header("List-Unsubscribe", value)
+                .header("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+            Err(err) => {
+                let mut builder = self;
+                builder.error.get_or_insert(err);
+                builder
+            }
+        }
+    }
+
+    /// Sets the `Reply-To` header, accepting an `EmailAddress` or a parseable string.
+    pub fn reply_to<A>(mut self, address: A) -> Self
+    where
+        A: TryInto<EmailAddress>,
+        A::Error: Into<BuildError>,
+    {
+        match address.try_into() {
+            Ok(address) => self.header("Reply-To", address),
+            Err(err) => {
+                self.error.get_or_insert(err.into());
+                self
+            }
+        }
+    }
+
     pub fn build(self) -> Result<Email, BuildError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
         if self.recipients.is_empty() {
             return Err(BuildError::MissingField("to"));
         }
+        if self.require_subject && self.subject.is_none() {
+            return Err(BuildError::MissingField("subject"));
+        }
+        if self.require_body && self.template.is_none() {
+            let has_body = self
+                .body
+                .as_ref()
+                .is_some_and(|body| body.text.is_some() || body.html.is_some());
+            if !has_body {
+                return Err(BuildError::MissingField("body"));
+            }
+        }
+
+        if !self.recipient_variables.is_empty() {
+            if let Some(missing) = self
+                .recipients
+                .iter()
+                .flat_map(|recipients| split_recipients(recipients))
+                .find(|r| !self.recipient_variables.contains_key(bare_address(r)))
+            {
+                return Err(BuildError::MissingRecipientVariables(
+                    missing.trim().to_string(),
+                ));
+            }
+        }
+
+        if let Some(deliver_at) = self.deliver_at {
+            if deliver_at > Utc::now() + Duration::days(MAX_DELIVERY_DELAY_DAYS) {
+                return Err(BuildError::InvalidField(
+                    "deliver_at",
+                    format!("more than {} days ahead", MAX_DELIVERY_DELAY_DAYS),
+                ));
+            }
+        }
+
+        if let Some(body) = &self.body {
+            if body.amp.is_some() && body.html.is_none() {
+                return Err(BuildError::InvalidField(
+                    "amp_body",
+                    "requires an html body as fallback".into(),
+                ));
+            }
+        }
+
+        if self.tags.len() > MAX_TAGS {
+            return Err(BuildError::InvalidField(
+                "tag",
+                format!("at most {} tags allowed", MAX_TAGS),
+            ));
+        }
+
+        for (name, value) in &self.headers {
+            validate_header(name, value)?;
+        }
+        if self.variables.contains_key("") {
+            return Err(BuildError::InvalidField(
+                "variable",
+                "keys may not be empty".into(),
+            ));
+        }
 
         Ok(Email {
             from: self.from.clone(),
             to: self.recipients.join(","),
-            subject: self.subject.unwrap_or_else(|| "no subject".into()),
+            subject: self.subject,
             body: self.body,
+            recipient_variables: self.recipient_variables,
+            deliver_at: self.deliver_at,
+            tags: self.tags,
+            tracking: self.tracking,
+            track_opens: self.track_opens,
+            track_clicks: self.track_clicks,
+            headers: self.headers,
+            variables: self.variables,
+            test_mode: self.test_mode,
+            options: self.options,
+            template: self.template,
+            template_version: self.template_version,
+            attachments: self.attachments,
+            idempotency_key: self.idempotency_key,
+            source: self.source,
+            form: FormCache::default(),
         })
     }
 }
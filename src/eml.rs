@@ -0,0 +1,313 @@
+//! Rendering an email as an RFC 5322 message, see `Email::to_eml`.
+use chrono::Utc;
+
+use crate::{
+    email::{bare_address, split_recipients},
+    BuildError, Email,
+};
+
+/// Neither base64 nor quoted-printable produce `=_`, so the boundaries never occur
+/// in the encoded parts.
+const MIXED_BOUNDARY: &str = "=_mailgun46_mixed";
+const ALTERNATIVE_BOUNDARY: &str = "=_mailgun46_alternative";
+
+/// Headers holding addresses, where only display names may be encoded.
+const ADDRESS_HEADERS: &[&str] = &["from", "to", "cc", "bcc", "reply-to", "sender"];
+
+/// RFC 5322 recommends lines of at most 78 characters.
+const LINE_LENGTH: usize = 76;
+
+impl Email {
+    /// Renders the email as an RFC 5322 message, which mail clients open as an
+    /// `.eml` file, for previewing or snapshot testing it without sending it.
+    ///
+    /// The bodies become a `multipart/alternative` part and attachments are added in
+    /// a `multipart/mixed` message. `Date` is the scheduled delivery time, or now.
+    /// Only what the email sets is rendered: the from address left to the Mailer is
+    /// missing, as is a template body, and sending options, tags and variables are
+    /// left out. Fails for streamed attachments, which can only be read when sending.
+    /// An email sent with `Mailer::send_mime` is its rendered message, one sent with
+    /// `Mailer::resend` fails as only Mailgun has the message.
+    ///
+    /// ```
+    /// use mailgun46::EmailBuilder;
+    /// # fn example() -> Result<(), mailgun46::BuildError> {
+    /// let email = EmailBuilder::default()
+    ///     .from("shop@example.com")
+    ///     .to("customer@example.com")
+    ///     .subject("Your receipt")
+    ///     .text_body("Thanks for your order")
+    ///     .build()?;
+    /// std::fs::write("receipt.eml", email.to_eml()?).expect("Writing preview");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_eml(&self) -> Result<String, BuildError> {
+        if let Some(mime) = self.mime() {
+            return Ok(String::from_utf8_lossy(mime).into_owned());
+        }
+        if let Some(url) = self.stored_url() {
+            return Err(BuildError::InvalidField(
+                "message",
+                format!("stored by Mailgun at `{}`", url),
+            ));
+        }
+        let mut eml = String::new();
+        header(
+            &mut eml,
+            "Date",
+            &self.deliver_at.unwrap_or_else(Utc::now).to_rfc2822(),
+        );
+        if let Some(from) = &self.from {
+            header(&mut eml, "From", from);
+        }
+        header(&mut eml, "To", &self.to);
+        if let Some(subject) = &self.subject {
+            header(&mut eml, "Subject", subject);
+        }
+        for (name, value) in &self.headers {
+            header(&mut eml, name, value);
+        }
+        header(&mut eml, "MIME-Version", "1.0");
+
+        let body = self.body.as_deref();
+        let mut alternatives = Vec::new();
+        if let Some(text) = body.and_then(|body| body.text.as_deref()) {
+            alternatives.push(("text/plain", text));
+        }
+        if let Some(amp) = body.and_then(|body| body.amp.as_deref()) {
+            alternatives.push(("text/x-amp-html", amp));
+        }
+        if let Some(html) = body.and_then(|body| body.html.as_deref()) {
+            alternatives.push(("text/html", html));
+        }
+        if alternatives.is_empty() {
+            alternatives.push(("text/plain", ""));
+        }
+
+        if self.attachments.is_empty() {
+            body_part(&mut eml, &alternatives);
+            return Ok(eml);
+        }
+
+        eml += &format!(
+            "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+            MIXED_BOUNDARY
+        );
+        eml += &format!("--{}\r\n", MIXED_BOUNDARY);
+        body_part(&mut eml, &alternatives);
+        for attachment in &self.attachments {
+            let data = attachment.data().ok_or_else(|| {
+                BuildError::InvalidField(
+                    "attachment",
+                    format!("stream for `{}` can not be rendered", attachment.name),
+                )
+            })?;
+            eml += &format!("--{}\r\n", MIXED_BOUNDARY);
+            eml += &format!(
+                "Content-Type: {}\r\nContent-Disposition: attachment; {}\r\n",
+                attachment.content_type,
+                filename_param(&attachment.name)
+            );
+            eml += "Content-Transfer-Encoding: base64\r\n\r\n";
+            eml += &base64_lines(data);
+        }
+        eml += &format!("--{}--\r\n", MIXED_BOUNDARY);
+        Ok(eml)
+    }
+}
+
+fn header(eml: &mut String, name: &str, value: &str) {
+    let value = if ADDRESS_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+        split_recipients(value)
+            .iter()
+            .map(|recipient| address(recipient.trim()))
+            .collect::<Vec<_>>()
+            .join(",\r\n ")
+    } else {
+        encoded_words(value)
+    };
+    *eml += &format!("{}: {}\r\n", name, value);
+}
+
+/// Encodes the display name of a non-ASCII address. The address itself is kept,
+/// as it can only be sent to servers supporting SMTPUTF8 anyway.
+fn address(recipient: &str) -> String {
+    let email = bare_address(recipient);
+    match recipient.strip_suffix('>').and_then(|r| r.rsplit_once('<')) {
+        Some((name, _)) if !name.is_ascii() => {
+            let name = name.trim().trim_matches('"');
+            format!("{} <{}>", encoded_words(name), email)
+        }
+        _ => recipient.to_string(),
+    }
+}
+
+/// RFC 2047 encoded words for non-ASCII text, each short enough for a line of its own.
+fn encoded_words(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if i + c.len_utf8() - start > 45 {
+            words.push(&text[start..i]);
+            start = i;
+        }
+    }
+    words.push(&text[start..]);
+    words
+        .iter()
+        .map(|word| format!("=?utf-8?b?{}?=", base64::encode(word)))
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+
+/// A single body, or the bodies as alternatives for the client to choose from.
+fn body_part(eml: &mut String, alternatives: &[(&str, &str)]) {
+    if let [(content_type, text)] = alternatives {
+        text_part(eml, content_type, text);
+        return;
+    }
+    *eml += &format!(
+        "Content-Type: multipart/alternative; boundary=\"{}\"\r\n\r\n",
+        ALTERNATIVE_BOUNDARY
+    );
+    for (content_type, text) in alternatives {
+        *eml += &format!("--{}\r\n", ALTERNATIVE_BOUNDARY);
+        text_part(eml, content_type, text);
+    }
+    *eml += &format!("--{}--\r\n", ALTERNATIVE_BOUNDARY);
+}
+
+fn text_part(eml: &mut String, content_type: &str, text: &str) {
+    *eml += &format!(
+        "Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\n",
+        content_type
+    );
+    *eml += &quoted_printable(text);
+    *eml += "\r\n";
+}
+
+/// Quoted-printable keeps ASCII text readable, line breaks become CRLF and long
+/// lines are wrapped with soft line breaks.
+fn quoted_printable(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            encoded += "\r\n";
+        }
+        let line = line.strip_suffix('\r').unwrap_or(line).as_bytes();
+        let mut length = 0;
+        for (j, &byte) in line.iter().enumerate() {
+            let literal = match byte {
+                // Trailing whitespace is dropped in transit.
+                b' ' | b'\t' => j + 1 < line.len(),
+                b'=' => false,
+                b'!'..=b'~' => true,
+                _ => false,
+            };
+            let width = if literal { 1 } else { 3 };
+            if length + width > LINE_LENGTH - 1 {
+                encoded += "=\r\n";
+                length = 0;
+            }
+            if literal {
+                encoded.push(byte as char);
+            } else {
+                encoded += &format!("={:02X}", byte);
+            }
+            length += width;
+        }
+    }
+    encoded
+}
+
+fn base64_lines(data: &[u8]) -> String {
+    let encoded = base64::encode(data);
+    let mut lines = String::with_capacity(encoded.len() + encoded.len() / LINE_LENGTH * 2 + 2);
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        lines += std::str::from_utf8(line).expect("base64 is ASCII");
+        lines += "\r\n";
+    }
+    lines
+}
+
+/// Plain file names are quoted, others are percent encoded as RFC 2231 describes.
+fn filename_param(name: &str) -> String {
+    let plain = name
+        .bytes()
+        .all(|byte| matches!(byte, b' '..=b'~') && byte != b'"' && byte != b'\\');
+    if plain {
+        return format!("filename=\"{}\"", name);
+    }
+    let mut encoded = String::from("filename*=utf-8''");
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BuildError, EmailBuilder};
+
+    #[test]
+    fn to_eml() {
+        let email = EmailBuilder::default()
+            .from("\"Åsa Öberg\" <asa@example.com>")
+            .to("a@example.com, \"B\" <b@example.com>")
+            .subject("Kvitto för din beställning")
+            .text_body(format!("Tack! {}\nPris = 100 kr ", "x".repeat(80)))
+            .html_body("<p>Tack!</p>")
+            .attachment("kvitto.pdf", "application/pdf", &b"%PDF-1.4"[..])
+            .attachment("bild ö.png", "image/png", &b"\x89PNG"[..])
+            .build()
+            .expect("Building email");
+
+        let eml = email.to_eml().expect("Rendering email");
+        let (headers, body) = eml.split_once("\r\n\r\n").expect("Header section");
+        assert!(headers.starts_with("Date: "));
+        assert!(headers.contains("\r\nFrom: =?utf-8?b?w4VzYSDDlmJlcmc=?= <asa@example.com>\r\n"));
+        assert!(headers.contains("\r\nTo: a@example.com,\r\n \"B\" <b@example.com>\r\n"));
+        assert!(headers
+            .contains("\r\nSubject: =?utf-8?b?S3ZpdHRvIGbDtnIgZGluIGJlc3TDpGxsbmluZw==?=\r\n"));
+        assert!(headers.ends_with(
+            "MIME-Version: 1.0\r\nContent-Type: multipart/mixed; boundary=\"=_mailgun46_mixed\""
+        ));
+
+        assert!(body.starts_with(
+            "--=_mailgun46_mixed\r\nContent-Type: multipart/alternative; boundary=\"=_mailgun46_alternative\"\r\n\r\n\
+             --=_mailgun46_alternative\r\nContent-Type: text/plain; charset=utf-8\r\n"
+        ));
+        assert!(body.contains(&format!(
+            "\r\n\r\nTack! {}=\r\n{}\r\nPris =3D 100 kr=20\r\n--=_mailgun46_alternative\r\n",
+            "x".repeat(69),
+            "x".repeat(11)
+        )));
+        assert!(body.contains("charset=utf-8\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\n<p>Tack!</p>\r\n--=_mailgun46_alternative--\r\n"));
+        assert!(body.contains(
+            "Content-Disposition: attachment; filename=\"kvitto.pdf\"\r\nContent-Transfer-Encoding: base64\r\n\r\nJVBERi0xLjQ=\r\n"
+        ));
+        assert!(body.contains("filename*=utf-8''bild%20%C3%B6.png\r\n"));
+        assert!(body.ends_with("iVBORw==\r\n--=_mailgun46_mixed--\r\n"));
+        assert!(eml
+            .split("\r\n")
+            .all(|line| line.len() <= 78 && !line.contains('\n')));
+
+        let streamed = EmailBuilder::default()
+            .to("a@example.com")
+            .attachment_stream("big.csv", "text/csv", &b"a,b"[..])
+            .build()
+            .expect("Building email");
+        assert!(matches!(
+            streamed.to_eml(),
+            Err(BuildError::InvalidField("attachment", _))
+        ));
+    }
+}
@@ -1,9 +1,12 @@
-use std::fmt;
+use std::{convert::Infallible, fmt, time::Duration};
 
 /// Error occuring when building a Mailer instance.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SetupError {
     EnvVarMissing(&'static str),
+    /// An environment variable, or the `.env` file, could not be parsed. Also reports
+    /// missing variables read with a custom prefix, see `MailerBuilder::from_env_with_prefix`.
+    InvalidEnvVar(String, String),
     InvalidVar(&'static str, String),
     Build(String),
 }
@@ -12,6 +15,7 @@ impl fmt::Display for SetupError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::EnvVarMissing(var) => write!(f, "Missing env variable `{}`", var),
+            Self::InvalidEnvVar(var, msg) => write!(f, "Invalid value for `{}`: {}", var, msg),
             Self::InvalidVar(var, msg) => write!(f, "Invalid value for `{}`: {}", var, msg),
             Self::Build(msg) => write!(f, "Creating Http Client: {}", msg),
         }
@@ -26,17 +30,71 @@ impl std::error::Error for SetupError {}
 pub enum BuildError {
     /// A required field missing.
     MissingField(&'static str),
+
+    /// A field has a value Mailgun would reject.
+    InvalidField(&'static str, String),
+
+    /// Recipient variables are used but missing for the given recipient.
+    MissingRecipientVariables(String),
+
+    /// An address could not be parsed.
+    InvalidAddress(AddressError),
+
+    /// A recipient added through `Recipients` could not be parsed.
+    InvalidRecipient(AddressError),
 }
 impl fmt::Display for BuildError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::MissingField(field) => write!(f, "Missing field `{}`", field),
+            Self::InvalidField(field, msg) => write!(f, "Invalid value for `{}`: {}", field, msg),
+            Self::MissingRecipientVariables(recipient) => {
+                write!(f, "Missing recipient variables for `{}`", recipient)
+            }
+            Self::InvalidAddress(err) => err.fmt(f),
+            Self::InvalidRecipient(err) => write!(f, "Invalid recipient: {}", err),
         }
     }
 }
 
 impl std::error::Error for BuildError {}
 
+impl From<AddressError> for BuildError {
+    fn from(err: AddressError) -> Self {
+        Self::InvalidAddress(err)
+    }
+}
+
+impl From<Infallible> for BuildError {
+    fn from(err: Infallible) -> Self {
+        match err {}
+    }
+}
+
+/// Error occuring when parsing an EmailAddress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressError {
+    pub address: String,
+    pub reason: &'static str,
+}
+
+impl AddressError {
+    pub(crate) fn new(address: impl Into<String>, reason: &'static str) -> Self {
+        Self {
+            address: address.into(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid address `{}`: {}", self.address, self.reason)
+    }
+}
+
+impl std::error::Error for AddressError {}
+
 /// Errors occuring when building an Email from EmailBuilder::build
 /// Network errors and unexpected replies from Mailgun
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -44,17 +102,243 @@ pub enum SendError {
     /// Http protocol error
     Http(String),
 
-    /// Unexpected reply from Mailgun.
+    /// A successful reply whose body could not be read as expected.
+    Decode(String),
+
+    /// The email or request could not be encoded, it was never sent.
+    Encode(String),
+
+    /// Unexpected reply from Mailgun, not covered by the variants below.
     Non200Reply {
         status: reqwest::StatusCode,
         body: String,
     },
+
+    /// 401, the token or domain is wrong.
+    Unauthorized { body: String },
+
+    /// 429, sending too fast. `retry_after` is taken from the `Retry-After` header.
+    RateLimited {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+
+    /// 413, the message is larger than Mailgun accepts.
+    PayloadTooLarge { body: String },
+
+    /// 400 caused by a recipient address Mailgun would not accept.
+    InvalidRecipient { message: String, body: String },
+
+    /// 3xx, a redirect that was not followed, such as from a misconfigured base url.
+    Redirect {
+        status: reqwest::StatusCode,
+        location: Option<String>,
+        body: String,
+    },
+
+    /// 5xx, a failure on Mailgun's side. `retry_after` is taken from the `Retry-After`
+    /// header, which may come with a 503.
+    ServerError {
+        status: reqwest::StatusCode,
+        retry_after: Option<Duration>,
+        body: String,
+    },
+
+    /// The send or a request took longer than its timeout.
+    Timeout,
+
+    /// The message is larger than the limit of the Mailer, it was never sent.
+    TooLarge { size: usize, limit: usize },
+
+    /// The email conflicts with the settings of the Mailer, it was never sent.
+    InvalidEmail(BuildError),
+
+    /// The recipient is on a suppression list, see `MailerBuilder::with_suppression_guard`.
+    /// The email was never sent.
+    Suppressed(String),
+
+    /// The Mailer's `RecipientPolicy` does not allow sending to this address, the email
+    /// was never sent.
+    BlockedByPolicy(String),
+
+    /// Writing the email failed, for transports storing emails instead of sending them.
+    Io(String),
+
+    /// A storage url that is not on Mailgun or the Mailer's base url, such as one from a
+    /// forged webhook. It was never requested, so the token was not sent to it.
+    UntrustedUrl(String),
+}
+
+/// Whether a reply status counts as success, any 2xx unless strict.
+pub(crate) fn is_success(status: reqwest::StatusCode, strict: bool) -> bool {
+    if strict {
+        status == reqwest::StatusCode::OK
+    } else {
+        status.is_success()
+    }
+}
+
+/// The error payload Mailgun replies with.
+#[derive(serde::Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+impl SendError {
+    /// Classifies an error reply from Mailgun.
+    pub(crate) fn from_reply(
+        status: reqwest::StatusCode,
+        headers: &reqwest::header::HeaderMap,
+        body: String,
+    ) -> Self {
+        use reqwest::StatusCode;
+
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized { body },
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited {
+                retry_after: crate::retry::retry_after(headers),
+                body,
+            },
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge { body },
+            StatusCode::BAD_REQUEST => match parse_message(&body) {
+                Some(message) if is_recipient_message(&message) => {
+                    Self::InvalidRecipient { message, body }
+                }
+                _ => Self::Non200Reply { status, body },
+            },
+            status if status.is_redirection() => Self::Redirect {
+                status,
+                location: headers
+                    .get(reqwest::header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .map(String::from),
+                body,
+            },
+            status if status.is_server_error() => Self::ServerError {
+                status,
+                retry_after: crate::retry::retry_after(headers),
+                body,
+            },
+            status => Self::Non200Reply { status, body },
+        }
+    }
+
+    /// The HTTP status of the reply, None for errors without a reply.
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        use reqwest::StatusCode;
+
+        match self {
+            Self::Http(_)
+            | Self::Decode(_)
+            | Self::Encode(_)
+            | Self::Timeout
+            | Self::TooLarge { .. }
+            | Self::InvalidEmail(_)
+            | Self::Suppressed(_)
+            | Self::BlockedByPolicy(_)
+            | Self::Io(_)
+            | Self::UntrustedUrl(_) => None,
+            Self::Non200Reply { status, .. }
+            | Self::Redirect { status, .. }
+            | Self::ServerError { status, .. } => Some(*status),
+            Self::Unauthorized { .. } => Some(StatusCode::UNAUTHORIZED),
+            Self::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS),
+            Self::PayloadTooLarge { .. } => Some(StatusCode::PAYLOAD_TOO_LARGE),
+            Self::InvalidRecipient { .. } => Some(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    /// Whether sending again later may succeed: rate limits, failures on Mailgun's side,
+    /// timeouts and requests failing without a reply, which are mostly lost connections.
+    /// Other errors fail the same way every time. A `Decode` error is not retried, as
+    /// Mailgun has already accepted the message.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Http(_) | Self::RateLimited { .. } | Self::ServerError { .. } | Self::Timeout => {
+                true
+            }
+            Self::Decode(_)
+            | Self::Encode(_)
+            | Self::Non200Reply { .. }
+            | Self::Redirect { .. }
+            | Self::Unauthorized { .. }
+            | Self::PayloadTooLarge { .. }
+            | Self::InvalidRecipient { .. }
+            | Self::TooLarge { .. }
+            | Self::InvalidEmail(_)
+            | Self::Suppressed(_)
+            | Self::BlockedByPolicy(_)
+            | Self::Io(_)
+            | Self::UntrustedUrl(_) => false,
+        }
+    }
+
+    /// How long Mailgun asked to wait before sending again, from the `Retry-After`
+    /// header of a 429 or 503 reply.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after, .. } | Self::ServerError { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+
+    /// The raw reply body, for logging.
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            Self::Http(_)
+            | Self::Decode(_)
+            | Self::Encode(_)
+            | Self::Timeout
+            | Self::TooLarge { .. }
+            | Self::InvalidEmail(_)
+            | Self::Suppressed(_)
+            | Self::BlockedByPolicy(_)
+            | Self::Io(_)
+            | Self::UntrustedUrl(_) => None,
+            Self::Non200Reply { body, .. }
+            | Self::Unauthorized { body }
+            | Self::RateLimited { body, .. }
+            | Self::PayloadTooLarge { body }
+            | Self::InvalidRecipient { body, .. }
+            | Self::Redirect { body, .. }
+            | Self::ServerError { body, .. } => Some(body),
+        }
+    }
+
+    /// The `message` field of Mailgun's JSON error payload, when present.
+    pub fn message(&self) -> Option<String> {
+        match self {
+            Self::InvalidRecipient { message, .. } => Some(message.clone()),
+            _ => self.body().and_then(parse_message),
+        }
+    }
+}
+
+fn parse_message(body: &str) -> Option<String> {
+    serde_json::from_str::<ErrorBody>(body)
+        .ok()
+        .map(|err| err.message)
+}
+
+/// Parts of Mailgun's messages rejecting a recipient, such as "'to' parameter is not a
+/// valid address" or the sandbox domain's "add the address to authorized recipients".
+const RECIPIENT_MESSAGES: &[&str] = &["is not a valid address", "authorized recipients"];
+
+fn is_recipient_message(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    RECIPIENT_MESSAGES
+        .iter()
+        .any(|rejection| message.contains(rejection))
 }
 
 impl fmt::Display for SendError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::Http(msg) => write!(f, "SendingError http `{}`", msg),
+            Self::Decode(msg) => write!(f, "Reading reply from mailgun: {}", msg),
+            Self::Encode(msg) => write!(f, "Encoding email: {}", msg),
             Self::Non200Reply { status, body } => {
                 write!(
                     f,
@@ -62,6 +346,41 @@ impl fmt::Display for SendError {
                     status, body
                 )
             }
+            Self::Unauthorized { .. } => {
+                write!(f, "Unauthorized by mailgun, check domain and token")
+            }
+            Self::RateLimited { retry_after, .. } => match retry_after {
+                Some(after) => write!(f, "Rate limited by mailgun, retry after {:?}", after),
+                None => write!(f, "Rate limited by mailgun"),
+            },
+            Self::PayloadTooLarge { .. } => write!(f, "Message too large for mailgun"),
+            Self::InvalidRecipient { message, .. } => write!(f, "Invalid recipient: {}", message),
+            Self::Redirect {
+                status, location, ..
+            } => match location {
+                Some(location) => write!(f, "Redirected with `{}` to {}", status, location),
+                None => write!(f, "Redirected with `{}`", status),
+            },
+            Self::ServerError { status, body, .. } => {
+                write!(f, "Mailgun server error: `{}`. Body:\n{}", status, body)
+            }
+            Self::Timeout => write!(f, "Sending timed out"),
+            Self::TooLarge { size, limit } => {
+                write!(
+                    f,
+                    "Message of {} bytes exceeds the limit of {}",
+                    size, limit
+                )
+            }
+            Self::InvalidEmail(err) => err.fmt(f),
+            Self::Suppressed(address) => {
+                write!(f, "Recipient `{}` is on a suppression list", address)
+            }
+            Self::BlockedByPolicy(address) => {
+                write!(f, "Recipient `{}` is not allowed by the Mailer", address)
+            }
+            Self::Io(msg) => write!(f, "Writing email: {}", msg),
+            Self::UntrustedUrl(url) => write!(f, "Not a Mailgun storage url: `{}`", url),
         }
     }
 }
@@ -70,6 +389,15 @@ impl std::error::Error for SendError {}
 
 impl From<reqwest::Error> for SendError {
     fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            return Self::Timeout;
+        }
+        if err.is_decode() {
+            return Self::Decode(err.to_string());
+        }
+        if err.is_builder() {
+            return Self::Encode(err.to_string());
+        }
         Self::Http(err.to_string())
     }
 }
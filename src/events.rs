@@ -0,0 +1,728 @@
+//! Querying the Mailgun Events API, `/v3/<domain>/events`.
+//!
+//! ```
+//! use mailgun46::{Mailer, events::{EventFilter, EventType}};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let filter = EventFilter::default()
+//!     .event(EventType::Delivered)
+//!     .recipient("someone@example.com");
+//! let mut page = mailer.events(&filter).await?;
+//! loop {
+//!     for event in &page.items {
+//!         println!("{:?}", event);
+//!     }
+//!     match page.next_page(&mailer).await? {
+//!         Some(next) => page = next,
+//!         None => break,
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use serde::Deserialize;
+
+use crate::{Mailer, MessageId, Page, SendError};
+
+/// The kinds of events that can be filtered on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Accepted,
+    Delivered,
+    Failed,
+    Opened,
+    Clicked,
+    Complained,
+    Unsubscribed,
+    /// A message stored by a `store()` route.
+    Stored,
+}
+
+impl EventType {
+    /// The name Mailgun uses for the event type.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Accepted => "accepted",
+            Self::Delivered => "delivered",
+            Self::Failed => "failed",
+            Self::Opened => "opened",
+            Self::Clicked => "clicked",
+            Self::Complained => "complained",
+            Self::Unsubscribed => "unsubscribed",
+            Self::Stored => "stored",
+        }
+    }
+}
+
+/// Filters for querying events, all are optional.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct EventFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<EventType>,
+
+    #[serde(rename = "message-id", skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipient: Option<String>,
+
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::datetime::serialize_timestamp"
+    )]
+    begin: Option<DateTime<Utc>>,
+
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::datetime::serialize_timestamp"
+    )]
+    end: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ascending: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u16>,
+}
+
+impl EventFilter {
+    pub fn event(mut self, event: EventType) -> Self {
+        self.event = Some(event);
+        self
+    }
+
+    /// Only events for the message with this id, with or without the surrounding `<>`.
+    pub fn message_id(mut self, id: impl Into<String>) -> Self {
+        let id = id.into();
+        self.message_id = Some(id.trim_start_matches('<').trim_end_matches('>').into());
+        self
+    }
+
+    pub fn recipient(mut self, recipient: impl Into<String>) -> Self {
+        self.recipient = Some(recipient.into());
+        self
+    }
+
+    /// Only events at or after this time.
+    pub fn begin(mut self, time: impl Into<DateTime<Utc>>) -> Self {
+        self.begin = Some(time.into());
+        self
+    }
+
+    /// Only events at or before this time.
+    pub fn end(mut self, time: impl Into<DateTime<Utc>>) -> Self {
+        self.end = Some(time.into());
+        self
+    }
+
+    /// Return events oldest first instead of newest first.
+    pub fn ascending(mut self, ascending: bool) -> Self {
+        self.ascending = Some(if ascending { "yes" } else { "no" });
+        self
+    }
+
+    /// Number of events per page, Mailgun caps this at 300.
+    pub fn limit(mut self, limit: u16) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+/// An event reported by Mailgun. Event types not modelled here are `Other`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    Accepted(EventDetails),
+    Delivered(EventDetails),
+    Failed(EventDetails),
+    Opened(EventDetails),
+    Clicked(EventDetails),
+    Complained(EventDetails),
+    Unsubscribed(EventDetails),
+    Stored(EventDetails),
+    #[serde(other)]
+    Other,
+}
+
+impl Event {
+    pub fn event_type(&self) -> Option<EventType> {
+        match self {
+            Self::Accepted(_) => Some(EventType::Accepted),
+            Self::Delivered(_) => Some(EventType::Delivered),
+            Self::Failed(_) => Some(EventType::Failed),
+            Self::Opened(_) => Some(EventType::Opened),
+            Self::Clicked(_) => Some(EventType::Clicked),
+            Self::Complained(_) => Some(EventType::Complained),
+            Self::Unsubscribed(_) => Some(EventType::Unsubscribed),
+            Self::Stored(_) => Some(EventType::Stored),
+            Self::Other => None,
+        }
+    }
+
+    /// How a failed message bounced, None for other events and for failures Mailgun
+    /// gave no severity or status code for.
+    pub fn bounce_kind(&self) -> Option<BounceKind> {
+        match self {
+            Self::Failed(details) => details.bounce_kind(),
+            _ => None,
+        }
+    }
+
+    pub fn details(&self) -> Option<&EventDetails> {
+        match self {
+            Self::Accepted(details)
+            | Self::Delivered(details)
+            | Self::Failed(details)
+            | Self::Opened(details)
+            | Self::Clicked(details)
+            | Self::Complained(details)
+            | Self::Unsubscribed(details)
+            | Self::Stored(details) => Some(details),
+            Self::Other => None,
+        }
+    }
+}
+
+/// Fields shared by all events, fields only present on some events are optional.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EventDetails {
+    pub id: String,
+
+    #[serde(deserialize_with = "crate::datetime::deserialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(default)]
+    pub recipient: Option<String>,
+
+    #[serde(default)]
+    pub message: Option<EventMessage>,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    #[serde(default)]
+    pub user_variables: BTreeMap<String, serde_json::Value>,
+
+    /// `permanent` or `temporary`, for failed events.
+    #[serde(default)]
+    pub severity: Option<String>,
+
+    /// Why a message failed, such as `bounce`, `espblock` or `suppress-bounce`.
+    #[serde(default)]
+    pub reason: Option<String>,
+
+    /// The reply of the receiving server, for delivered and failed events.
+    #[serde(default)]
+    pub delivery_status: Option<DeliveryStatus>,
+
+    /// The clicked url, for clicked events.
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Where the message is kept, for stored and accepted events.
+    /// Fetch it with `Mailer::stored_message`.
+    #[serde(default)]
+    pub storage: Option<EventStorage>,
+}
+
+impl EventDetails {
+    fn bounce_kind(&self) -> Option<BounceKind> {
+        let status = self.delivery_status.as_ref();
+        let code = status.and_then(|status| status.code);
+        let reason = self.reason.as_deref().unwrap_or_default();
+
+        // Mailgun reports its own suppressions with status codes in the 600s.
+        if reason.starts_with("suppress-") || matches!(code, Some(600..=699)) {
+            return Some(BounceKind::Suppressed);
+        }
+        let message = status
+            .map(|status| format!("{} {}", status.message, status.description))
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if reason == "espblock" || SPAM_BLOCK_WORDS.iter().any(|word| message.contains(word)) {
+            return Some(BounceKind::SpamBlock);
+        }
+        match (self.severity.as_deref(), code) {
+            (Some("permanent"), _) => Some(BounceKind::Hard),
+            (Some("temporary"), _) => Some(BounceKind::Soft),
+            (_, Some(500..=599)) => Some(BounceKind::Hard),
+            (_, Some(400..=499)) => Some(BounceKind::Soft),
+            _ => None,
+        }
+    }
+}
+
+/// Receiving servers mention these when refusing a message as spam.
+const SPAM_BLOCK_WORDS: &[&str] = &["spam", "blocklist", "blacklist", "blocked"];
+
+/// How a failed message bounced, see `Event::bounce_kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BounceKind {
+    /// The address does not exist or refuses mail for good, suppress it.
+    Hard,
+    /// A temporary failure such as a full mailbox, Mailgun already retries these.
+    Soft,
+    /// Mailgun did not send, as the address is on a suppression list.
+    Suppressed,
+    /// The receiving server or Mailgun refused the message as spam, check the content
+    /// and sending reputation rather than the address.
+    SpamBlock,
+}
+
+impl BounceKind {
+    /// Whether sending to the address again later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Soft | Self::SpamBlock)
+    }
+}
+
+/// The SMTP reply of the receiving server.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeliveryStatus {
+    /// The SMTP status code, such as 550.
+    #[serde(default, deserialize_with = "deserialize_code")]
+    pub code: Option<u16>,
+
+    /// The enhanced status code, such as `5.1.1`.
+    #[serde(default)]
+    pub enhanced_code: Option<String>,
+
+    #[serde(default)]
+    pub message: String,
+
+    #[serde(default)]
+    pub description: String,
+
+    /// The server the message was delivered to or refused by.
+    #[serde(default)]
+    pub mx_host: Option<String>,
+
+    #[serde(default)]
+    pub attempt_no: Option<u32>,
+
+    #[serde(default)]
+    pub tls: Option<bool>,
+}
+
+/// Codes come as numbers, or as strings in older events. 0 means no code.
+fn deserialize_code<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Code {
+        Number(u16),
+        Text(String),
+    }
+
+    let code = match Option::<Code>::deserialize(deserializer)? {
+        Some(Code::Number(code)) => Some(code),
+        Some(Code::Text(text)) => text.trim().parse().ok(),
+        None => None,
+    };
+    Ok(code.filter(|code| *code != 0))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct EventStorage {
+    pub url: String,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct EventMessage {
+    pub headers: EventMessageHeaders,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EventMessageHeaders {
+    #[serde(default)]
+    pub message_id: Option<String>,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub subject: Option<String>,
+}
+
+/// A page of events.
+pub type EventPage = Page<Event>;
+
+/// How often `wait_for_delivery` polls the Events API.
+const DELIVERY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The terminal event of a message, along with its details.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryOutcome {
+    Delivered(EventDetails),
+    /// Failed permanently, temporary failures are retried by Mailgun.
+    Failed(EventDetails),
+}
+
+impl Mailer {
+    /// Fetches the first page of events matching the filter.
+    pub async fn events(&self, filter: &EventFilter) -> Result<EventPage, SendError> {
+        self.execute_page(self.get(self.domain_url(&["events"])).query(filter))
+            .await
+    }
+
+    /// All events matching the filter, fetching the following pages as the stream
+    /// is read. The stream ends after the first error.
+    ///
+    /// ```
+    /// use futures_util::StreamExt;
+    /// use mailgun46::{Mailer, events::{EventFilter, EventType}};
+    /// # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+    /// let filter = EventFilter::default().event(EventType::Failed);
+    /// let mut events = Box::pin(mailer.event_stream(&filter));
+    /// while let Some(event) = events.next().await {
+    ///     println!("{:?}", event?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn event_stream(
+        &self,
+        filter: &EventFilter,
+    ) -> impl Stream<Item = Result<Event, SendError>> + '_ {
+        self.execute_stream(self.get(self.domain_url(&["events"])).query(filter))
+    }
+
+    /// Polls the Events API until the message is delivered or has failed permanently.
+    /// Fails with `SendError::Timeout` if neither happened within the timeout.
+    ///
+    /// Events may take a minute to show up, this is meant for tests and critical workflows.
+    pub async fn wait_for_delivery(
+        &self,
+        id: &MessageId,
+        timeout: Duration,
+    ) -> Result<DeliveryOutcome, SendError> {
+        self.poll_delivery(id, timeout, DELIVERY_POLL_INTERVAL)
+            .await
+    }
+
+    async fn poll_delivery(
+        &self,
+        id: &MessageId,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<DeliveryOutcome, SendError> {
+        let filter = EventFilter::default().message_id(id.bare());
+        let poll = async {
+            loop {
+                if let Some(outcome) = self.delivery_outcome(&filter).await? {
+                    return Ok(outcome);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        };
+        tokio::time::timeout(timeout, poll)
+            .await
+            .unwrap_or(Err(SendError::Timeout))
+    }
+
+    async fn delivery_outcome(
+        &self,
+        filter: &EventFilter,
+    ) -> Result<Option<DeliveryOutcome>, SendError> {
+        let mut page = self.events(filter).await?;
+        loop {
+            for event in page.items.drain(..) {
+                match event {
+                    Event::Delivered(details) => {
+                        return Ok(Some(DeliveryOutcome::Delivered(details)))
+                    }
+                    Event::Failed(details) if details.severity.as_deref() != Some("temporary") => {
+                        return Ok(Some(DeliveryOutcome::Failed(details)))
+                    }
+                    _ => {}
+                }
+            }
+            match page.next_page(self).await? {
+                Some(next) => page = next,
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn deserialize_events() {
+        let json = r#"[
+            {
+                "event": "delivered",
+                "id": "W3X4JOhFT-OZidZGKKr9iA",
+                "timestamp": 1614172276.5,
+                "recipient": "someone@example.com",
+                "tags": ["newsletter"],
+                "user-variables": {"order": 42},
+                "message": {"headers": {"message-id": "20210224131116.1.E5C867B3818DC87B@fakedomain"}}
+            },
+            {
+                "event": "failed",
+                "id": "czsjqFATSlC3QtAK-C80nw",
+                "timestamp": 1614172277,
+                "severity": "permanent",
+                "reason": "bounce",
+                "delivery-status": {
+                    "code": 550,
+                    "enhanced-code": "5.1.1",
+                    "message": "5.1.1 The email account that you tried to reach does not exist",
+                    "description": "",
+                    "mx-host": "gmail-smtp-in.l.google.com",
+                    "attempt-no": 1,
+                    "tls": true
+                }
+            },
+            {"event": "list_member_uploaded", "id": "x", "timestamp": 1614172278},
+            {
+                "event": "stored",
+                "id": "y",
+                "timestamp": 1614172279,
+                "storage": {"url": "https://storage.eu.mailgun.net/v3/domains/fakedomain/messages/AgEF", "key": "AgEF"}
+            }
+        ]"#;
+
+        let events: Vec<Event> = serde_json::from_str(json).expect("Deserializing events");
+
+        assert_eq!(events[0].event_type(), Some(EventType::Delivered));
+        let details = events[0].details().unwrap();
+        assert_eq!(details.recipient.as_deref(), Some("someone@example.com"));
+        assert_eq!(details.timestamp.timestamp_millis(), 1614172276500);
+        assert_eq!(details.user_variables["order"], 42);
+        assert_eq!(
+            details
+                .message
+                .as_ref()
+                .unwrap()
+                .headers
+                .message_id
+                .as_deref(),
+            Some("20210224131116.1.E5C867B3818DC87B@fakedomain")
+        );
+
+        let failed = events[1].details().unwrap();
+        assert_eq!(failed.severity.as_deref(), Some("permanent"));
+        let status = failed.delivery_status.as_ref().unwrap();
+        assert_eq!(status.code, Some(550));
+        assert_eq!(
+            status.mx_host.as_deref(),
+            Some("gmail-smtp-in.l.google.com")
+        );
+        assert_eq!(events[1].bounce_kind(), Some(BounceKind::Hard));
+        assert_eq!(events[0].bounce_kind(), None);
+        assert_eq!(events[2], Event::Other);
+        let stored = events[3].details().unwrap();
+        assert_eq!(stored.storage.as_ref().unwrap().key, "AgEF");
+    }
+
+    #[test]
+    fn classifies_bounces() {
+        let kind = |json: serde_json::Value| {
+            serde_json::from_value::<Event>(json)
+                .expect("Deserializing event")
+                .bounce_kind()
+        };
+        let failed = serde_json::json!({"event": "failed", "id": "1", "timestamp": 1614172277});
+        let with = |fields: serde_json::Value| {
+            let mut event = failed.clone();
+            event
+                .as_object_mut()
+                .unwrap()
+                .extend(fields.as_object().unwrap().clone());
+            event
+        };
+
+        assert_eq!(kind(failed.clone()), None);
+        assert_eq!(
+            kind(with(serde_json::json!({
+                "severity": "permanent",
+                "reason": "suppress-bounce",
+                "delivery-status": {"code": 605, "message": "Not delivering to previously bounced address"}
+            }))),
+            Some(BounceKind::Suppressed)
+        );
+        assert_eq!(
+            kind(with(serde_json::json!({
+                "severity": "permanent",
+                "reason": "generic",
+                "delivery-status": {"code": "554", "message": "5.7.1 Message rejected as spam"}
+            }))),
+            Some(BounceKind::SpamBlock)
+        );
+        assert_eq!(
+            kind(with(serde_json::json!({
+                "severity": "temporary",
+                "delivery-status": {"code": 452, "message": "4.2.2 Mailbox full"}
+            }))),
+            Some(BounceKind::Soft)
+        );
+        assert_eq!(
+            kind(with(serde_json::json!({"delivery-status": {"code": 550}}))),
+            Some(BounceKind::Hard)
+        );
+        assert!(BounceKind::Soft.is_retryable());
+        assert!(!BounceKind::Hard.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn follows_paging() {
+        let server = MockServer::start().await;
+        let next = format!("{}/v3/fakedomain/events/page2", server.uri());
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events"))
+            .and(matchers::query_param("event", "delivered"))
+            .and(matchers::query_param("message-id", "abc@fakedomain"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [{"event": "delivered", "id": "1", "timestamp": 1614172276}],
+                "paging": {"next": next}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events/page2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [],
+                "paging": {"next": next}
+            })))
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let filter = EventFilter::default()
+            .event(EventType::Delivered)
+            .message_id("<abc@fakedomain>");
+
+        let page = mailer.events(&filter).await.expect("First page");
+        assert_eq!(page.items.len(), 1);
+
+        let page = page.next_page(&mailer).await.expect("Second page").unwrap();
+        assert!(page.items.is_empty());
+        assert!(page.next_page(&mailer).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn streams_events() {
+        use futures_util::StreamExt;
+
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events"))
+            .and(matchers::query_param("event", "failed"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [
+                    {"event": "failed", "id": "1", "timestamp": 1614172276},
+                    {"event": "failed", "id": "2", "timestamp": 1614172277}
+                ],
+                "paging": {"next": format!("{}/v3/fakedomain/events/page2", server.uri())}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events/page2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [{"event": "failed", "id": "3", "timestamp": 1614172278}],
+                "paging": {"next": format!("{}/v3/fakedomain/events/page3", server.uri())}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events/page3"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let filter = EventFilter::default().event(EventType::Failed);
+        let events: Vec<_> = mailer.event_stream(&filter).collect().await;
+
+        assert_eq!(events.len(), 4);
+        let ids: Vec<_> = events[..3]
+            .iter()
+            .map(|event| event.as_ref().unwrap().details().unwrap().id.as_str())
+            .collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+        assert!(matches!(events[3], Err(SendError::ServerError { .. })));
+    }
+
+    #[tokio::test]
+    async fn waits_for_delivery() {
+        let server = MockServer::start().await;
+        let next = format!("{}/v3/fakedomain/events/page2", server.uri());
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events"))
+            .and(matchers::query_param("message-id", "abc@fakedomain"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [{"event": "accepted", "id": "1", "timestamp": 1614172276}],
+                "paging": {"next": next}
+            })))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events"))
+            .and(matchers::query_param("message-id", "abc@fakedomain"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [
+                    {"event": "accepted", "id": "1", "timestamp": 1614172276},
+                    {"event": "failed", "id": "2", "timestamp": 1614172277, "severity": "temporary"},
+                    {"event": "failed", "id": "3", "timestamp": 1614172278, "severity": "permanent"}
+                ],
+                "paging": {"next": next}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events"))
+            .and(matchers::query_param("message-id", "other@fakedomain"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [],
+                "paging": {"next": next}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/events/page2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [],
+                "paging": {"next": next}
+            })))
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let id: MessageId = "<abc@fakedomain>".parse().unwrap();
+
+        let outcome = mailer
+            .poll_delivery(&id, Duration::from_secs(5), Duration::from_millis(10))
+            .await
+            .expect("Polling delivery");
+        assert!(matches!(outcome, DeliveryOutcome::Failed(details) if details.id == "3"));
+
+        let other: MessageId = "<other@fakedomain>".parse().unwrap();
+        let result = mailer
+            .poll_delivery(&other, Duration::from_millis(50), Duration::from_millis(10))
+            .await;
+        assert_eq!(result, Err(SendError::Timeout));
+    }
+}
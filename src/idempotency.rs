@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::OnceCell;
+
+use crate::SendError;
+
+/// Header carrying the idempotency key, so duplicates can be told apart downstream too.
+pub(crate) const IDEMPOTENCY_HEADER: &str = "X-Idempotency-Key";
+
+/// Remembers the outcome per idempotency key for a while. Sends with the same key
+/// wait for one in flight and then reuse its receipt, failed sends are not remembered.
+#[derive(Debug)]
+pub(crate) struct IdempotencyCache<T> {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry<T>>>,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    created: Instant,
+    cell: Arc<OnceCell<T>>,
+}
+
+impl<T: Clone> IdempotencyCache<T> {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Runs `send` unless the key was sent successfully within the ttl.
+    pub(crate) async fn send_once<F, Fut>(&self, key: &str, send: F) -> Result<T, SendError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, SendError>>,
+    {
+        let cell = self.cell(key);
+        cell.get_or_try_init(send).await.cloned()
+    }
+
+    fn cell(&self, key: &str) -> Arc<OnceCell<T>> {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let now = Instant::now();
+        entries.retain(|_, entry| now.duration_since(entry.created) < self.ttl);
+        entries
+            .entry(key.to_string())
+            .or_insert_with(|| Entry {
+                created: now,
+                cell: Arc::default(),
+            })
+            .cell
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageId;
+
+    #[tokio::test]
+    async fn sends_once_per_key() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60));
+        let send = |id: &'static str| move || async move { Ok(MessageId(id.into())) };
+
+        assert_eq!(
+            cache.send_once("a", send("1")).await,
+            Ok(MessageId("1".into()))
+        );
+        assert_eq!(
+            cache.send_once("a", send("2")).await,
+            Ok(MessageId("1".into()))
+        );
+        assert_eq!(
+            cache.send_once("b", send("3")).await,
+            Ok(MessageId("3".into()))
+        );
+
+        let failed = cache
+            .send_once("c", || async { Err(SendError::Http("down".into())) })
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(
+            cache.send_once("c", send("4")).await,
+            Ok(MessageId("4".into()))
+        );
+
+        let expiring = IdempotencyCache::new(Duration::ZERO);
+        expiring.send_once("a", send("1")).await.unwrap();
+        assert_eq!(
+            expiring.send_once("a", send("2")).await,
+            Ok(MessageId("2".into()))
+        );
+    }
+}
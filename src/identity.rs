@@ -0,0 +1,154 @@
+use std::{fmt, sync::Arc};
+
+use crate::{Email, EmailAddress, Mailer, MessageId, SendError};
+
+/// A Mailer sending from another default address, for subsystems sharing one Mailer
+/// and its connection pool. Emails with a from address of their own keep it.
+///
+/// ```
+/// use std::sync::Arc;
+/// use mailgun46::{EmailBuilder, Mailer};
+/// # async fn example() -> Result<(), Box<dyn std::error::Error + 'static>> {
+/// let mailer = Arc::new(
+///     Mailer::builder("example.com", "token")
+///         .identity("billing", "Billing <billing@example.com>")
+///         .build()?,
+/// );
+/// let billing = mailer.identity("billing").expect("Registered on build");
+///
+/// let email = EmailBuilder::default()
+///     .to("customer@example.com")
+///     .subject("Your invoice")
+///     .build()?;
+/// billing.send(email).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct FromIdentity {
+    mailer: Arc<Mailer>,
+    from: String,
+}
+
+impl FromIdentity {
+    pub(crate) fn new(mailer: Arc<Mailer>, from: String) -> Self {
+        Self { mailer, from }
+    }
+
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+
+    pub fn mailer(&self) -> &Arc<Mailer> {
+        &self.mailer
+    }
+
+    pub async fn send(&self, mut email: Email) -> Result<MessageId, SendError> {
+        if email.from.is_none() {
+            email.from = Some(self.from.clone());
+        }
+        self.mailer.send(email).await
+    }
+}
+
+impl fmt::Debug for FromIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FromIdentity")
+            .field("domain", &self.mailer.domain())
+            .field("from", &self.from)
+            .finish()
+    }
+}
+
+impl Mailer {
+    /// The identity registered with `MailerBuilder::identity` under this name.
+    pub fn identity(self: &Arc<Self>, name: &str) -> Option<FromIdentity> {
+        let from = self.identities.get(name)?;
+        Some(FromIdentity::new(self.clone(), from.clone()))
+    }
+
+    /// A handle sending from the given address by default, validated like a recipient.
+    pub fn with_default_from<A>(self: &Arc<Self>, from: A) -> Result<FromIdentity, SendError>
+    where
+        A: TryInto<EmailAddress>,
+        A::Error: Into<crate::BuildError>,
+    {
+        let from = from
+            .try_into()
+            .map_err(|err| SendError::InvalidEmail(err.into()))?;
+        Ok(FromIdentity::new(self.clone(), from.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn sends_from_identity() {
+        let server = MockServer::start().await;
+        for from in [
+            "billing%40fakedomain",
+            "%22Support%22+%3Csupport%40fakedomain%3E",
+        ] {
+            Mock::given(matchers::method("POST"))
+                .and(matchers::path("/v3/fakedomain/messages"))
+                .and(matchers::body_string_contains(format!("from={}", from)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(serde_json::json!({"id": "<1@fakedomain>"})),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("from=cto%40fakedomain"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<2@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Arc::new(
+            Mailer::builder("fakedomain", "tomatotoken")
+                .base_url(server.uri())
+                .identity("billing", "billing@fakedomain")
+                .build()
+                .expect("Creating Mailer"),
+        );
+        let email = || EmailBuilder::default().to("someone").build().unwrap();
+
+        let billing = mailer.identity("billing").expect("Identity registered");
+        assert_eq!(billing.from(), "billing@fakedomain");
+        billing.send(email()).await.expect("Sending as billing");
+        assert!(mailer.identity("sales").is_none());
+
+        let support = mailer
+            .with_default_from("Support <support@fakedomain>")
+            .expect("Valid from");
+        support.send(email()).await.expect("Sending as support");
+        support
+            .send(
+                EmailBuilder::default()
+                    .from("cto@fakedomain")
+                    .to("someone")
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .expect("Keeping the from of the email");
+
+        assert!(mailer.with_default_from("not an address").is_err());
+        let err = Mailer::builder("fakedomain", "tomatotoken")
+            .identity("broken", "not an address")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, crate::SetupError::InvalidVar("identity", _)));
+    }
+}
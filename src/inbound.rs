@@ -0,0 +1,309 @@
+//! Fetching inbound messages kept by `store()` routes.
+//!
+//! The storage url comes from the `storage` of a stored event, or from the
+//! `message-url` of a store notification. As the token is sent along, only urls on
+//! Mailgun's storage hosts or the Mailer's base url are requested, others fail with
+//! `SendError::UntrustedUrl`.
+//!
+//! ```
+//! use mailgun46::{Mailer, events::{EventFilter, EventType}};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let page = mailer.events(&EventFilter::default().event(EventType::Stored)).await?;
+//! for storage in page.items.iter().filter_map(|e| e.details()?.storage.as_ref()) {
+//!     let message = mailer.stored_message(&storage.url).await?;
+//!     println!("{}: {:?}", message.sender, message.stripped_text);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::{email::Source, BuildError, EmailBuilder, Mailer, MessageId, SendError};
+
+/// An inbound message as parsed by Mailgun.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StoredMessage {
+    /// The envelope recipients, comma separated.
+    #[serde(default)]
+    pub recipients: String,
+
+    /// The envelope sender.
+    #[serde(default)]
+    pub sender: String,
+
+    #[serde(default)]
+    pub from: String,
+
+    #[serde(default)]
+    pub subject: String,
+
+    #[serde(default)]
+    pub body_plain: Option<String>,
+
+    #[serde(default)]
+    pub body_html: Option<String>,
+
+    /// The text without quoted parts and signature, handy for replies by email.
+    #[serde(default)]
+    pub stripped_text: Option<String>,
+
+    #[serde(default)]
+    pub stripped_html: Option<String>,
+
+    #[serde(default)]
+    pub stripped_signature: Option<String>,
+
+    /// All MIME headers in order, as name and value.
+    #[serde(default)]
+    pub message_headers: Vec<(String, String)>,
+
+    #[serde(default)]
+    pub attachments: Vec<StoredAttachment>,
+}
+
+impl StoredMessage {
+    /// The first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.message_headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StoredAttachment {
+    /// Download with `Mailer::stored_attachment`.
+    pub url: String,
+    pub content_type: String,
+    pub name: String,
+    pub size: u64,
+}
+
+impl Mailer {
+    /// Fetches a stored message by its storage url.
+    /// Mailgun keeps stored messages for 3 days.
+    pub async fn stored_message(&self, url: &str) -> Result<StoredMessage, SendError> {
+        self.execute(self.get(self.storage_url(url)?)).await
+    }
+
+    /// Downloads the contents of an attachment of a stored message.
+    pub async fn stored_attachment(
+        &self,
+        attachment: &StoredAttachment,
+    ) -> Result<Vec<u8>, SendError> {
+        let res = self.get(self.storage_url(&attachment.url)?).send().await?;
+        if !crate::error::is_success(res.status(), self.strict_status) {
+            return self.read_reply(res).await;
+        }
+        Ok(res.bytes().await?.to_vec())
+    }
+
+    /// Sends a stored message again to other recipients, such as a customer who says
+    /// they never got it. The storage url is found in `EventDetails::storage` of
+    /// accepted and stored events. Goes through the transport like `Email::send`, with
+    /// the Mailer's options, recipient policy, middleware and observers applied.
+    pub async fn resend<I>(&self, url: &str, to: I) -> Result<MessageId, SendError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let to = to
+            .into_iter()
+            .map(|recipient| recipient.as_ref().trim().to_string())
+            .filter(|recipient| !recipient.is_empty())
+            .collect::<Vec<_>>()
+            .join(",");
+        if to.is_empty() {
+            return Err(SendError::InvalidEmail(BuildError::MissingField("to")));
+        }
+        let mut email = EmailBuilder::default()
+            .to(to)
+            .build()
+            .map_err(SendError::InvalidEmail)?;
+        email.source = Source::Stored(self.storage_url(url)?.into());
+        self.send(email).await
+    }
+}
+
+/// Hosts Mailgun serves stored messages from, such as `storage-us-east4.api.mailgun.net`.
+const STORAGE_DOMAINS: &[&str] = &[".mailgun.net", ".mailgun.org"];
+
+impl Mailer {
+    /// Parses a storage url, which may come from an unverified payload, refusing any
+    /// that would send the token elsewhere than Mailgun.
+    pub(crate) fn storage_url(&self, url: &str) -> Result<reqwest::Url, SendError> {
+        let untrusted = || SendError::UntrustedUrl(url.to_string());
+        let parsed: reqwest::Url = url.parse().map_err(|_| untrusted())?;
+        let on_base_url = parsed.scheme() == self.base_url.scheme()
+            && parsed.host() == self.base_url.host()
+            && parsed.port_or_known_default() == self.base_url.port_or_known_default();
+        let on_storage_host = parsed.scheme() == "https"
+            && parsed.port_or_known_default() == Some(443)
+            && parsed.domain().is_some_and(|host| {
+                let host = host.to_ascii_lowercase();
+                STORAGE_DOMAINS.iter().any(|domain| host.ends_with(domain))
+            });
+        if !on_base_url && !on_storage_host {
+            return Err(untrusted());
+        }
+        if !parsed.username().is_empty() || parsed.password().is_some() {
+            return Err(untrusted());
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn fetch_stored_message() {
+        let server = MockServer::start().await;
+        let message_path = "/v3/domains/fakedomain/messages/AgEFmh";
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path(message_path))
+            .and(matchers::header(
+                "Authorization",
+                "Basic YXBpOnRvbWF0b3Rva2Vu",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "recipients": "support@fakedomain",
+                "sender": "someone@example.com",
+                "from": "Someone <someone@example.com>",
+                "subject": "Re: Your order",
+                "body-plain": "Thanks!\n\n> On Monday you wrote",
+                "stripped-text": "Thanks!",
+                "message-headers": [
+                    ["In-Reply-To", "<order-7@fakedomain>"],
+                    ["Subject", "Re: Your order"]
+                ],
+                "attachments": [{
+                    "url": format!("{}{}/attachments/0", server.uri(), message_path),
+                    "content-type": "text/plain",
+                    "name": "note.txt",
+                    "size": 5
+                }]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path(format!("{}/attachments/0", message_path)))
+            .respond_with(ResponseTemplate::new(200).set_body_string("hello"))
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let message = mailer
+            .stored_message(&format!("{}{}", server.uri(), message_path))
+            .await
+            .expect("Fetching message");
+        assert_eq!(message.stripped_text.as_deref(), Some("Thanks!"));
+        assert_eq!(message.header("in-reply-to"), Some("<order-7@fakedomain>"));
+
+        let contents = mailer
+            .stored_attachment(&message.attachments[0])
+            .await
+            .expect("Fetching attachment");
+        assert_eq!(contents, b"hello");
+    }
+
+    #[tokio::test]
+    async fn resend_stored_message() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/domains/fakedomain/messages/AgEFmh"))
+            .and(matchers::body_string(
+                "to=a%40example.com%2Cb%40example.com",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "<resent@fakedomain>",
+                "message": "Queued. Thank you."
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let url = format!("{}/v3/domains/fakedomain/messages/AgEFmh", server.uri());
+
+        let id = mailer
+            .resend(&url, ["a@example.com", " b@example.com"])
+            .await
+            .expect("Resending message");
+        assert_eq!(id, MessageId("<resent@fakedomain>".into()));
+
+        assert_eq!(
+            mailer.resend(&url, Vec::<String>::new()).await,
+            Err(SendError::InvalidEmail(BuildError::MissingField("to")))
+        );
+    }
+
+    #[tokio::test]
+    async fn resend_applies_mailer_options() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/domains/fakedomain/messages/AgEFmh"))
+            .and(matchers::body_string_contains("to=qa%40fakedomain"))
+            .and(matchers::body_string_contains("o%3Atestmode=yes"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "<resent@fakedomain>",
+                "message": "Queued. Thank you."
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .sandbox(true)
+            .recipient_policy(crate::RecipientPolicy::catch_all("qa@fakedomain"))
+            .build()
+            .expect("Creating Mailer");
+        let url = format!("{}/v3/domains/fakedomain/messages/AgEFmh", server.uri());
+        mailer
+            .resend(&url, ["customer@example.com"])
+            .await
+            .expect("Resending message");
+    }
+
+    #[tokio::test]
+    async fn refuses_untrusted_storage_urls() {
+        let mailer = Mailer::new("fakedomain", "tomatotoken").expect("Creating Mailer");
+
+        for url in [
+            "https://storage-us-east4.api.mailgun.net/v3/domains/fakedomain/messages/AgEFmh",
+            "https://se.api.mailgun.net/v3/domains/fakedomain/messages/AgEFmh",
+            "https://api.eu.mailgun.net/v3/domains/fakedomain/messages/AgEFmh",
+        ] {
+            assert!(mailer.storage_url(url).is_ok(), "{}", url);
+        }
+        for url in [
+            "https://attacker.example.com/v3/domains/fakedomain/messages/AgEFmh",
+            "https://mailgun.net.attacker.example.com/messages/AgEFmh",
+            "https://attacker-mailgun.net/messages/AgEFmh",
+            "http://storage.api.mailgun.net/messages/AgEFmh",
+            "https://storage.api.mailgun.net:8443/messages/AgEFmh",
+            "https://user@storage.api.mailgun.net/messages/AgEFmh",
+            "not a url",
+        ] {
+            assert_eq!(
+                mailer.storage_url(url),
+                Err(SendError::UntrustedUrl(url.into())),
+                "{}",
+                url
+            );
+        }
+
+        let err = mailer
+            .stored_message("https://attacker.example.com/messages/AgEFmh")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SendError::UntrustedUrl(_)));
+    }
+}
@@ -14,44 +14,139 @@
 //! # Ok(())
 //! # }
 //! ```
-use std::env;
+use std::{sync::Arc, time::Instant};
 
+mod address;
+mod attachment;
+mod batch;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod builder;
+mod datetime;
+pub mod domains;
 mod email;
+mod eml;
 mod error;
+pub mod events;
+mod idempotency;
+mod identity;
+pub mod inbound;
+pub mod limits;
+pub mod lists;
+mod message_id;
+mod middleware;
+pub mod observer;
+mod options;
+mod paging;
+mod pool;
+pub mod queue;
+mod rate_limit;
+mod receipt;
+mod recipients;
+mod region;
+#[cfg(feature = "render")]
+mod render;
+mod retry;
+pub mod routes;
+pub mod stats;
+mod suppression_guard;
+pub mod suppressions;
+pub mod tags;
+pub mod templates;
+#[cfg(feature = "text-fallback")]
+mod text_fallback;
+pub mod transport;
+pub mod validation;
+pub mod webhooks;
+mod window;
 
 pub use {
-    email::{Email, EmailBody, EmailBuilder},
-    error::{BuildError, SendError, SetupError},
+    address::EmailAddress,
+    batch::{BatchHandle, BatchProgress, BatchResult, MAX_BATCH_RECIPIENTS},
+    builder::MailerBuilder,
+    email::{Email, EmailBody, EmailBuilder, RecipientVariables},
+    error::{AddressError, BuildError, SendError, SetupError},
+    identity::FromIdentity,
+    message_id::{MessageId, MessageIdParts},
+    middleware::SendMiddleware,
+    observer::{Observer, SendInfo},
+    options::{Priority, RecipientPolicy, SendOptions, SubjectPolicy, TlsPolicy},
+    paging::Page,
+    pool::MailerPool,
+    receipt::SendReceipt,
+    recipients::Recipients,
+    region::Region,
+    retry::RetryPolicy,
+    transport::{SendEmail, Transport},
+    window::DeliveryWindow,
 };
 
+/// Mailgun rejects messages larger than 25MB.
+const MAX_MESSAGE_SIZE: usize = 25 * 1024 * 1024;
+
 static USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
-static MG_BASE_URL: &str = "https://api.eu.mailgun.net";
+
+/// Scopes a request with the primary account's key to a subaccount.
+const ON_BEHALF_OF: &str = "X-Mailgun-On-Behalf-Of";
 
 #[derive(Debug)]
 pub struct Mailer {
     from: String,
+    domain: String,
+    base_url: reqwest::Url,
     messages_url: reqwest::Url,
+    /// Sent per request, so a client shared with other services never carries it.
+    auth: reqwest::header::HeaderValue,
+    /// The subaccount every request is sent for, see `MailerBuilder::on_behalf_of`.
+    on_behalf_of: Option<reqwest::header::HeaderValue>,
     client: reqwest::Client,
+    retry: Option<RetryPolicy>,
+    batch_concurrency: usize,
+    sandbox: bool,
+    tls: TlsPolicy,
+    subject_policy: SubjectPolicy,
+    recipient_policy: RecipientPolicy,
+    /// Only 200 counts as success rather than any 2xx, see `MailerBuilder::strict_status`.
+    strict_status: bool,
+    max_message_size: usize,
+    rate_limiter: Option<rate_limit::RateLimiter>,
+    send_timeout: Option<std::time::Duration>,
+    transport: Arc<dyn Transport>,
+    idempotency: idempotency::IdempotencyCache<SendReceipt>,
+    observers: Vec<Arc<dyn Observer>>,
+    middleware: Vec<Arc<dyn SendMiddleware>>,
+    suppression_guard: Option<suppression_guard::SuppressionGuard>,
+    /// Named from addresses, see `Mailer::identity`.
+    identities: std::collections::HashMap<String, String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct MessageId(String);
-
 impl Mailer {
     /// Creates a new Mailer by reading from Environment variables:
     /// * `MAILER46_DOMAIN`: The domain to send from.
     /// * `MAILER46_TOKEN`: The raw token received from Mailgun.
+    /// * `MAILER46_REGION`: Optional, `eu`, `us` or a base url. Defaults to `eu`.
+    /// * `MAILER46_BASE_URL`: Optional, a base url taking precedence over the region.
+    /// * `MAILER46_FROM`: Optional, the from address for emails without one.
+    /// * `MAILER46_TRANSPORT`: Optional, `file:<directory>` writes emails to the directory
+    ///   with `FileTransport` instead of sending them, `http` (default) sends them.
     ///
-    /// Uses base url to mailgun: `https://api.eu.mailgun.net`
+    /// Uses base url to mailgun: `https://api.eu.mailgun.net` unless another region is given.
     ///
     ///
     pub fn from_env() -> Result<Self, SetupError> {
-        let domain = env::var("MAILER46_DOMAIN")
-            .map_err(|_| SetupError::EnvVarMissing("MAILER46_DOMAIN"))?;
-        let token =
-            env::var("MAILER46_TOKEN").map_err(|_| SetupError::EnvVarMissing("MAILER46_TOKEN"))?;
+        MailerBuilder::from_env()?.build()
+    }
+
+    /// Same as `from_env`, reading `<prefix>_DOMAIN`, `<prefix>_TOKEN` and so on.
+    pub fn from_env_with_prefix(prefix: &str) -> Result<Self, SetupError> {
+        MailerBuilder::from_env_with_prefix(prefix)?.build()
+    }
 
-        Self::new(domain, token)
+    /// Same as `from_env_with_prefix`, also reading variables from a `.env` file.
+    /// See `MailerBuilder::from_dotenv`.
+    #[cfg(feature = "dotenv")]
+    pub fn from_dotenv(prefix: &str) -> Result<Self, SetupError> {
+        MailerBuilder::from_dotenv(prefix)?.build()
     }
 
     /// Creates a new client operating against the given domain.
@@ -59,7 +154,18 @@ impl Mailer {
     ///
     /// Uses base url to mailgun: `https://api.eu.mailgun.net`
     pub fn new(domain: impl AsRef<str>, token: impl AsRef<str>) -> Result<Self, SetupError> {
-        Self::new_with_mg_url(MG_BASE_URL, domain, token)
+        Self::builder(domain.as_ref(), token.as_ref()).build()
+    }
+
+    /// Creates a new client operating against the given domain in the given region.
+    pub fn new_with_region(
+        region: Region,
+        domain: impl AsRef<str>,
+        token: impl AsRef<str>,
+    ) -> Result<Self, SetupError> {
+        Self::builder(domain.as_ref(), token.as_ref())
+            .region(region)
+            .build()
     }
 
     pub fn new_with_mg_url(
@@ -67,59 +173,292 @@ impl Mailer {
         domain: impl AsRef<str>,
         token: impl AsRef<str>,
     ) -> Result<Self, SetupError> {
-        let from = format!("noreply@{}", domain.as_ref());
+        MailerBuilder::new(domain.as_ref(), token.as_ref())
+            .base_url(mg_url.as_ref())
+            .build()
+    }
+
+    /// Creates a builder for configuring the Mailer further.
+    pub fn builder(domain: impl Into<String>, token: impl Into<String>) -> MailerBuilder {
+        MailerBuilder::new(domain, token)
+    }
+
+    /// The domain this Mailer operates against.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
 
-        let messages_url = format!("{}/v3/{}/messages", mg_url.as_ref(), domain.as_ref())
-            .parse::<reqwest::Url>()
-            .map_err(|err| SetupError::InvalidVar("domain", err.to_string()))?;
+    async fn send(&self, email: Email) -> Result<MessageId, SendError> {
+        self.send_within(email, self.send_timeout)
+            .await
+            .map(|receipt| receipt.id)
+    }
 
-        let mut headers = reqwest::header::HeaderMap::new();
-        let token = base64::encode(format!("api:{}", token.as_ref()));
-        let auth_value = reqwest::header::HeaderValue::from_str(&format!("Basic {}", token))
-            .map_err(|err| SetupError::Build(err.to_string()))?;
+    /// Sends the email, giving up with `SendError::Timeout` once the timeout has passed.
+    pub(crate) async fn send_within(
+        &self,
+        mut email: Email,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<SendReceipt, SendError> {
+        email
+            .apply_defaults(&self.from, self.sandbox, self.tls, &self.subject_policy)
+            .map_err(SendError::InvalidEmail)?;
+        for middleware in &self.middleware {
+            email = middleware.before(email).await?;
+        }
+        // Last, so recipients added by middleware are checked too.
+        self.recipient_policy.apply(&mut email)?;
+        // Encoded once, for the size check, observers, logging and the request.
+        let size = email.encode()?.size;
+        email.check_size(self.max_message_size)?;
 
-        headers.insert("Authorization", auth_value);
+        let info = SendInfo::new(&self.domain, &email, size);
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "mailgun46.send",
+            domain = %info.domain,
+            recipients = info.recipients,
+            size = info.size,
+            outcome = tracing::field::Empty,
+        );
 
-        let client = reqwest::Client::builder()
-            .user_agent(USER_AGENT)
-            .default_headers(headers)
-            .build()
-            .map_err(|err| SetupError::Build(err.to_string()))?;
+        for observer in &self.observers {
+            observer.on_send_start(&info);
+        }
+        let started = Instant::now();
+        let delivery = async {
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, self.deliver(email))
+                    .await
+                    .unwrap_or(Err(SendError::Timeout)),
+                None => self.deliver(email).await,
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let delivery = tracing::Instrument::instrument(delivery, span.clone());
+        let result = delivery.await;
+        let elapsed = started.elapsed();
+        for middleware in self.middleware.iter().rev() {
+            middleware.after(&info, &result).await;
+        }
+
+        match &result {
+            Ok(receipt) => {
+                for observer in &self.observers {
+                    observer.on_send_success(&info, &receipt.id, elapsed);
+                }
+            }
+            Err(err) => {
+                for observer in &self.observers {
+                    observer.on_send_failure(&info, err, elapsed);
+                }
+            }
+        }
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(_) => span.record("outcome", "sent"),
+            Err(err) => {
+                tracing::warn!(parent: &span, error = %err, "Sending email failed");
+                span.record("outcome", "failed")
+            }
+        };
+
+        result
+    }
+
+    /// Hands the email to the transport, at most once per idempotency key.
+    async fn deliver(&self, email: Email) -> Result<SendReceipt, SendError> {
+        if let Some(guard) = &self.suppression_guard {
+            guard.check(self, &email).await?;
+        }
+        match email.idempotency_key.clone() {
+            Some(key) => {
+                self.idempotency
+                    .send_once(&key, || self.submit(email))
+                    .await
+            }
+            None => self.submit(email).await,
+        }
+    }
+
+    async fn submit(&self, email: Email) -> Result<SendReceipt, SendError> {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire().await;
+        }
+        self.transport.send_with_receipt(self, email).await
+    }
+
+    /// Posts the email to Mailgun, used by `HttpTransport`.
+    pub(crate) async fn send_http(&self, email: Email) -> Result<SendReceipt, SendError> {
+        let url = &match &email.source {
+            email::Source::Fields => self.messages_url.clone(),
+            email::Source::Mime(_) => self.domain_url(&["messages.mime"]),
+            email::Source::Stored(url) => self.storage_url(url)?,
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            fields = ?email.redacted_fields(),
+            attachments = email.attachments.len(),
+            "Posting email"
+        );
+        let res = if email.is_form() {
+            let form = email.form()?;
+            self.send_with_retry(|| Ok(self.post(url.clone()).form(&form.fields)))
+                .await?
+        } else if email
+            .attachments
+            .iter()
+            .all(attachment::Attachment::is_replayable)
+        {
+            self.send_with_retry(|| Ok(self.post(url.clone()).multipart(email.multipart()?)))
+                .await?
+        } else {
+            self.post(url.clone())
+                .multipart(email.multipart()?)
+                .send()
+                .await?
+        };
 
-        Ok(Self {
-            from,
-            messages_url,
-            client,
+        let status = res.status();
+        let received_at = res
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| datetime::parse_rfc2822(date).ok())
+            .unwrap_or_else(chrono::Utc::now);
+        let reply: MailReply = self.read_reply(res).await?;
+        Ok(SendReceipt {
+            id: MessageId(reply.id),
+            message: reply.message,
+            status: Some(status),
+            received_at,
         })
     }
 
-    async fn send(&self, email: Email) -> Result<MessageId, SendError> {
-        let res = self
+    /// Sends a message already rendered as MIME, such as one built with another library.
+    /// Recipients are taken from `to`, not from the headers of the message.
+    /// Goes through the transport like `Email::send`, with the Mailer's options,
+    /// recipient policy, middleware and observers applied.
+    pub async fn send_mime(
+        &self,
+        to: impl AsRef<str>,
+        mime: impl Into<Vec<u8>>,
+    ) -> Result<MessageId, SendError> {
+        let mut email = EmailBuilder::default()
+            .to(to.as_ref())
+            .build()
+            .map_err(SendError::InvalidEmail)?;
+        email.source = email::Source::Mime(mime.into().into());
+        self.send(email).await
+    }
+
+    /// Sends the request until it succeeds or may no longer be retried,
+    /// returning the last reply.
+    async fn send_with_retry<F>(&self, request: F) -> Result<reqwest::Response, SendError>
+    where
+        F: Fn() -> Result<reqwest::RequestBuilder, SendError>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = request()?.send().await;
+
+            let policy = self.retry.as_ref().filter(|p| p.should_retry(attempt));
+            let delay = match (&result, policy) {
+                (Ok(res), Some(policy)) if retry::is_retryable_status(res.status()) => {
+                    policy.delay(attempt, retry::retry_after(res.headers()))
+                }
+                (Err(err), Some(policy)) if retry::is_retryable_error(err) => {
+                    policy.delay(attempt, None)
+                }
+                _ => return Ok(result?),
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Starts an authorized request.
+    pub(crate) fn request(
+        &self,
+        method: reqwest::Method,
+        url: reqwest::Url,
+    ) -> reqwest::RequestBuilder {
+        let request = self
             .client
-            .post(self.messages_url.clone())
-            .form(&email)
-            .send()
-            .await?;
+            .request(method, url)
+            .header(reqwest::header::AUTHORIZATION, self.auth.clone());
+        match &self.on_behalf_of {
+            Some(subaccount) => request.header(ON_BEHALF_OF, subaccount.clone()),
+            None => request,
+        }
+    }
+
+    pub(crate) fn get(&self, url: reqwest::Url) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::GET, url)
+    }
+
+    pub(crate) fn post(&self, url: reqwest::Url) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::POST, url)
+    }
+
+    pub(crate) fn put(&self, url: reqwest::Url) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::PUT, url)
+    }
+
+    pub(crate) fn delete(&self, url: reqwest::Url) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::DELETE, url)
+    }
+
+    /// Url to an endpoint below the Mailgun base url, each segment is escaped.
+    pub(crate) fn api_url(&self, segments: &[&str]) -> reqwest::Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("base url validated on construction")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    /// Url to an endpoint below `/v3/<domain>`.
+    pub(crate) fn domain_url(&self, segments: &[&str]) -> reqwest::Url {
+        let mut url = self.api_url(&["v3", &self.domain]);
+        url.path_segments_mut()
+            .expect("base url validated on construction")
+            .extend(segments);
+        url
+    }
+
+    /// Sends a request and deserializes the JSON reply, non 2xx replies are errors.
+    pub(crate) async fn execute<T>(&self, request: reqwest::RequestBuilder) -> Result<T, SendError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.read_reply(request.send().await?).await
+    }
 
-        if res.status() != reqwest::StatusCode::OK {
+    /// Deserializes the JSON reply, non 2xx replies are errors.
+    pub(crate) async fn read_reply<T>(&self, res: reqwest::Response) -> Result<T, SendError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        if !error::is_success(res.status(), self.strict_status) {
             let status = res.status();
+            let headers = res.headers().clone();
             let body_bs = res.bytes().await?;
             let body = String::from_utf8_lossy(&body_bs);
-            return Err(SendError::Non200Reply {
-                status,
-                body: body.into(),
-            });
+            return Err(SendError::from_reply(status, &headers, body.into()));
         }
 
-        let reply = res.json::<MailReply>().await?;
-
-        Ok(MessageId(reply.id))
+        Ok(res.json::<T>().await?)
     }
 }
 
 #[derive(serde::Deserialize)]
 pub(crate) struct MailReply {
     id: String,
+    #[serde(default)]
+    message: String,
 }
 
 #[cfg(test)]
@@ -147,11 +486,15 @@ mod tests {
             .mount(&server)
             .await;
 
-        let client = Mailer::new_with_mg_url(&server.uri(), "fakedomain", "tomatotoken")
+        let client = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
             .expect("Creating Mailer");
         (client, server)
     }
 
+    fn form_fields(email: &Email) -> Vec<(String, String)> {
+        email.form().expect("Encoding email").fields.clone()
+    }
+
     #[test]
     fn serialize_email() {
         let email = EmailBuilder::default()
@@ -171,6 +514,1301 @@ mod tests {
         );
     }
 
+    #[test]
+    fn recipient_variables() {
+        let email = EmailBuilder::default()
+            .from("niclas")
+            .to("a@example.com")
+            .to("b@example.com")
+            .recipient_variable("a@example.com", "name", "A")
+            .recipient_variable("b@example.com", "name", "B")
+            .build()
+            .unwrap();
+
+        let form = form_fields(&email);
+        assert!(form.contains(&(
+            "recipient-variables".into(),
+            r#"{"a@example.com":{"name":"A"},"b@example.com":{"name":"B"}}"#.into()
+        )));
+
+        let err = EmailBuilder::default()
+            .to("a@example.com")
+            .to("b@example.com")
+            .recipient_variable("a@example.com", "name", "A")
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            BuildError::MissingRecipientVariables("b@example.com".into())
+        );
+
+        let email = EmailBuilder::default()
+            .to("a@example.com, B <b@example.com>")
+            .recipient_variable("a@example.com", "name", "A")
+            .recipient_variable("b@example.com", "name", "B")
+            .build()
+            .expect("Building email with a recipient list");
+        assert_eq!(email.recipient_count(), 2);
+        let err = EmailBuilder::default()
+            .to("a@example.com, c@example.com")
+            .recipient_variable("a@example.com", "name", "A")
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            BuildError::MissingRecipientVariables("c@example.com".into())
+        );
+    }
+
+    #[test]
+    fn deliver_at() {
+        let time = chrono::DateTime::parse_from_rfc3339("2021-02-24T13:11:16Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let email = EmailBuilder::default()
+            .to("someoneelse")
+            .deliver_at(time)
+            .build()
+            .unwrap();
+
+        assert!(form_fields(&email).contains(&(
+            "o:deliverytime".into(),
+            "Wed, 24 Feb 2021 13:11:16 +0000".into()
+        )));
+
+        let err = EmailBuilder::default()
+            .to("someoneelse")
+            .deliver_at(chrono::Utc::now() + chrono::Duration::days(4))
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InvalidField("deliver_at", _)));
+    }
+
+    #[test]
+    fn tags() {
+        let email = EmailBuilder::default()
+            .to("someoneelse")
+            .tag("newsletter")
+            .tag("october")
+            .build()
+            .unwrap();
+
+        let tags: Vec<_> = form_fields(&email)
+            .into_iter()
+            .filter(|(k, _)| k == "o:tag")
+            .map(|(_, v)| v)
+            .collect();
+        assert_eq!(tags, vec!["newsletter", "october"]);
+
+        let err = (0..4)
+            .fold(EmailBuilder::default().to("someoneelse"), |b, i| {
+                b.tag(i.to_string())
+            })
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InvalidField("tag", _)));
+    }
+
+    #[test]
+    fn tracking_toggles() {
+        let email = EmailBuilder::default()
+            .to("someoneelse")
+            .tracking(false)
+            .track_clicks(true)
+            .build()
+            .unwrap();
+
+        let form = form_fields(&email);
+        assert!(form.contains(&("o:tracking".into(), "no".into())));
+        assert!(form.contains(&("o:tracking-clicks".into(), "yes".into())));
+        assert!(!form.iter().any(|(k, _)| k == "o:tracking-opens"));
+    }
+
+    #[test]
+    fn send_options() {
+        let email = EmailBuilder::default()
+            .to("someone")
+            .options(SendOptions::new().dkim(true).require_tls(true))
+            .build()
+            .unwrap();
+
+        let fields = form_fields(&email);
+        assert!(fields.contains(&("o:dkim".into(), "yes".into())));
+        assert!(fields.contains(&("o:require-tls".into(), "yes".into())));
+        assert!(!fields.iter().any(|(k, _)| k == "o:skip-verification"));
+    }
+
+    #[test]
+    fn accessors() {
+        let email = EmailBuilder::default()
+            .to("\"Doe, Jane\" <jane@example.com>")
+            .to("b@example.com")
+            .subject("Receipt")
+            .html_body("<p>Thanks</p>")
+            .header("X-Campaign", "spring")
+            .tag("receipts")
+            .tag("orders")
+            .build()
+            .unwrap();
+
+        assert_eq!(email.recipient_count(), 2);
+        assert_eq!(email.to(), "\"Doe, Jane\" <jane@example.com>,b@example.com");
+        assert_eq!(email.subject(), Some("Receipt"));
+        assert_eq!(email.header("x-campaign"), Some("spring"));
+        let body = email.body().unwrap();
+        assert_eq!(body.html(), Some("<p>Thanks</p>"));
+        assert_eq!(body.text(), None);
+        assert_eq!(
+            email.estimated_size(),
+            serde_urlencoded::to_string(form_fields(&email))
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn with_recipient() {
+        let prototype = EmailBuilder::default()
+            .to("a@example.com")
+            .to("b@example.com")
+            .subject("Spring sale")
+            .html_body("<p>Everything must go</p>")
+            .recipient_variable("a@example.com", "name", "A")
+            .recipient_variable("b@example.com", "name", "B")
+            .idempotency_key("campaign-1")
+            .build()
+            .unwrap();
+
+        let email = prototype.with_recipient("B <b@example.com>");
+        assert!(Arc::ptr_eq(
+            email.body.as_ref().unwrap(),
+            prototype.body.as_ref().unwrap()
+        ));
+        assert_eq!(email.idempotency_key, None);
+
+        let fields = form_fields(&email);
+        assert!(fields.contains(&("to".into(), "B <b@example.com>".into())));
+        assert!(fields.contains(&(
+            "recipient-variables".into(),
+            r#"{"b@example.com":{"name":"B"}}"#.into()
+        )));
+        assert!(!fields
+            .iter()
+            .any(|(name, _)| name.starts_with("h:X-Idempotency")));
+
+        let keyed = EmailBuilder::default()
+            .to("a@example.com")
+            .header("x-idempotency-key", "campaign-2")
+            .build()
+            .unwrap();
+        let copy = keyed.with_recipient("b@example.com");
+        assert_eq!(copy.header("X-Idempotency-Key"), None);
+
+        let edited = email
+            .into_builder()
+            .text_body("Everything")
+            .build()
+            .unwrap();
+        assert!(!Arc::ptr_eq(
+            edited.body.as_ref().unwrap(),
+            prototype.body.as_ref().unwrap()
+        ));
+    }
+
+    #[test]
+    fn require_body() {
+        let build = |builder: EmailBuilder| builder.to("someone").require_body().build();
+
+        assert_eq!(
+            build(EmailBuilder::default()).unwrap_err(),
+            BuildError::MissingField("body")
+        );
+        assert_eq!(
+            build(EmailBuilder::default().body(EmailBody::default())).unwrap_err(),
+            BuildError::MissingField("body")
+        );
+        build(EmailBuilder::default().text_body("Hello")).expect("Text body");
+        build(EmailBuilder::default().html_body("<p>Hello</p>")).expect("Html body");
+        build(EmailBuilder::default().template("welcome")).expect("Template");
+    }
+
+    #[test]
+    fn missing_subject() {
+        let email = EmailBuilder::default().to("someone").build().unwrap();
+        assert!(!form_fields(&email)
+            .iter()
+            .any(|(name, _)| name == "subject"));
+
+        let err = EmailBuilder::default()
+            .to("someone")
+            .require_subject()
+            .build()
+            .unwrap_err();
+        assert_eq!(err, BuildError::MissingField("subject"));
+    }
+
+    #[tokio::test]
+    async fn subject_policy() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("subject=no+subject"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<1@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let email = || EmailBuilder::default().to("someone").build().unwrap();
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .subject_policy(SubjectPolicy::no_subject())
+            .build()
+            .expect("Creating Mailer");
+        email().send(&mailer).await.expect("Sending email");
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .subject_policy(SubjectPolicy::Reject)
+            .build()
+            .expect("Creating Mailer");
+        assert_eq!(
+            email().send(&mailer).await.unwrap_err(),
+            SendError::InvalidEmail(BuildError::MissingField("subject"))
+        );
+    }
+
+    #[test]
+    fn threading_headers() {
+        let original = MessageId("<20210224131116.1.E5C867B3818DC87B@fakedomain>".into());
+        let email = EmailBuilder::default()
+            .to("someone")
+            .in_reply_to(&original)
+            .references(["first@fakedomain", original.as_str()])
+            .build()
+            .unwrap();
+
+        let fields = form_fields(&email);
+        assert!(fields.contains(&(
+            "h:In-Reply-To".into(),
+            "<20210224131116.1.E5C867B3818DC87B@fakedomain>".into()
+        )));
+        assert!(fields.contains(&(
+            "h:References".into(),
+            "<first@fakedomain> <20210224131116.1.E5C867B3818DC87B@fakedomain>".into()
+        )));
+
+        let email = EmailBuilder::default()
+            .to("someone")
+            .references(Vec::<String>::new())
+            .build()
+            .unwrap();
+        assert_eq!(email.header("References"), None);
+    }
+
+    #[test]
+    fn mailgun_variables() {
+        #[derive(serde::Serialize)]
+        struct Order {
+            order_id: u32,
+            items: Vec<&'static str>,
+        }
+
+        let email = EmailBuilder::default()
+            .to("someone")
+            .mailgun_variables(&Order {
+                order_id: 42,
+                items: vec!["book"],
+            })
+            .build()
+            .unwrap();
+        assert!(form_fields(&email).contains(&(
+            "h:X-Mailgun-Variables".into(),
+            r#"{"items":["book"],"order_id":42}"#.into()
+        )));
+
+        for variables in [
+            serde_json::json!([1, 2]),
+            serde_json::json!({"x": "y".repeat(5000)}),
+        ] {
+            let err = EmailBuilder::default()
+                .to("someone")
+                .mailgun_variables(&variables)
+                .build()
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                BuildError::InvalidField("mailgun_variables", _)
+            ));
+        }
+    }
+
+    #[test]
+    fn ip_pool_and_dkim_signature() {
+        let email = EmailBuilder::default()
+            .to("someone")
+            .options(SendOptions::new().dkim_signature("example.com/s1"))
+            .ip_pool("60140bc1fee3e84dec5abeeb")
+            .build()
+            .unwrap();
+
+        let fields = form_fields(&email);
+        assert!(fields.contains(&("o:secondary-dkim".into(), "example.com/s1".into())));
+        assert!(fields.contains(&(
+            "o:sending-ip-pool".into(),
+            "60140bc1fee3e84dec5abeeb".into()
+        )));
+    }
+
+    #[test]
+    fn template() {
+        let email = EmailBuilder::default()
+            .to("someone")
+            .template("welcome")
+            .template_version("v2")
+            .build()
+            .unwrap();
+
+        let fields = form_fields(&email);
+        assert!(fields.contains(&("template".into(), "welcome".into())));
+        assert!(fields.contains(&("t:version".into(), "v2".into())));
+    }
+
+    #[test]
+    fn amp_body() {
+        let email = EmailBuilder::default()
+            .to("someone")
+            .html_body("<p>Hi</p>")
+            .amp_body("<html amp4email><body>Hi</body></html>")
+            .build()
+            .unwrap();
+        let fields = form_fields(&email);
+        assert!(fields.contains(&(
+            "amp-html".into(),
+            "<html amp4email><body>Hi</body></html>".into()
+        )));
+
+        let err = EmailBuilder::default()
+            .to("someone")
+            .text_body("Hi")
+            .amp_body("<html amp4email></html>")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InvalidField("amp_body", _)));
+    }
+
+    #[test]
+    fn custom_headers() {
+        let email = EmailBuilder::default()
+            .to("someoneelse")
+            .header("Reply-To", "support@example.com")
+            .header("X-Correlation-Id", "abc123")
+            .build()
+            .unwrap();
+
+        let form = form_fields(&email);
+        assert!(form.contains(&("h:Reply-To".into(), "support@example.com".into())));
+        assert!(form.contains(&("h:X-Correlation-Id".into(), "abc123".into())));
+
+        let err = EmailBuilder::default()
+            .to("someoneelse")
+            .header("Not a header:", "value")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InvalidField("header", _)));
+    }
+
+    #[test]
+    fn deserialize_email() {
+        let email = EmailBuilder::default()
+            .to("\"Doe, John\" <john@example.com>")
+            .to("jane@example.com")
+            .subject("Order")
+            .html_body("<p>Shipped</p>")
+            .text_body("Shipped")
+            .recipient_variable("john@example.com", "id", 1)
+            .recipient_variable("jane@example.com", "id", 2)
+            .deliver_at(
+                chrono::DateTime::parse_from_rfc3339("2021-02-24T13:11:16Z")
+                    .unwrap()
+                    .with_timezone(&chrono::Utc),
+            )
+            .tag("orders")
+            .tag("shipping")
+            .track_clicks(false)
+            .require_tls()
+            .idempotency_key("order-7")
+            .header("X-Order", "7")
+            .variable("order-id", "7")
+            .build()
+            .unwrap();
+
+        let json = serde_json::to_string(&email).expect("Serializing email");
+        assert!(
+            json.contains(r#""o:tag":["orders","shipping"]"#),
+            "{}",
+            json
+        );
+        let stored: Email = serde_json::from_str(&json).expect("Deserializing email");
+        assert_eq!(form_fields(&stored), form_fields(&email));
+        // Stores such as Postgres jsonb keep only one of repeated keys.
+        let value = serde_json::to_value(&email).expect("Serializing email");
+        let stored: Email = serde_json::from_value(value).expect("Deserializing email");
+        assert_eq!(stored.tags, ["orders", "shipping"]);
+        let old: Email = serde_json::from_str(r#"{"to":"a","o:tag":"a","o:tag":"b"}"#)
+            .expect("Deserializing repeated tags");
+        assert_eq!(old.tags, ["a", "b"]);
+        assert_eq!(stored.idempotency_key.as_deref(), Some("order-7"));
+
+        let edited = stored
+            .into_builder()
+            .subject("Order, again")
+            .build()
+            .unwrap();
+        let mut expected = form_fields(&email);
+        for field in expected.iter_mut().filter(|(name, _)| name == "subject") {
+            field.1 = "Order, again".into();
+        }
+        assert_eq!(form_fields(&edited), expected);
+
+        let err = serde_json::from_str::<Email>(r#"{"to": "a@example.com", "o:unknown": "yes"}"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("o:unknown"));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn redacted_fields() {
+        let email = EmailBuilder::default()
+            .from("support@example.com")
+            .to("\"Doe, John\" <john@example.com>")
+            .to("jane")
+            .subject("Your order")
+            .text_body("Hi John")
+            .recipient_variable("john@example.com", "id", 1)
+            .recipient_variable("jane", "id", 2)
+            .reply_to("john.doe@example.org")
+            .build()
+            .unwrap();
+
+        let fields = email.redacted_fields();
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(field("from"), Some("support@example.com"));
+        assert_eq!(field("to"), Some("***@example.com,***"));
+        assert_eq!(field("subject"), Some("Your order"));
+        assert_eq!(field("text"), Some("<7 bytes>"));
+        assert_eq!(field("recipient-variables"), Some("<redacted>"));
+        assert_eq!(field("h:Reply-To"), Some("***@example.org"));
+    }
+
+    #[test]
+    fn list_unsubscribe() {
+        let email = EmailBuilder::default()
+            .to("someoneelse")
+            .list_unsubscribe(
+                "unsubscribe@example.com?subject=unsubscribe",
+                "https://example.com/unsubscribe?id=42",
+            )
+            .build()
+            .unwrap();
+
+        let form = form_fields(&email);
+        assert!(form.contains(&(
+            "h:List-Unsubscribe".into(),
+            "<mailto:unsubscribe@example.com?subject=unsubscribe>, <https://example.com/unsubscribe?id=42>".into()
+        )));
+        assert!(form.contains(&(
+            "h:List-Unsubscribe-Post".into(),
+            "List-Unsubscribe=One-Click".into()
+        )));
+
+        for (mailto, url) in [
+            ("unsubscribe@example.com", "http://example.com/unsubscribe"),
+            ("mailto:", "https://example.com/unsubscribe"),
+            ("unsubscribe@example.com", "https://example.com/a, b"),
+        ] {
+            let err = EmailBuilder::default()
+                .to("someoneelse")
+                .list_unsubscribe(mailto, url)
+                .build()
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                BuildError::InvalidField("list_unsubscribe", _)
+            ));
+        }
+    }
+
+    #[test]
+    fn priority() {
+        let email = EmailBuilder::default()
+            .to("someoneelse")
+            .priority(Priority::High)
+            .build()
+            .unwrap();
+
+        let form = form_fields(&email);
+        assert!(form.contains(&("h:X-Priority".into(), "1 (Highest)".into())));
+        assert!(form.contains(&("h:Importance".into(), "High".into())));
+    }
+
+    #[test]
+    fn variables() {
+        let email = EmailBuilder::default()
+            .to("someoneelse")
+            .variable("order-id", "7")
+            .variable("cart", r#"{"items": 2}"#)
+            .build()
+            .unwrap();
+
+        let form = form_fields(&email);
+        assert!(form.contains(&("v:order-id".into(), "7".into())));
+        assert!(form.contains(&("v:cart".into(), r#"{"items": 2}"#.into())));
+    }
+
+    #[test]
+    fn encodes_once() {
+        let mut email = EmailBuilder::default()
+            .to("someone@example.com")
+            .text_body("Hello")
+            .build()
+            .unwrap();
+        assert!(email.form.0.is_none());
+
+        let form = email.encode().expect("Encoding email");
+        assert_eq!(form.size, "to=someone%40example.com&text=Hello".len());
+        assert!(Arc::ptr_eq(&form, &email.form().unwrap()));
+        assert_eq!(email.estimated_size(), form.size);
+        assert!(email.clone().form.0.is_none());
+    }
+
+    #[test]
+    fn reply_to() {
+        let email = EmailBuilder::default()
+            .to(EmailAddress::new("someone@example.com").unwrap())
+            .reply_to(EmailAddress::with_name("Support", "support@example.com").unwrap())
+            .build()
+            .unwrap();
+
+        let form = form_fields(&email);
+        assert!(form.contains(&("to".into(), "someone@example.com".into())));
+        assert!(form.contains(&(
+            "h:Reply-To".into(),
+            r#""Support" <support@example.com>"#.into()
+        )));
+
+        let err = EmailBuilder::default()
+            .to("someone@example.com")
+            .reply_to("support")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InvalidAddress(_)));
+
+        let email = EmailBuilder::default()
+            .to("someone@example.com")
+            .header("reply-to", "old@example.com")
+            .reply_to("support@example.com")
+            .build()
+            .unwrap();
+        let reply_to: Vec<_> = form_fields(&email)
+            .into_iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("h:reply-to"))
+            .collect();
+        assert_eq!(
+            reply_to,
+            [("h:Reply-To".to_string(), "support@example.com".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_send() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .retry(RetryPolicy::default().max_attempts(3))
+            .build()
+            .expect("Creating Mailer");
+
+        let id = EmailBuilder::default()
+            .to("someone@example.com")
+            .build()
+            .unwrap()
+            .send(&client)
+            .await
+            .expect("Sending after retries");
+        assert_eq!(id, MessageId("<id@fakedomain>".into()));
+    }
+
+    #[test]
+    fn region_urls() {
+        let mailer = Mailer::new_with_region(Region::Us, "fakedomain", "tomatotoken").unwrap();
+        assert_eq!(
+            mailer.messages_url.as_str(),
+            "https://api.mailgun.net/v3/fakedomain/messages"
+        );
+
+        let mailer = Mailer::new("fakedomain", "tomatotoken").unwrap();
+        assert_eq!(
+            mailer.domain_url(&["events"]).as_str(),
+            "https://api.eu.mailgun.net/v3/fakedomain/events"
+        );
+    }
+
+    #[tokio::test]
+    async fn builder_default_from() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::header(
+                "Authorization",
+                "Basic YXBpOnRvbWF0b3Rva2Vu",
+            ))
+            .and(matchers::body_string_contains("from=support%40fakedomain"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .from("support@fakedomain")
+            .client(reqwest::Client::new())
+            .build()
+            .expect("Creating Mailer");
+
+        EmailBuilder::default()
+            .to("someone@example.com")
+            .build()
+            .unwrap()
+            .send(&client)
+            .await
+            .expect("Sending");
+    }
+
+    #[tokio::test]
+    async fn shared_client() {
+        let server = MockServer::start().await;
+        for (domain, auth) in [
+            ("fakedomain", "Basic YXBpOnRvbWF0b3Rva2Vu"),
+            ("otherdomain", "Basic YXBpOm90aGVydG9rZW4="),
+        ] {
+            Mock::given(matchers::method("POST"))
+                .and(matchers::path(format!("/v3/{}/messages", domain)))
+                .and(matchers::header("Authorization", auth))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let client = reqwest::Client::new();
+        for (domain, token) in [("fakedomain", "tomatotoken"), ("otherdomain", "othertoken")] {
+            let mailer = Mailer::builder(domain, token)
+                .base_url(server.uri())
+                .with_client(client.clone())
+                .build()
+                .expect("Creating Mailer");
+            EmailBuilder::default()
+                .to("someone@example.com")
+                .build()
+                .unwrap()
+                .send(&mailer)
+                .await
+                .expect("Sending");
+        }
+    }
+
+    #[tokio::test]
+    async fn send_mime() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages.mime"))
+            .and(matchers::body_string_contains("name=\"to\""))
+            .and(matchers::body_string_contains("name=\"o:testmode\""))
+            .and(matchers::body_string_contains("Subject: Raw"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .sandbox(true)
+            .recipient_policy(RecipientPolicy::allow(["example.com"]))
+            .build()
+            .expect("Creating Mailer");
+        let mime = "From: a@fakedomain\r\nTo: someone@example.com\r\nSubject: Raw\r\n\r\nHello";
+        let id = mailer
+            .send_mime("someone@example.com", mime)
+            .await
+            .expect("Sending mime");
+        assert_eq!(id, MessageId("<id@fakedomain>".into()));
+
+        let err = mailer
+            .send_mime("someone@elsewhere.com", mime)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SendError::BlockedByPolicy(_)), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn structured_errors() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("to=nobody"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "message": "'to' parameter is not a valid address. please check documentation"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("to=busy"))
+            .respond_with(ResponseTemplate::new(503).insert_header("Retry-After", "30"))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("to=variables"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "message": "recipient-variables parameter is not a valid JSON"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("to=garbled"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Queued"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "12"))
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let send = |to: &'static str| {
+            let email = EmailBuilder::default().to(to).build().unwrap();
+            email.send(&mailer)
+        };
+
+        let err = send("nobody").await.unwrap_err();
+        assert!(
+            matches!(err, SendError::InvalidRecipient { .. }),
+            "{:?}",
+            err
+        );
+        assert!(err.body().unwrap().contains("not a valid address"));
+        assert!(!err.is_retryable());
+
+        let err = send("busy").await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), Some(std::time::Duration::from_secs(30)));
+
+        let err = send("variables").await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+        assert!(matches!(err, SendError::Non200Reply { .. }), "{:?}", err);
+
+        let err = send("garbled").await.unwrap_err();
+        assert!(matches!(err, SendError::Decode(_)), "{:?}", err);
+        assert!(!err.is_retryable());
+
+        let err = send("someone@example.com").await.unwrap_err();
+        assert_eq!(
+            err,
+            SendError::RateLimited {
+                retry_after: Some(std::time::Duration::from_secs(12)),
+                body: String::new(),
+            }
+        );
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), Some(std::time::Duration::from_secs(12)));
+    }
+
+    #[tokio::test]
+    async fn success_statuses() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("to=accepted"))
+            .respond_with(
+                ResponseTemplate::new(202)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(301).insert_header("Location", "https://eu.example.com/"),
+            )
+            .mount(&server)
+            .await;
+
+        let send = |mailer: Mailer, to: &'static str| async move {
+            let email = EmailBuilder::default().to(to).build().unwrap();
+            email.send(&mailer).await
+        };
+        let mailer = || MailerBuilder::new("fakedomain", "tomatotoken").base_url(server.uri());
+
+        let id = send(mailer().build().unwrap(), "accepted").await;
+        assert_eq!(id.expect("Sending email").0, "<id@fakedomain>");
+
+        let err = send(mailer().build().unwrap(), "moved").await.unwrap_err();
+        assert_eq!(
+            err,
+            SendError::Redirect {
+                status: reqwest::StatusCode::MOVED_PERMANENTLY,
+                location: Some("https://eu.example.com/".into()),
+                body: String::new(),
+            }
+        );
+        assert!(!err.is_retryable());
+
+        let strict = mailer().strict_status(true).build().unwrap();
+        let err = send(strict, "accepted").await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::ACCEPTED));
+    }
+
+    #[tokio::test]
+    async fn on_behalf_of() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::header("X-Mailgun-On-Behalf-Of", "subaccount-1"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/tags"))
+            .and(matchers::header("X-Mailgun-On-Behalf-Of", "subaccount-1"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"items": [], "paging": {}})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .on_behalf_of("subaccount-1")
+            .build()
+            .expect("Creating Mailer");
+        EmailBuilder::default()
+            .to("someone@example.com")
+            .build()
+            .unwrap()
+            .send(&mailer)
+            .await
+            .expect("Sending email");
+        mailer.tags().await.expect("Listing tags");
+
+        let err = MailerBuilder::new("fakedomain", "tomatotoken")
+            .on_behalf_of(" ")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, SetupError::InvalidVar("on_behalf_of", _)));
+    }
+
+    #[tokio::test]
+    async fn recipient_policy() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("to=qa%40example.com"))
+            .and(matchers::body_string_contains(
+                "h%3AX-Original-To=customer%40gmail.com%2Cother%40gmail.com",
+            ))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let email = || {
+            EmailBuilder::default()
+                .to("customer@gmail.com")
+                .to("other@gmail.com")
+                .build()
+                .unwrap()
+        };
+
+        let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .recipient_policy(RecipientPolicy::catch_all("qa@example.com"))
+            .build()
+            .expect("Creating Mailer");
+        mailer.send(email()).await.expect("Sending to catch-all");
+
+        let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .recipient_policy(RecipientPolicy::allow([
+                "example.com",
+                "Customer@gmail.com",
+            ]))
+            .build()
+            .expect("Creating Mailer");
+        assert_eq!(
+            mailer.send(email()).await,
+            Err(SendError::BlockedByPolicy("other@gmail.com".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn sandbox_sets_test_mode() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("o%3Atestmode=yes"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .sandbox(true)
+            .build()
+            .expect("Creating Mailer");
+
+        EmailBuilder::default()
+            .to("someone@example.com")
+            .test_mode(false)
+            .build()
+            .unwrap()
+            .send(&mailer)
+            .await
+            .expect("Sending in sandbox");
+    }
+
+    #[tokio::test]
+    async fn tls_policy() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::body_string_contains("o%3Arequire-tls=yes"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .tls_policy(TlsPolicy::Required)
+            .build()
+            .expect("Creating Mailer");
+
+        let email = EmailBuilder::default().to("someone").build().unwrap();
+        assert!(email.send(&mailer).await.is_ok());
+
+        let email = EmailBuilder::default()
+            .to("someone")
+            .options(SendOptions::new().require_tls(false))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            email.send(&mailer).await,
+            Err(SendError::InvalidEmail(BuildError::InvalidField(
+                "require_tls",
+                _
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn attachments() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::header_exists("content-type"))
+            .and(matchers::body_string_contains(r#"name="to""#))
+            .and(matchers::body_string_contains(r#"filename="note.txt""#))
+            .and(matchers::body_string_contains("in memory"))
+            .and(matchers::body_string_contains(r#"filename="report.csv""#))
+            .and(matchers::body_string_contains("streamed,content"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let email = EmailBuilder::default()
+            .to("someone@example.com")
+            .attachment("note.txt", "text/plain", "in memory")
+            .attachment_stream("report.csv", "text/csv", b"streamed,content".as_slice())
+            .build()
+            .unwrap();
+
+        assert!(email.clone().send(&mailer).await.is_ok());
+        assert!(matches!(
+            email.send(&mailer).await,
+            Err(SendError::InvalidEmail(BuildError::InvalidField(
+                "attachment",
+                _
+            )))
+        ));
+
+        let err = EmailBuilder::default()
+            .to("someone@example.com")
+            .attachment("note.txt", "not a type", "x")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InvalidField("attachment", _)));
+    }
+
+    #[tokio::test]
+    async fn too_large() {
+        let (_, server) = setup().await;
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .max_message_size(100)
+            .build()
+            .expect("Creating Mailer");
+
+        let email = EmailBuilder::default()
+            .to("someone@example.com")
+            .text_body("x".repeat(100))
+            .build()
+            .unwrap();
+        let err = email.send(&mailer).await.unwrap_err();
+        assert!(matches!(err, SendError::TooLarge { limit: 100, size } if size > 100));
+    }
+
+    #[tokio::test]
+    async fn send_with_receipt() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Date", "Tue, 27 Sep 2011 20:24:22 GMT")
+                    .set_body_json(serde_json::json!({
+                        "id": "<20111114174239.25659.5817@samples.mailgun.org>",
+                        "message": "Queued. Thank you."
+                    })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let receipt = EmailBuilder::default()
+            .to("someoneelse")
+            .build()
+            .unwrap()
+            .send_with_receipt(&mailer)
+            .await
+            .expect("Sending email");
+
+        assert_eq!(
+            receipt.id.as_str(),
+            "<20111114174239.25659.5817@samples.mailgun.org>"
+        );
+        assert_eq!(receipt.message, "Queued. Thank you.");
+        assert_eq!(receipt.status, Some(reqwest::StatusCode::OK));
+        assert_eq!(
+            receipt.received_at.to_rfc2822(),
+            "Tue, 27 Sep 2011 20:24:22 +0000"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"}))
+                    .set_delay(std::time::Duration::from_secs(5)),
+            )
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .send_timeout(std::time::Duration::from_secs(10))
+            .build()
+            .expect("Creating Mailer");
+
+        let email = EmailBuilder::default().to("someone").build().unwrap();
+        let result = email
+            .send_with_timeout(&mailer, std::time::Duration::from_millis(50))
+            .await;
+        assert_eq!(result, Err(SendError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn idempotent_send() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains(
+                "h%3AX-Idempotency-Key=order-7",
+            ))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let email = EmailBuilder::default()
+            .to("someone@example.com")
+            .idempotency_key("order-7")
+            .build()
+            .unwrap();
+
+        let (first, second) = tokio::join!(email.clone().send(&mailer), email.send(&mailer));
+        assert_eq!(first, Ok(MessageId("<id@fakedomain>".into())));
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn observers() {
+        use std::sync::Mutex;
+
+        #[derive(Debug, Default)]
+        struct Calls(Mutex<Vec<String>>);
+
+        impl Observer for Calls {
+            fn on_send_start(&self, info: &SendInfo) {
+                let call = format!("start {} {}", info.domain, info.recipients);
+                self.0.lock().unwrap().push(call);
+            }
+
+            fn on_send_success(&self, _: &SendInfo, id: &MessageId, _: std::time::Duration) {
+                self.0.lock().unwrap().push(format!("sent {}", id.0));
+            }
+
+            fn on_send_failure(&self, _: &SendInfo, err: &SendError, _: std::time::Duration) {
+                let call = format!("failed {:?}", err.status());
+                self.0.lock().unwrap().push(call);
+            }
+        }
+
+        let (_, server) = setup().await;
+        let calls = Arc::new(Calls::default());
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .observer(calls.clone())
+            .build()
+            .expect("Creating Mailer");
+
+        let email = EmailBuilder::default()
+            .to("a@example.com")
+            .to("b@example.com")
+            .build()
+            .unwrap();
+        email.send(&mailer).await.unwrap();
+
+        let unauthorized = Mailer::builder("otherdomain", "tomatotoken")
+            .base_url(server.uri())
+            .observer(calls.clone())
+            .build()
+            .expect("Creating Mailer");
+        let email = EmailBuilder::default().to("c@example.com").build().unwrap();
+        assert!(email.send(&unauthorized).await.is_err());
+
+        assert_eq!(
+            *calls.0.lock().unwrap(),
+            [
+                "start fakedomain 2",
+                "sent <20210224131116.1.E5C867B3818DC87B@fakedomain>",
+                "start otherdomain 1",
+                "failed Some(404)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_transport() {
+        #[derive(Debug)]
+        struct Recipients(std::sync::Mutex<Vec<String>>);
+
+        #[async_trait::async_trait]
+        impl Transport for Recipients {
+            async fn send(&self, mailer: &Mailer, email: Email) -> Result<MessageId, SendError> {
+                self.0.lock().unwrap().push(email.to);
+                Ok(MessageId(format!("<1@{}>", mailer.domain())))
+            }
+        }
+
+        let transport = Arc::new(Recipients(Default::default()));
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .transport(transport.clone())
+            .build()
+            .expect("Creating Mailer");
+
+        let id = EmailBuilder::default()
+            .to("someone@example.com")
+            .build()
+            .unwrap()
+            .send(&mailer)
+            .await
+            .expect("Sending");
+        assert_eq!(id, MessageId("<1@fakedomain>".into()));
+        assert_eq!(*transport.0.lock().unwrap(), vec!["someone@example.com"]);
+    }
+
     #[tokio::test]
     async fn send_a_test_email() {
         let (client, server) = setup().await;
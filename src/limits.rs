@@ -0,0 +1,110 @@
+//! Sending limits set on the domain or account, along with the current usage.
+//!
+//! ```
+//! use mailgun46::Mailer;
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! if let Some(limit) = mailer.limits().await? {
+//!     if limit.remaining() < 1000 {
+//!         println!("Pausing, {} of {} sent this {}", limit.current, limit.limit, limit.period);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use reqwest::StatusCode;
+
+use crate::{Mailer, SendError};
+
+/// How many messages may be sent per period, and how many were sent in the current one.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SendingLimit {
+    pub limit: u64,
+    pub current: u64,
+
+    /// Length of the period, such as `1d` or `1m`.
+    pub period: String,
+}
+
+impl SendingLimit {
+    /// Messages left to send in the current period.
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.current)
+    }
+
+    /// Mailgun rejects messages until the period ends.
+    pub fn is_exhausted(&self) -> bool {
+        self.current >= self.limit
+    }
+}
+
+impl Mailer {
+    /// The sending limit of the Mailer's domain, None when no limit is set.
+    pub async fn limits(&self) -> Result<Option<SendingLimit>, SendError> {
+        not_found_as_none(
+            self.execute(self.get(self.api_url(&["v3", "domains", &self.domain, "limits"])))
+                .await,
+        )
+    }
+
+    /// The monthly sending limit of the account, None when no limit is set.
+    pub async fn account_limits(&self) -> Result<Option<SendingLimit>, SendError> {
+        not_found_as_none(
+            self.execute(self.get(self.api_url(&["v5", "accounts", "limit", "custom", "monthly"])))
+                .await,
+        )
+    }
+}
+
+/// Mailgun replies 404 for limits that were never set.
+fn not_found_as_none(
+    result: Result<SendingLimit, SendError>,
+) -> Result<Option<SendingLimit>, SendError> {
+    match result {
+        Ok(limit) => Ok(Some(limit)),
+        Err(err) if err.status() == Some(StatusCode::NOT_FOUND) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn limits() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/domains/fakedomain/limits"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "limit": 10000,
+                "current": 9500,
+                "period": "1d"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v5/accounts/limit/custom/monthly"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "message": "No limit set"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let limit = mailer
+            .limits()
+            .await
+            .expect("Fetching limits")
+            .expect("Domain limit");
+        assert_eq!(limit.remaining(), 500);
+        assert!(!limit.is_exhausted());
+        assert_eq!(limit.period, "1d");
+
+        assert_eq!(mailer.account_limits().await, Ok(None));
+    }
+}
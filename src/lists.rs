@@ -0,0 +1,237 @@
+//! Managing mailing lists and their members, `/v3/lists`.
+//!
+//! ```
+//! use mailgun46::{Mailer, lists::{MailingList, Member}};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let list = mailer
+//!     .create_mailing_list(&MailingList::new("news@example.com"))
+//!     .await?;
+//! mailer
+//!     .add_list_member(&list.address, &Member::new("someone@example.com"))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::de::IgnoredAny;
+
+use crate::{email::serialize_yes_no_flag, Mailer, Page, SendError};
+
+/// Who may post to a mailing list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLevel {
+    /// Only authenticated API calls may post.
+    #[default]
+    Readonly,
+    /// Members may post.
+    Members,
+    /// Anyone may post.
+    Everyone,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MailingList {
+    pub address: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub access_level: AccessLevel,
+
+    /// Set by Mailgun.
+    #[serde(
+        default,
+        skip_serializing,
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+
+    /// Set by Mailgun.
+    #[serde(default, skip_serializing)]
+    pub members_count: u64,
+}
+
+impl MailingList {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            name: None,
+            description: None,
+            access_level: AccessLevel::default(),
+            created_at: None,
+            members_count: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Member {
+    pub address: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Arbitrary data, available as `%recipient.<key>%` when sending to the list.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        serialize_with = "crate::email::serialize_json_string"
+    )]
+    pub vars: BTreeMap<String, serde_json::Value>,
+
+    #[serde(
+        default = "subscribed_default",
+        serialize_with = "serialize_yes_no_flag"
+    )]
+    pub subscribed: bool,
+}
+
+impl Member {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            name: None,
+            vars: BTreeMap::new(),
+            subscribed: true,
+        }
+    }
+}
+
+fn subscribed_default() -> bool {
+    true
+}
+
+#[derive(serde::Deserialize)]
+struct ListReply {
+    list: MailingList,
+}
+
+#[derive(serde::Deserialize)]
+struct MemberReply {
+    member: Member,
+}
+
+impl Mailer {
+    /// Lists the first page of mailing lists on the account.
+    pub async fn mailing_lists(&self) -> Result<Page<MailingList>, SendError> {
+        self.execute_page(self.get(self.api_url(&["v3", "lists", "pages"])))
+            .await
+    }
+
+    pub async fn create_mailing_list(&self, list: &MailingList) -> Result<MailingList, SendError> {
+        let reply: ListReply = self
+            .execute(self.post(self.api_url(&["v3", "lists"])).form(list))
+            .await?;
+        Ok(reply.list)
+    }
+
+    pub async fn delete_mailing_list(&self, address: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.api_url(&["v3", "lists", address])))
+            .await?;
+        Ok(())
+    }
+
+    /// Lists the first page of members on a mailing list.
+    pub async fn list_members(&self, list: &str) -> Result<Page<Member>, SendError> {
+        self.execute_page(self.get(self.api_url(&["v3", "lists", list, "members", "pages"])))
+            .await
+    }
+
+    pub async fn add_list_member(&self, list: &str, member: &Member) -> Result<Member, SendError> {
+        let reply: MemberReply = self
+            .execute(
+                self.post(self.api_url(&["v3", "lists", list, "members"]))
+                    .form(member),
+            )
+            .await?;
+        Ok(reply.member)
+    }
+
+    pub async fn remove_list_member(&self, list: &str, member: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.api_url(&["v3", "lists", list, "members", member])))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn manage_members() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/lists/news@example.com/members"))
+            .and(matchers::body_string_contains("subscribed=yes"))
+            .and(matchers::body_string_contains(
+                "vars=%7B%22plan%22%3A%22pro%22%7D",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "member": {
+                    "address": "someone@example.com",
+                    "name": null,
+                    "subscribed": true,
+                    "vars": {"plan": "pro"}
+                },
+                "message": "Mailing list member has been created"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/lists/news@example.com/members/pages"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [{"address": "someone@example.com", "subscribed": false, "vars": {}}],
+                "paging": {"next": format!("{}/v3/lists/news@example.com/members/pages?page=next", server.uri())}
+            })))
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let mut member = Member::new("someone@example.com");
+        member.vars.insert("plan".into(), "pro".into());
+        let member = mailer
+            .add_list_member("news@example.com", &member)
+            .await
+            .expect("Adding member");
+        assert_eq!(member.vars["plan"], "pro");
+
+        let members = mailer
+            .list_members("news@example.com")
+            .await
+            .expect("Listing members");
+        assert!(!members.items[0].subscribed);
+    }
+
+    #[test]
+    fn deserialize_list() {
+        let list: MailingList = serde_json::from_str(
+            r#"{
+                "access_level": "members",
+                "address": "news@example.com",
+                "created_at": "Tue, 09 Aug 2016 13:37:49 -0000",
+                "description": "",
+                "members_count": 12,
+                "name": "News"
+            }"#,
+        )
+        .expect("Deserializing list");
+
+        assert_eq!(list.access_level, AccessLevel::Members);
+        assert_eq!(list.members_count, 12);
+        assert!(list.created_at.is_some());
+    }
+}
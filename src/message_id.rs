@@ -0,0 +1,111 @@
+use std::{convert::Infallible, fmt, str::FromStr};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+/// The id Mailgun assigns to an accepted message, such as
+/// `<20210224131116.1.E5C867B3818DC87B@example.com>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MessageId(pub(crate) String);
+
+/// The components of a Mailgun message id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageIdParts {
+    /// When Mailgun accepted the message, to the second.
+    pub timestamp: DateTime<Utc>,
+    pub sequence: u32,
+    pub token: String,
+    pub domain: String,
+}
+
+impl MessageId {
+    /// The id as returned by Mailgun, including the surrounding `<>`.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+
+    /// The id without the surrounding `<>`, as used by the Events API and webhooks.
+    pub fn bare(&self) -> &str {
+        self.0.trim_start_matches('<').trim_end_matches('>')
+    }
+
+    /// Splits `<timestamp.sequence.token@domain>` into its components,
+    /// None for ids in another format.
+    pub fn parts(&self) -> Option<MessageIdParts> {
+        let (local, domain) = self.bare().rsplit_once('@')?;
+        let mut fields = local.splitn(3, '.');
+        let timestamp = NaiveDateTime::parse_from_str(fields.next()?, "%Y%m%d%H%M%S").ok()?;
+        let sequence = fields.next()?.parse().ok()?;
+        let token = fields.next()?;
+        if token.is_empty() || domain.is_empty() {
+            return None;
+        }
+
+        Some(MessageIdParts {
+            timestamp: DateTime::from_naive_utc_and_offset(timestamp, Utc),
+            sequence,
+            token: token.to_string(),
+            domain: domain.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = Infallible;
+
+    /// Accepts ids with or without the surrounding `<>`, such as from events.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bare = s.trim().trim_start_matches('<').trim_end_matches('>');
+        Ok(Self(format!("<{}>", bare)))
+    }
+}
+
+impl From<MessageId> for String {
+    fn from(id: MessageId) -> Self {
+        id.0
+    }
+}
+
+impl AsRef<str> for MessageId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parse_message_id() {
+        let id: MessageId = "20210224131116.1.E5C867B3818DC87B@fakedomain"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            id.as_str(),
+            "<20210224131116.1.E5C867B3818DC87B@fakedomain>"
+        );
+        assert_eq!(id.bare(), "20210224131116.1.E5C867B3818DC87B@fakedomain");
+
+        let parts = id.parts().unwrap();
+        assert_eq!(
+            parts.timestamp,
+            Utc.with_ymd_and_hms(2021, 2, 24, 13, 11, 16).unwrap()
+        );
+        assert_eq!(parts.sequence, 1);
+        assert_eq!(parts.token, "E5C867B3818DC87B");
+        assert_eq!(parts.domain, "fakedomain");
+
+        assert_eq!(MessageId("<1@mock>".into()).parts(), None);
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{Email, SendError, SendInfo, SendReceipt};
+
+/// Hooks around every send of a Mailer, for auditing, rewriting emails or refusing
+/// to send some of them. Added with `MailerBuilder::middleware`, `before` runs in the
+/// order middleware was added, `after` in the reverse order.
+///
+/// ```
+/// use async_trait::async_trait;
+/// use mailgun46::{Email, MailerBuilder, SendError, SendMiddleware};
+///
+/// /// Marks every email with the environment it was sent from.
+/// #[derive(Debug)]
+/// struct Environment(&'static str);
+///
+/// #[async_trait]
+/// impl SendMiddleware for Environment {
+///     async fn before(&self, email: Email) -> Result<Email, SendError> {
+///         email
+///             .into_builder()
+///             .header("X-Environment", self.0)
+///             .build()
+///             .map_err(SendError::InvalidEmail)
+///     }
+/// }
+///
+/// # fn example() -> Result<(), mailgun46::SetupError> {
+/// let mailer = MailerBuilder::new("example.com", "token")
+///     .middleware(Environment("staging"))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[async_trait]
+pub trait SendMiddleware: fmt::Debug + Send + Sync {
+    /// Runs before sending, once the Mailer's defaults are applied, returning the email
+    /// to send. The Mailer's `RecipientPolicy` applies to the returned email.
+    /// Failing fails the send without running later middleware, sending or
+    /// running any `after`.
+    async fn before(&self, email: Email) -> Result<Email, SendError> {
+        Ok(email)
+    }
+
+    /// Runs once the send succeeded or failed.
+    async fn after(&self, _info: &SendInfo, _result: &Result<SendReceipt, SendError>) {}
+}
+
+#[async_trait]
+impl<T: SendMiddleware + ?Sized> SendMiddleware for Arc<T> {
+    async fn before(&self, email: Email) -> Result<Email, SendError> {
+        (**self).before(email).await
+    }
+
+    async fn after(&self, info: &SendInfo, result: &Result<SendReceipt, SendError>) {
+        (**self).after(info, result).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{BuildError, EmailBuilder, MailerBuilder, RecipientPolicy};
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[derive(Debug, Default)]
+    struct Audit(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl SendMiddleware for Audit {
+        async fn before(&self, email: Email) -> Result<Email, SendError> {
+            if email.to.contains("@blocked.example.com") {
+                return Err(SendError::InvalidEmail(BuildError::InvalidField(
+                    "to",
+                    "blocked".into(),
+                )));
+            }
+            email
+                .into_builder()
+                .header("X-Audited", "yes")
+                .build()
+                .map_err(SendError::InvalidEmail)
+        }
+
+        async fn after(&self, info: &SendInfo, result: &Result<SendReceipt, SendError>) {
+            let outcome = match result {
+                Ok(receipt) => receipt.id.to_string(),
+                Err(err) => err.to_string(),
+            };
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", info.recipients, outcome));
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_around_sends() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .and(matchers::body_string_contains("h%3AX-Audited=yes"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let audit = Arc::new(Audit::default());
+        let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .middleware(audit.clone())
+            .build()
+            .expect("Creating Mailer");
+
+        let send = |to: &str| {
+            EmailBuilder::default()
+                .to(to)
+                .build()
+                .unwrap()
+                .send(&mailer)
+        };
+        send("someone@example.com").await.expect("Sending email");
+        let err = send("someone@blocked.example.com").await.unwrap_err();
+        assert_eq!(
+            err,
+            SendError::InvalidEmail(BuildError::InvalidField("to", "blocked".into()))
+        );
+
+        assert_eq!(*audit.0.lock().unwrap(), ["1 <id@fakedomain>"]);
+    }
+
+    /// Copies every email to an outside address.
+    #[derive(Debug)]
+    struct Archive;
+
+    #[async_trait]
+    impl SendMiddleware for Archive {
+        async fn before(&self, email: Email) -> Result<Email, SendError> {
+            email
+                .into_builder()
+                .to("archive@elsewhere.example.org")
+                .build()
+                .map_err(SendError::InvalidEmail)
+        }
+    }
+
+    #[tokio::test]
+    async fn recipient_policy_applies_after_middleware() {
+        let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+            .recipient_policy(RecipientPolicy::allow(["example.com"]))
+            .middleware(Archive)
+            .build()
+            .expect("Creating Mailer");
+
+        let err = EmailBuilder::default()
+            .to("someone@example.com")
+            .build()
+            .unwrap()
+            .send(&mailer)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            SendError::BlockedByPolicy("archive@elsewhere.example.org".into())
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use crate::{Email, MessageId, SendError};
+
+/// Callbacks around every send of a Mailer, for metrics such as Prometheus counters.
+/// All methods default to doing nothing.
+///
+/// With the `tracing` feature sends are also recorded as `mailgun46.send` spans, and the
+/// posted form fields are logged at debug level with addresses and bodies redacted.
+pub trait Observer: fmt::Debug + Send + Sync {
+    fn on_send_start(&self, _info: &SendInfo) {}
+
+    fn on_send_success(&self, _info: &SendInfo, _id: &MessageId, _elapsed: Duration) {}
+
+    fn on_send_failure(&self, _info: &SendInfo, _err: &SendError, _elapsed: Duration) {}
+}
+
+impl<T: Observer + ?Sized> Observer for Arc<T> {
+    fn on_send_start(&self, info: &SendInfo) {
+        (**self).on_send_start(info)
+    }
+
+    fn on_send_success(&self, info: &SendInfo, id: &MessageId, elapsed: Duration) {
+        (**self).on_send_success(info, id, elapsed)
+    }
+
+    fn on_send_failure(&self, info: &SendInfo, err: &SendError, elapsed: Duration) {
+        (**self).on_send_failure(info, err, elapsed)
+    }
+}
+
+/// What is being sent, passed to each Observer callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendInfo {
+    pub domain: String,
+    pub recipients: usize,
+    /// Size of the encoded form in bytes.
+    pub size: usize,
+}
+
+impl SendInfo {
+    pub(crate) fn new(domain: &str, email: &Email, size: usize) -> Self {
+        Self {
+            domain: domain.to_string(),
+            recipients: email.recipient_count(),
+            size,
+        }
+    }
+}
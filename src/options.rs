@@ -0,0 +1,201 @@
+use crate::{
+    email::{bare_address, insert_header, serialize_yes_no, split_recipients},
+    Email, SendError,
+};
+
+/// Per message delivery options, attached with `EmailBuilder::options`.
+/// Unset options fall back to the domain settings in Mailgun.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SendOptions {
+    #[serde(
+        rename = "o:dkim",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_yes_no"
+    )]
+    pub(crate) dkim: Option<bool>,
+
+    #[serde(
+        rename = "o:require-tls",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_yes_no"
+    )]
+    pub(crate) require_tls: Option<bool>,
+
+    #[serde(
+        rename = "o:skip-verification",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_yes_no"
+    )]
+    pub(crate) skip_verification: Option<bool>,
+
+    #[serde(rename = "o:secondary-dkim", skip_serializing_if = "Option::is_none")]
+    pub(crate) dkim_signature: Option<String>,
+
+    #[serde(rename = "o:sending-ip-pool", skip_serializing_if = "Option::is_none")]
+    pub(crate) ip_pool: Option<String>,
+}
+
+impl SendOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signs the message with DKIM.
+    pub fn dkim(mut self, enabled: bool) -> Self {
+        self.dkim = Some(enabled);
+        self
+    }
+
+    /// Only delivers over TLS, the message fails instead of falling back to plain text.
+    pub fn require_tls(mut self, required: bool) -> Self {
+        self.require_tls = Some(required);
+        self
+    }
+
+    /// Delivers over TLS without verifying the certificate of the receiving server.
+    pub fn skip_verification(mut self, skip: bool) -> Self {
+        self.skip_verification = Some(skip);
+        self
+    }
+
+    /// Also signs the message with a DKIM key of another domain, given as
+    /// `signing_domain/selector` such as `example.com/s1`, or only a domain
+    /// with a key in Mailgun.
+    pub fn dkim_signature(mut self, domain: impl Into<String>) -> Self {
+        self.dkim_signature = Some(domain.into());
+        self
+    }
+
+    /// Sends from the dedicated IP pool with this id, instead of the domain's pool.
+    pub fn ip_pool(mut self, id: impl Into<String>) -> Self {
+        self.ip_pool = Some(id.into());
+        self
+    }
+}
+
+/// Whether a Mailer requires TLS delivery of every message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TlsPolicy {
+    /// Messages follow their own options and the domain settings.
+    #[default]
+    Opportunistic,
+    /// Every message is sent with `o:require-tls`, sending a message that disables
+    /// it fails with `SendError::InvalidEmail`.
+    Required,
+}
+
+/// What a Mailer does with emails built without a subject.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SubjectPolicy {
+    /// The email is sent without a subject.
+    #[default]
+    Omit,
+    /// Sending fails with `SendError::InvalidEmail(BuildError::MissingField("subject"))`.
+    Reject,
+    /// The email is sent with this subject instead.
+    Fallback(String),
+}
+
+impl SubjectPolicy {
+    /// Falls back to `no subject`, the subject emails used to be built with.
+    pub fn no_subject() -> Self {
+        Self::Fallback("no subject".into())
+    }
+}
+
+/// Who a Mailer sends to, so staging environments never email real customers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RecipientPolicy {
+    /// Emails are sent to their recipients.
+    #[default]
+    AllowAll,
+    /// Only sends to these addresses, and to any address at these domains. Emails with
+    /// other recipients fail with `SendError::BlockedByPolicy`, reaching no one.
+    AllowList(Vec<String>),
+    /// Sends every email to this address instead, the original recipients are kept in
+    /// the `X-Original-To` header. A batch reaches the address once.
+    CatchAll(String),
+}
+
+impl RecipientPolicy {
+    /// An allow list of addresses such as `qa@example.com` and domains such as `example.com`.
+    pub fn allow<I>(entries: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self::AllowList(entries.into_iter().map(Into::into).collect())
+    }
+
+    pub fn catch_all(address: impl Into<String>) -> Self {
+        Self::CatchAll(address.into())
+    }
+
+    /// The recipients to send to instead of `to`, failing for those not allowed.
+    pub(crate) fn recipients(&self, to: &str) -> Result<String, SendError> {
+        match self {
+            Self::AllowAll => Ok(to.to_string()),
+            Self::AllowList(entries) => {
+                for recipient in split_recipients(to) {
+                    let address = bare_address(&recipient).to_ascii_lowercase();
+                    let domain = address.rsplit_once('@').map_or("", |(_, domain)| domain);
+                    let allowed = entries.iter().any(|entry| {
+                        let entry = entry.trim().to_ascii_lowercase();
+                        entry == address || entry == domain
+                    });
+                    if !allowed {
+                        return Err(SendError::BlockedByPolicy(address));
+                    }
+                }
+                Ok(to.to_string())
+            }
+            Self::CatchAll(address) => Ok(address.clone()),
+        }
+    }
+
+    /// Checks or rewrites the recipients of the email.
+    pub(crate) fn apply(&self, email: &mut Email) -> Result<(), SendError> {
+        let to = self.recipients(&email.to)?;
+        let original = std::mem::replace(&mut email.to, to);
+        if let Self::CatchAll(address) = self {
+            // Variables are keyed on the recipient, keep the first recipient's.
+            let variables = split_recipients(&original)
+                .iter()
+                .find_map(|recipient| email.recipient_variables.remove(bare_address(recipient)));
+            email.recipient_variables.clear();
+            if let Some(variables) = variables {
+                let key = bare_address(address).to_string();
+                email.recipient_variables.insert(key, variables);
+            }
+            insert_header(&mut email.headers, "X-Original-To".into(), original);
+        }
+        Ok(())
+    }
+}
+
+/// How mail clients flag a message, sent as `X-Priority` and `Importance` headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub(crate) fn x_priority(self) -> &'static str {
+        match self {
+            Self::High => "1 (Highest)",
+            Self::Normal => "3 (Normal)",
+            Self::Low => "5 (Lowest)",
+        }
+    }
+
+    pub(crate) fn importance(self) -> &'static str {
+        match self {
+            Self::High => "High",
+            Self::Normal => "Normal",
+            Self::Low => "Low",
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use futures_util::{stream, Stream};
+use serde::de::DeserializeOwned;
+
+use crate::{Mailer, SendError};
+
+/// A page of items from a paginated Mailgun endpoint, along with the cursor to the next page.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    next: Option<reqwest::Url>,
+}
+
+impl<T: DeserializeOwned> Page<T> {
+    /// Fetches the following page, None when there are no more items.
+    pub async fn next_page(&self, mailer: &Mailer) -> Result<Option<Page<T>>, SendError> {
+        match &self.next {
+            Some(url) => mailer.execute_page(mailer.get(url.clone())).await.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct PageReply<T> {
+    items: Vec<T>,
+    paging: Paging,
+}
+
+#[derive(serde::Deserialize)]
+struct Paging {
+    next: Option<String>,
+}
+
+impl<T> From<PageReply<T>> for Page<T> {
+    /// Mailgun always returns a next url, an empty page marks the end.
+    fn from(reply: PageReply<T>) -> Self {
+        let next = if reply.items.is_empty() {
+            None
+        } else {
+            reply.paging.next.and_then(|url| url.parse().ok())
+        };
+        Self {
+            items: reply.items,
+            next,
+        }
+    }
+}
+
+impl Mailer {
+    pub(crate) async fn execute_page<T>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<Page<T>, SendError>
+    where
+        T: DeserializeOwned,
+    {
+        let reply: PageReply<T> = self.execute(request).await?;
+        Ok(reply.into())
+    }
+
+    /// The items of every page, starting from the page `request` fetches.
+    /// The stream ends after the first error.
+    pub(crate) fn execute_stream<'a, T>(
+        &'a self,
+        request: reqwest::RequestBuilder,
+    ) -> impl Stream<Item = Result<T, SendError>> + 'a
+    where
+        T: DeserializeOwned + 'a,
+    {
+        enum State<T> {
+            First(reqwest::RequestBuilder),
+            /// Items are reversed, so they can be popped in order.
+            Page(Page<T>),
+            Done,
+        }
+
+        stream::unfold(State::First(request), move |mut state| async move {
+            loop {
+                let next = match state {
+                    State::First(request) => self.execute_page(request).await.map(Some),
+                    State::Page(mut page) => match page.items.pop() {
+                        Some(item) => return Some((Ok(item), State::Page(page))),
+                        None => page.next_page(self).await,
+                    },
+                    State::Done => return None,
+                };
+                state = match next {
+                    Ok(Some(mut page)) => {
+                        page.items.reverse();
+                        State::Page(page)
+                    }
+                    Ok(None) => State::Done,
+                    Err(err) => return Some((Err(err), State::Done)),
+                };
+            }
+        })
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{Mailer, MailerBuilder, SetupError};
+
+/// Mailers for many domains sharing one client, its connection pool and the token.
+/// Each domain gets its own Mailer on first use, built with the settings of the
+/// builder passed to `MailerBuilder::build_pool`. Rate limits, idempotency keys and
+/// suppression guards apply per domain.
+///
+/// ```
+/// use mailgun46::{EmailBuilder, Mailer};
+/// # async fn example() -> Result<(), Box<dyn std::error::Error + 'static>> {
+/// let pool = Mailer::builder("mg.example.com", "token")
+///     .build_pool()?
+///     .default_from("mg.customer.com", "Customer <support@mg.customer.com>");
+///
+/// EmailBuilder::default()
+///     .to("someone@example.com")
+///     .build()?
+///     .send(&*pool.mailer("mg.customer.com")?)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MailerPool {
+    builder: MailerBuilder,
+    from: HashMap<String, String>,
+    mailers: Mutex<HashMap<String, Arc<Mailer>>>,
+}
+
+impl MailerPool {
+    pub(crate) fn new(builder: MailerBuilder, from: HashMap<String, String>) -> Self {
+        Self {
+            builder,
+            from,
+            mailers: Mutex::default(),
+        }
+    }
+
+    /// Fails early for settings that would fail every domain.
+    pub(crate) fn validate(&self) -> Result<(), SetupError> {
+        self.builder.clone().build().map(drop)
+    }
+
+    /// The default from address for a domain, `noreply@<domain>` unless set.
+    pub fn default_from(mut self, domain: impl Into<String>, from: impl Into<String>) -> Self {
+        let domain = domain.into();
+        self.mailers
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&domain);
+        self.from.insert(domain, from.into());
+        self
+    }
+
+    /// The Mailer for the domain, built on first use.
+    pub fn mailer(&self, domain: &str) -> Result<Arc<Mailer>, SetupError> {
+        let mut mailers = self
+            .mailers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(mailer) = mailers.get(domain) {
+            return Ok(mailer.clone());
+        }
+        let mailer = Arc::new(
+            self.builder
+                .for_domain(domain, self.from.get(domain).cloned())
+                .build()?,
+        );
+        mailers.insert(domain.to_string(), mailer.clone());
+        Ok(mailer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EmailBuilder, Mailer};
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn sends_per_domain() {
+        let server = MockServer::start().await;
+        for (domain, from) in [
+            ("fakedomain", "support%40fakedomain"),
+            ("customer.com", "hello%40customer.com"),
+            ("other.com", "noreply%40other.com"),
+        ] {
+            Mock::given(matchers::method("POST"))
+                .and(matchers::path(format!("/v3/{}/messages", domain)))
+                .and(matchers::header(
+                    "Authorization",
+                    "Basic YXBpOnRvbWF0b3Rva2Vu",
+                ))
+                .and(matchers::body_string_contains(format!("from={}", from)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(serde_json::json!({"id": format!("<1@{}>", domain)})),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let pool = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .from("support@fakedomain")
+            .build_pool()
+            .expect("Creating pool")
+            .default_from("customer.com", "hello@customer.com");
+
+        for domain in ["fakedomain", "customer.com", "other.com"] {
+            let mailer = pool.mailer(domain).expect("Creating Mailer");
+            assert_eq!(mailer.domain(), domain);
+            EmailBuilder::default()
+                .to("someone@example.com")
+                .build()
+                .unwrap()
+                .send(&mailer)
+                .await
+                .expect("Sending");
+        }
+        assert!(std::sync::Arc::ptr_eq(
+            &pool.mailer("other.com").unwrap(),
+            &pool.mailer("other.com").unwrap()
+        ));
+        assert!(pool.mailer("not/a/domain").is_err());
+    }
+}
@@ -0,0 +1,484 @@
+//! Background sending: emails are enqueued and a tokio task sends them, reporting each
+//! outcome on a completion stream. Emails that fail for good can also be captured with
+//! `QueueConfig::dead_letter`, for persisting and alerting.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use futures_util::StreamExt;
+//! use mailgun46::{queue::{MailQueue, QueueConfig}, EmailBuilder, Mailer};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let (queue, mut completions) = MailQueue::start(Arc::new(mailer), QueueConfig::default());
+//! queue.enqueue(EmailBuilder::default().to("someone@example.com").build()?).await?;
+//! queue.finish().await;
+//!
+//! while let Some((email, result)) = completions.next().await {
+//!     println!("{:?}: {:?}", email, result);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::{stream, Stream, StreamExt};
+use tokio::{sync::mpsc, task::JoinHandle, time};
+
+use crate::{Email, Mailer, MessageId, RetryPolicy, SendError};
+
+/// How a MailQueue sends, see the setters for the defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueConfig {
+    capacity: usize,
+    concurrency: usize,
+    rate_limit: Option<f64>,
+    retry: Option<RetryPolicy>,
+    dead_letters: Option<DeadLetterSink>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            concurrency: 4,
+            rate_limit: None,
+            retry: None,
+            dead_letters: None,
+        }
+    }
+}
+
+impl QueueConfig {
+    /// Number of emails waiting to be sent before `enqueue` waits, defaults to 1024.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Number of emails sent at the same time, defaults to 4.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Starts at most this many sends per second, unlimited by default.
+    /// Retries are not counted.
+    pub fn rate_limit(mut self, per_second: f64) -> Self {
+        self.rate_limit = Some(per_second).filter(|rate| *rate > 0.0);
+        self
+    }
+
+    /// Retries sends failing with an error that `SendError::is_retryable`, such as 429,
+    /// 5xx, timeouts and lost connections, waiting at least as long as Mailgun asked.
+    /// Off by default.
+    ///
+    /// Each attempt is a full `Email::send`, so a `MailerBuilder::retry` policy of the
+    /// Mailer runs inside every attempt and the attempts multiply: three queue attempts
+    /// of a Mailer making three can send nine requests. Retry in one place, usually here
+    /// with a Mailer without a retry policy.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Calls `sink` with every email that failed for good, after any retries.
+    /// The failure is reported on the completion stream as well.
+    pub fn dead_letter<F>(mut self, sink: F) -> Self
+    where
+        F: Fn(DeadLetter) + Send + Sync + 'static,
+    {
+        self.dead_letters = Some(DeadLetterSink(Arc::new(sink)));
+        self
+    }
+
+    /// Sends every email that failed for good to the channel, same as `dead_letter`.
+    pub fn dead_letter_channel(self, sender: mpsc::UnboundedSender<DeadLetter>) -> Self {
+        self.dead_letter(move |letter| {
+            let _ = sender.send(letter);
+        })
+    }
+}
+
+/// An email the queue gave up on, along with the error of its last attempt.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub email: Email,
+    pub error: SendError,
+    /// Number of sends made, including retries.
+    pub attempts: u32,
+}
+
+#[derive(Clone)]
+struct DeadLetterSink(Arc<dyn Fn(DeadLetter) + Send + Sync>);
+
+impl fmt::Debug for DeadLetterSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DeadLetterSink")
+    }
+}
+
+impl PartialEq for DeadLetterSink {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Handle for enqueueing emails, sent in the background by a tokio task.
+#[derive(Debug)]
+pub struct MailQueue {
+    sender: mpsc::Sender<Email>,
+    task: JoinHandle<()>,
+    counts: Arc<Counts>,
+}
+
+/// Emails enqueued and completed so far, the difference is still queued or in flight.
+#[derive(Debug, Default)]
+struct Counts {
+    enqueued: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+impl Counts {
+    fn pending(&self) -> usize {
+        let completed = self.completed.load(Ordering::SeqCst);
+        self.enqueued
+            .load(Ordering::SeqCst)
+            .saturating_sub(completed)
+    }
+}
+
+/// What happened to the emails still pending when `MailQueue::shutdown` was called,
+/// or to the recipients of a batch for `BatchHandle::shutdown`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Sent, or failed for good, before the deadline.
+    pub flushed: usize,
+    /// Still queued or in flight at the deadline, these were never reported as completed.
+    pub abandoned: usize,
+}
+
+/// Outcome of each queued email, in completion order.
+pub type Completion = (Email, Result<MessageId, SendError>);
+
+/// Stream of completions. Dropping it does not stop the queue, outcomes are then discarded.
+#[derive(Debug)]
+pub struct Completions {
+    receiver: mpsc::UnboundedReceiver<Completion>,
+}
+
+impl Stream for Completions {
+    type Item = Completion;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl MailQueue {
+    /// Spawns the sending task, must be called within a tokio runtime.
+    /// The completion stream ends once the queue is finished and drained.
+    pub fn start(mailer: Arc<Mailer>, config: QueueConfig) -> (Self, Completions) {
+        let (sender, receiver) = mpsc::channel(config.capacity);
+        let (completed, completions) = mpsc::unbounded_channel();
+        let counts = Arc::new(Counts::default());
+        let task = tokio::spawn(drain(mailer, config, receiver, completed, counts.clone()));
+
+        (
+            Self {
+                sender,
+                task,
+                counts,
+            },
+            Completions {
+                receiver: completions,
+            },
+        )
+    }
+
+    /// Adds the email to the queue, waiting while the queue is full.
+    pub async fn enqueue(&self, email: Email) -> Result<(), QueueClosed> {
+        self.sender
+            .send(email)
+            .await
+            .map_err(|err| QueueClosed(err.0))?;
+        self.counts.enqueued.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Stops accepting emails and waits until all enqueued ones are sent.
+    pub async fn finish(self) {
+        drop(self.sender);
+        let _ = self.task.await;
+    }
+
+    /// Stops accepting emails and sends the enqueued ones until `deadline` has passed.
+    /// Emails not sent by then are dropped, sends in flight are cancelled.
+    pub async fn shutdown(self, deadline: Duration) -> ShutdownReport {
+        let Self {
+            sender,
+            mut task,
+            counts,
+        } = self;
+        let pending = counts.pending();
+        drop(sender);
+
+        if time::timeout(deadline, &mut task).await.is_err() {
+            task.abort();
+            let _ = task.await;
+        }
+        let abandoned = counts.pending();
+        ShutdownReport {
+            flushed: pending.saturating_sub(abandoned),
+            abandoned,
+        }
+    }
+}
+
+async fn drain(
+    mailer: Arc<Mailer>,
+    config: QueueConfig,
+    receiver: mpsc::Receiver<Email>,
+    completed: mpsc::UnboundedSender<Completion>,
+    counts: Arc<Counts>,
+) {
+    let ticker = config.rate_limit.map(|per_second| {
+        let mut ticker = time::interval(Duration::from_secs_f64(1.0 / per_second));
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        ticker
+    });
+
+    let emails = stream::unfold((receiver, ticker), |(mut receiver, mut ticker)| async {
+        let email = receiver.recv().await?;
+        if let Some(ticker) = &mut ticker {
+            ticker.tick().await;
+        }
+        Some((email, (receiver, ticker)))
+    });
+
+    let (mailer, retry, completed) = (&mailer, &config.retry, &completed);
+    let (dead_letters, counts) = (&config.dead_letters, &counts);
+    emails
+        .for_each_concurrent(config.concurrency, |email| async move {
+            let (result, attempts) = send_with_retry(mailer, retry.as_ref(), &email).await;
+            if let (Err(error), Some(sink)) = (&result, dead_letters) {
+                (sink.0)(DeadLetter {
+                    email: email.clone(),
+                    error: error.clone(),
+                    attempts,
+                });
+            }
+            let _ = completed.send((email, result));
+            counts.completed.fetch_add(1, Ordering::SeqCst);
+        })
+        .await;
+}
+
+/// Returns the result of the last attempt, along with the number of attempts made.
+async fn send_with_retry(
+    mailer: &Mailer,
+    retry: Option<&RetryPolicy>,
+    email: &Email,
+) -> (Result<MessageId, SendError>, u32) {
+    let mut attempt = 1;
+    loop {
+        let result = mailer.send(email.clone()).await;
+
+        let delay = match (&result, retry.filter(|p| p.should_retry(attempt))) {
+            (Err(err), Some(policy)) if err.is_retryable() => {
+                policy.delay(attempt, err.retry_after())
+            }
+            _ => return (result, attempt),
+        };
+
+        time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// The queue has stopped, returning the email that could not be enqueued.
+#[derive(Debug)]
+pub struct QueueClosed(pub Email);
+
+impl fmt::Display for QueueClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Mail queue is closed")
+    }
+}
+
+impl std::error::Error for QueueClosed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmailBuilder;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn drains_queue() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::body_string_contains("flaky"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let config = QueueConfig::default()
+            .concurrency(2)
+            .rate_limit(100.0)
+            .retry(RetryPolicy::default().base_delay(Duration::from_millis(1)));
+        let (queue, completions) = MailQueue::start(Arc::new(mailer), config);
+
+        for to in ["a@example.com", "flaky@example.com", "c@example.com"] {
+            let email = EmailBuilder::default().to(to).build().unwrap();
+            queue.enqueue(email).await.unwrap();
+        }
+        queue.finish().await;
+
+        let completions: Vec<Completion> = completions.collect().await;
+        assert_eq!(completions.len(), 3);
+        assert!(completions.iter().all(|(_, result)| result.is_ok()));
+        assert!(completions
+            .iter()
+            .any(|(email, _)| email.to == "flaky@example.com"));
+    }
+
+    #[tokio::test]
+    async fn retries_timeouts() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .send_timeout(Duration::from_millis(200))
+            .build()
+            .expect("Creating Mailer");
+        let config = QueueConfig::default()
+            .retry(RetryPolicy::default().base_delay(Duration::from_millis(1)));
+        let (queue, completions) = MailQueue::start(Arc::new(mailer), config);
+
+        let email = EmailBuilder::default().to("a@example.com").build().unwrap();
+        queue.enqueue(email).await.unwrap();
+        queue.finish().await;
+
+        let completions: Vec<Completion> = completions.collect().await;
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].1, Ok(MessageId("<id@fakedomain>".into())));
+    }
+
+    #[tokio::test]
+    async fn captures_dead_letters() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::body_string_contains("down"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let (sender, mut dead_letters) = mpsc::unbounded_channel();
+        let config = QueueConfig::default()
+            .retry(
+                RetryPolicy::default()
+                    .max_attempts(3)
+                    .base_delay(Duration::from_millis(1)),
+            )
+            .dead_letter_channel(sender);
+        let (queue, completions) = MailQueue::start(Arc::new(mailer), config);
+
+        for to in ["a@example.com", "down@example.com"] {
+            let email = EmailBuilder::default().to(to).build().unwrap();
+            queue.enqueue(email).await.unwrap();
+        }
+        queue.finish().await;
+
+        let letter = dead_letters.recv().await.expect("A dead letter");
+        assert_eq!(letter.email.to, "down@example.com");
+        assert_eq!(letter.attempts, 3);
+        assert!(matches!(letter.error, SendError::ServerError { .. }));
+        assert!(dead_letters.recv().await.is_none());
+        assert_eq!(completions.collect::<Vec<_>>().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn shutdown_with_deadline() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::body_string_contains("slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_delay(Duration::from_millis(100))
+                    .set_body_json(serde_json::json!({"id": "<id@fakedomain>"})),
+            )
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let (queue, completions) =
+            MailQueue::start(Arc::new(mailer), QueueConfig::default().concurrency(1));
+
+        for to in ["a@example.com", "slow@example.com", "c@example.com"] {
+            let email = EmailBuilder::default().to(to).build().unwrap();
+            queue.enqueue(email).await.unwrap();
+        }
+        let report = queue.shutdown(Duration::from_millis(1000)).await;
+
+        assert_eq!(
+            report,
+            ShutdownReport {
+                flushed: 1,
+                abandoned: 2
+            }
+        );
+        assert_eq!(completions.collect::<Vec<_>>().await.len(), 1);
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Token bucket holding up to a minute worth of sends, refilled continuously.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    per_second: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while sends are waiting for tokens reserved ahead of time.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn per_minute(per_minute: u32) -> Self {
+        let capacity = f64::from(per_minute.max(1));
+        Self {
+            per_second: capacity / 60.0,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token, returning how long to wait before sending.
+    pub(crate) fn reserve(&self) -> Duration {
+        let mut bucket = self
+            .bucket
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.capacity) - 1.0;
+        bucket.updated = now;
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.per_second)
+        }
+    }
+
+    pub(crate) async fn acquire(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserves_tokens() {
+        let limiter = RateLimiter::per_minute(2);
+        assert_eq!(limiter.reserve(), Duration::ZERO);
+        assert_eq!(limiter.reserve(), Duration::ZERO);
+
+        let third = limiter.reserve();
+        assert!(third > Duration::from_secs(29) && third <= Duration::from_secs(30));
+        let fourth = limiter.reserve();
+        assert!(fourth > Duration::from_secs(59) && fourth <= Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,30 @@
+use chrono::{DateTime, Utc};
+
+use crate::MessageId;
+
+/// What Mailgun replied when accepting a message, see `Email::send_with_receipt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendReceipt {
+    pub id: MessageId,
+
+    /// Mailgun's reply, such as `Queued. Thank you.`, empty for other transports.
+    pub message: String,
+
+    /// The HTTP status of the reply, None for transports not talking HTTP.
+    pub status: Option<reqwest::StatusCode>,
+
+    /// When the reply was sent, from its `Date` header when present.
+    pub received_at: DateTime<Utc>,
+}
+
+impl SendReceipt {
+    /// A receipt holding only the id, received now.
+    pub fn new(id: MessageId) -> Self {
+        Self {
+            id,
+            message: String::new(),
+            status: None,
+            received_at: Utc::now(),
+        }
+    }
+}
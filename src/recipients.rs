@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+
+use crate::{email::split_recipients, AddressError, EmailAddress};
+
+/// A list of parsed recipient addresses without duplicates, compared ignoring case.
+/// Entries that fail to parse are kept aside and fail `EmailBuilder::build` with
+/// `BuildError::InvalidRecipient`, so a list can be collected without checking each entry.
+/// The `email-address` feature checks addresses against RFC 5322 as well.
+///
+/// ```
+/// use mailgun46::{BuildError, EmailBuilder, Recipients};
+///
+/// let recipients: Recipients = ["a@example.com", "A@Example.com", "\"B\" <b@example.com>"]
+///     .into_iter()
+///     .collect();
+/// assert_eq!(recipients.len(), 2);
+///
+/// let err = EmailBuilder::default()
+///     .recipients(Recipients::parse("c@example.com, not an address"))
+///     .build()
+///     .unwrap_err();
+/// assert!(matches!(err, BuildError::InvalidRecipient(_)));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recipients {
+    addresses: Vec<EmailAddress>,
+    /// Lowercased bare addresses, for deduplication.
+    seen: HashSet<String>,
+    invalid: Vec<AddressError>,
+}
+
+impl Recipients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a comma separated list, such as the value of a `To` header.
+    pub fn parse(list: &str) -> Self {
+        split_recipients(list).iter().map(|r| r.trim()).collect()
+    }
+
+    /// Adds an address, returning false for duplicates and for addresses that fail to parse.
+    pub fn push(&mut self, recipient: impl AsRef<str>) -> bool {
+        match self.try_push(recipient) {
+            Ok(added) => added,
+            Err(err) => {
+                self.invalid.push(err);
+                false
+            }
+        }
+    }
+
+    /// Adds an address, failing right away if it does not parse.
+    /// Returns false for duplicates.
+    pub fn try_push(&mut self, recipient: impl AsRef<str>) -> Result<bool, AddressError> {
+        let address: EmailAddress = recipient.as_ref().parse()?;
+        Ok(self.insert(address))
+    }
+
+    /// Adds an already parsed address, returning false for duplicates.
+    pub fn insert(&mut self, address: EmailAddress) -> bool {
+        if !self.seen.insert(address.email().to_lowercase()) {
+            return false;
+        }
+        self.addresses.push(address);
+        true
+    }
+
+    pub fn contains(&self, email: &str) -> bool {
+        self.seen.contains(&email.to_lowercase())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &EmailAddress> {
+        self.addresses.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// The entries that failed to parse.
+    pub fn invalid(&self) -> &[AddressError] {
+        &self.invalid
+    }
+
+    pub(crate) fn into_parts(self) -> (Vec<EmailAddress>, Vec<AddressError>) {
+        (self.addresses, self.invalid)
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for Recipients {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut recipients = Self::new();
+        recipients.extend(iter);
+        recipients
+    }
+}
+
+impl<S: AsRef<str>> Extend<S> for Recipients {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, iter: I) {
+        for recipient in iter {
+            self.push(recipient);
+        }
+    }
+}
+
+impl From<Vec<EmailAddress>> for Recipients {
+    fn from(addresses: Vec<EmailAddress>) -> Self {
+        let mut recipients = Self::new();
+        for address in addresses {
+            recipients.insert(address);
+        }
+        recipients
+    }
+}
+
+impl<'a> IntoIterator for &'a Recipients {
+    type Item = &'a EmailAddress;
+    type IntoIter = std::slice::Iter<'a, EmailAddress>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.addresses.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedupes_ignoring_case() {
+        let mut recipients =
+            Recipients::parse(r#""Doe, John" <John@Example.com>, jane@example.com"#);
+        assert_eq!(recipients.len(), 2);
+        assert!(!recipients.push("john@example.com"));
+        assert!(recipients.push("joe@example.com"));
+        assert!(recipients.contains("JANE@example.com"));
+        assert_eq!(recipients.try_push("jane@example.com"), Ok(false));
+        assert!(recipients.try_push("jane").is_err());
+        assert!(recipients.invalid().is_empty());
+
+        assert!(!recipients.push("not an address"));
+        assert_eq!(recipients.invalid()[0].address, "not an address");
+        assert_eq!(
+            recipients.iter().next().unwrap().to_string(),
+            "\"Doe, John\" <John@Example.com>"
+        );
+    }
+}
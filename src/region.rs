@@ -0,0 +1,68 @@
+use std::{fmt, str::FromStr};
+
+/// The Mailgun region a domain is hosted in, determining the API base url.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Region {
+    /// `https://api.eu.mailgun.net`
+    #[default]
+    Eu,
+    /// `https://api.mailgun.net`
+    Us,
+    /// Any other base url, such as a proxy or a mock server.
+    Custom(reqwest::Url),
+}
+
+impl Region {
+    pub fn base_url(&self) -> reqwest::Url {
+        match self {
+            Self::Eu => "https://api.eu.mailgun.net".parse().expect("Valid url"),
+            Self::Us => "https://api.mailgun.net".parse().expect("Valid url"),
+            Self::Custom(url) => url.clone(),
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    /// Parses `eu`, `us` or a base url.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "eu" => Ok(Self::Eu),
+            "us" => Ok(Self::Us),
+            _ => s
+                .parse()
+                .map(Self::Custom)
+                .map_err(|err| format!("expected `eu`, `us` or a url: {}", err)),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Eu => f.write_str("eu"),
+            Self::Us => f.write_str("us"),
+            Self::Custom(url) => url.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_region() {
+        assert_eq!("EU".parse::<Region>(), Ok(Region::Eu));
+        assert_eq!("us".parse::<Region>(), Ok(Region::Us));
+        assert_eq!(
+            "http://localhost:8080"
+                .parse::<Region>()
+                .unwrap()
+                .base_url(),
+            "http://localhost:8080".parse().unwrap()
+        );
+        assert!("moon".parse::<Region>().is_err());
+    }
+}
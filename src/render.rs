@@ -0,0 +1,70 @@
+//! Rendering local handlebars templates into email bodies.
+//!
+//! Handlebars is the default engine of Mailgun's stored templates, so templates can
+//! move between the app and Mailgun unchanged. Rendering is strict: a variable missing
+//! from the context fails `build` rather than rendering as empty.
+use handlebars::Handlebars;
+use serde::Serialize;
+
+use crate::BuildError;
+
+/// Renders html escaping the values, and text as is.
+pub(crate) fn render<T>(
+    field: &'static str,
+    template: &str,
+    context: &T,
+    escape: bool,
+) -> Result<String, BuildError>
+where
+    T: Serialize,
+{
+    let mut registry = Handlebars::new();
+    registry.set_strict_mode(true);
+    if !escape {
+        registry.register_escape_fn(handlebars::no_escape);
+    }
+    registry
+        .render_template(template, context)
+        .map_err(|err| BuildError::InvalidField(field, err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BuildError, EmailBuilder};
+
+    #[derive(serde::Serialize)]
+    struct Order {
+        name: &'static str,
+        items: Vec<&'static str>,
+    }
+
+    #[test]
+    fn renders_bodies() {
+        let order = Order {
+            name: "Fish & Chips",
+            items: vec!["cod", "fries"],
+        };
+        let email = EmailBuilder::default()
+            .to("someoneelse")
+            .render_html(
+                "<p>{{name}}</p><ul>{{#each items}}<li>{{this}}</li>{{/each}}</ul>",
+                &order,
+            )
+            .render_text("{{name}}: {{#each items}}{{this}} {{/each}}", &order)
+            .build()
+            .unwrap();
+        let json = serde_json::to_value(&email).unwrap();
+        assert_eq!(
+            json["html"],
+            "<p>Fish &amp; Chips</p><ul><li>cod</li><li>fries</li></ul>"
+        );
+        assert_eq!(json["text"], "Fish & Chips: cod fries ");
+
+        let err = EmailBuilder::default()
+            .to("someoneelse")
+            .render_html("<p>{{missing}}</p>", &order)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::InvalidField("render_html", _)));
+    }
+}
@@ -0,0 +1,331 @@
+//! Routing inbound mail, `/v3/routes`.
+//!
+//! ```
+//! use mailgun46::{Mailer, routes::{Action, Expression, NewRoute}};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let route = NewRoute::new(Expression::MatchRecipient("support@example.com".into()))
+//!     .action(Action::Forward("https://example.com/inbound".into()))
+//!     .action(Action::Stop);
+//! mailer.create_route(&route).await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::{convert::Infallible, fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::de::IgnoredAny;
+
+use crate::{Mailer, SendError};
+
+/// Mailgun lists at most this many routes per request.
+const MAX_ROUTES: &str = "1000";
+
+/// Which messages a route applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    /// Recipient address matching a regular expression.
+    MatchRecipient(String),
+    /// Header with the given name matching a regular expression.
+    MatchHeader(String, String),
+    /// Every message, typically the lowest priority route.
+    CatchAll,
+    /// An expression not covered above, kept as written.
+    Other(String),
+}
+
+/// What a route does with matching messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Forwards to an email address or posts to a url.
+    Forward(String),
+    /// Stores the message for retrieval, optionally notifying a url.
+    Store { notify: Option<String> },
+    /// Skips routes of lower priority.
+    Stop,
+    /// An action not covered above, kept as written.
+    Other(String),
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MatchRecipient(pattern) => write!(f, "match_recipient(\"{}\")", pattern),
+            Self::MatchHeader(name, pattern) => {
+                write!(f, "match_header(\"{}\", \"{}\")", name, pattern)
+            }
+            Self::CatchAll => f.write_str("catch_all()"),
+            Self::Other(expression) => f.write_str(expression),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Forward(destination) => write!(f, "forward(\"{}\")", destination),
+            Self::Store { notify: Some(url) } => write!(f, "store(notify=\"{}\")", url),
+            Self::Store { notify: None } => f.write_str("store()"),
+            Self::Stop => f.write_str("stop()"),
+            Self::Other(action) => f.write_str(action),
+        }
+    }
+}
+
+/// Splits `name(args)` into name and the quoted arguments.
+fn parse_call(s: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, args) = s.trim().strip_suffix(')')?.split_once('(')?;
+    let args = args
+        .split(',')
+        .map(str::trim)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| {
+            let value = arg.split_once('=').map_or(arg, |(_, value)| value.trim());
+            value.trim_matches('"')
+        })
+        .collect();
+    Some((name.trim(), args))
+}
+
+impl FromStr for Expression {
+    type Err = Infallible;
+
+    /// Unknown expressions parse as `Expression::Other`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match parse_call(s) {
+            Some(("match_recipient", args)) if args.len() == 1 => {
+                Self::MatchRecipient(args[0].into())
+            }
+            Some(("match_header", args)) if args.len() == 2 => {
+                Self::MatchHeader(args[0].into(), args[1].into())
+            }
+            Some(("catch_all", args)) if args.is_empty() => Self::CatchAll,
+            _ => Self::Other(s.into()),
+        })
+    }
+}
+
+impl FromStr for Action {
+    type Err = Infallible;
+
+    /// Unknown actions parse as `Action::Other`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match parse_call(s) {
+            Some(("forward", args)) if args.len() == 1 => Self::Forward(args[0].into()),
+            Some(("store", args)) if args.len() <= 1 => Self::Store {
+                notify: args.first().map(|url| url.to_string()),
+            },
+            Some(("stop", args)) if args.is_empty() => Self::Stop,
+            _ => Self::Other(s.into()),
+        })
+    }
+}
+
+fn deserialize_parsed<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr<Err = Infallible>,
+{
+    let s: String = serde::Deserialize::deserialize(deserializer)?;
+    Ok(s.parse().unwrap_or_else(|err| match err {}))
+}
+
+fn deserialize_actions<'de, D>(deserializer: D) -> Result<Vec<Action>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let actions: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(actions
+        .iter()
+        .map(|action| action.parse().unwrap_or_else(|err| match err {}))
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct Route {
+    pub id: String,
+    pub priority: u32,
+
+    #[serde(default)]
+    pub description: String,
+
+    #[serde(deserialize_with = "deserialize_parsed")]
+    pub expression: Expression,
+
+    #[serde(deserialize_with = "deserialize_actions")]
+    pub actions: Vec<Action>,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A route to create or replace an existing one with.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct NewRoute {
+    /// Lower values are evaluated first, defaults to 0.
+    pub priority: u32,
+
+    pub description: String,
+
+    #[serde(serialize_with = "serialize_display")]
+    pub expression: Expression,
+
+    /// Sent as repeated `action` fields.
+    #[serde(flatten, serialize_with = "serialize_actions")]
+    pub actions: Vec<Action>,
+}
+
+impl NewRoute {
+    pub fn new(expression: Expression) -> Self {
+        Self {
+            priority: 0,
+            description: String::new(),
+            expression,
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn action(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+}
+
+fn serialize_display<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: fmt::Display,
+{
+    serializer.collect_str(value)
+}
+
+fn serialize_actions<S>(actions: &[Action], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_map(actions.iter().map(|action| ("action", action.to_string())))
+}
+
+#[derive(serde::Deserialize)]
+struct RoutesReply {
+    items: Vec<Route>,
+}
+
+#[derive(serde::Deserialize)]
+struct RouteReply {
+    route: Route,
+}
+
+impl Mailer {
+    /// Lists the routes on the account, up to 1000.
+    pub async fn routes(&self) -> Result<Vec<Route>, SendError> {
+        let reply: RoutesReply = self
+            .execute(
+                self.get(self.api_url(&["v3", "routes"]))
+                    .query(&[("limit", MAX_ROUTES)]),
+            )
+            .await?;
+        Ok(reply.items)
+    }
+
+    pub async fn route(&self, id: &str) -> Result<Route, SendError> {
+        let reply: RouteReply = self
+            .execute(self.get(self.api_url(&["v3", "routes", id])))
+            .await?;
+        Ok(reply.route)
+    }
+
+    pub async fn create_route(&self, route: &NewRoute) -> Result<Route, SendError> {
+        let reply: RouteReply = self
+            .execute(self.post(self.api_url(&["v3", "routes"])).form(route))
+            .await?;
+        Ok(reply.route)
+    }
+
+    /// Replaces the route with the given id, Mailgun replies with the updated route.
+    pub async fn update_route(&self, id: &str, route: &NewRoute) -> Result<Route, SendError> {
+        self.execute(self.put(self.api_url(&["v3", "routes", id])).form(route))
+            .await
+    }
+
+    pub async fn delete_route(&self, id: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.api_url(&["v3", "routes", id])))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn parse_route_model() {
+        assert_eq!(
+            r#"match_header("subject", ".*support")"#.parse(),
+            Ok(Expression::MatchHeader(
+                "subject".into(),
+                ".*support".into()
+            ))
+        );
+        assert_eq!("catch_all()".parse(), Ok(Expression::CatchAll));
+        assert_eq!(
+            r#"store(notify="https://example.com/hook")"#.parse(),
+            Ok(Action::Store {
+                notify: Some("https://example.com/hook".into())
+            })
+        );
+        assert_eq!(
+            "frobnicate()".parse(),
+            Ok(Action::Other("frobnicate()".into()))
+        );
+
+        for action in [
+            Action::Forward("a@example.com".into()),
+            Action::Store { notify: None },
+            Action::Stop,
+        ] {
+            assert_eq!(action.to_string().parse(), Ok(action));
+        }
+    }
+
+    #[tokio::test]
+    async fn create_route() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/routes"))
+            .and(matchers::body_string_contains(
+                "expression=match_recipient%28%22support%40example.com%22%29",
+            ))
+            .and(matchers::body_string_contains("action=stop%28%29"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "message": "Route has been created",
+                "route": {
+                    "actions": ["forward(\"https://example.com/inbound\")", "stop()"],
+                    "created_at": "Wed, 15 Feb 2012 13:03:31 GMT",
+                    "description": "",
+                    "expression": "match_recipient(\"support@example.com\")",
+                    "id": "4f3bad2335335426750048c6",
+                    "priority": 0
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let route = NewRoute::new(Expression::MatchRecipient("support@example.com".into()))
+            .action(Action::Forward("https://example.com/inbound".into()))
+            .action(Action::Stop);
+        let created = mailer.create_route(&route).await.expect("Creating route");
+
+        assert_eq!(created.expression, route.expression);
+        assert_eq!(created.actions, route.actions);
+    }
+}
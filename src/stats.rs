@@ -0,0 +1,217 @@
+//! Aggregated sending statistics, `/v3/<domain>/stats/total`.
+//!
+//! ```
+//! use mailgun46::{Mailer, events::EventType, stats::{Resolution, StatsQuery}};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let query = StatsQuery::new([EventType::Accepted, EventType::Failed])
+//!     .resolution(Resolution::Day)
+//!     .duration("7d");
+//! let stats = mailer.stats(&query).await?;
+//! for point in &stats.stats {
+//!     println!("{}: {} failed", point.time, point.failed.permanent.total);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use chrono::{DateTime, Utc};
+
+use crate::{events::EventType, Mailer, SendError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Hour,
+    Day,
+    Month,
+}
+
+impl Resolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+            Self::Month => "month",
+        }
+    }
+}
+
+/// Which events to aggregate and over what period.
+#[derive(Debug, Clone)]
+pub struct StatsQuery {
+    events: Vec<EventType>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    resolution: Option<Resolution>,
+    duration: Option<String>,
+}
+
+impl StatsQuery {
+    /// A query for the given event types, at least one is required by Mailgun.
+    pub fn new(events: impl IntoIterator<Item = EventType>) -> Self {
+        Self {
+            events: events.into_iter().collect(),
+            start: None,
+            end: None,
+            resolution: None,
+            duration: None,
+        }
+    }
+
+    pub fn start(mut self, time: impl Into<DateTime<Utc>>) -> Self {
+        self.start = Some(time.into());
+        self
+    }
+
+    pub fn end(mut self, time: impl Into<DateTime<Utc>>) -> Self {
+        self.end = Some(time.into());
+        self
+    }
+
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    /// Period counted back from `end`, such as `7d` or `1m`. Takes precedence over `start`.
+    pub fn duration(mut self, duration: impl Into<String>) -> Self {
+        self.duration = Some(duration.into());
+        self
+    }
+
+    /// Events are sent as repeated `event` parameters.
+    pub(crate) fn query_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs: Vec<_> = self
+            .events
+            .iter()
+            .map(|event| ("event", event.as_str().to_string()))
+            .collect();
+        pairs.extend(self.start.map(|time| ("start", time.to_rfc2822())));
+        pairs.extend(self.end.map(|time| ("end", time.to_rfc2822())));
+        pairs.extend(
+            self.resolution
+                .map(|res| ("resolution", res.as_str().to_string())),
+        );
+        pairs.extend(self.duration.clone().map(|duration| ("duration", duration)));
+        pairs
+    }
+}
+
+/// A time series of counters, one entry per resolution step.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct Stats {
+    #[serde(deserialize_with = "crate::datetime::deserialize_rfc2822")]
+    pub start: DateTime<Utc>,
+    #[serde(deserialize_with = "crate::datetime::deserialize_rfc2822")]
+    pub end: DateTime<Utc>,
+    pub resolution: Resolution,
+    pub stats: Vec<StatsEntry>,
+}
+
+/// Counters for a single step, event types not queried are zero.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct StatsEntry {
+    #[serde(deserialize_with = "crate::datetime::deserialize_rfc2822")]
+    pub time: DateTime<Utc>,
+    pub accepted: Counts,
+    pub delivered: Counts,
+    pub failed: FailedCounts,
+    pub opened: Total,
+    pub clicked: Total,
+    pub complained: Total,
+    pub unsubscribed: Total,
+    pub stored: Total,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct Total {
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct Counts {
+    pub incoming: u64,
+    pub outgoing: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct FailedCounts {
+    pub temporary: TemporaryFailures,
+    pub permanent: PermanentFailures,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct TemporaryFailures {
+    pub espblock: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PermanentFailures {
+    pub suppress_bounce: u64,
+    pub suppress_unsubscribe: u64,
+    pub suppress_complaint: u64,
+    pub bounce: u64,
+    pub delayed_bounce: u64,
+    pub total: u64,
+}
+
+impl Mailer {
+    /// Fetches total counters for the domain.
+    pub async fn stats(&self, query: &StatsQuery) -> Result<Stats, SendError> {
+        self.execute(
+            self.get(self.domain_url(&["stats", "total"]))
+                .query(&query.query_pairs()),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn fetch_stats() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/stats/total"))
+            .and(matchers::query_param("event", "failed"))
+            .and(matchers::query_param("resolution", "day"))
+            .and(matchers::query_param("duration", "2d"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "start": "Mon, 22 Feb 2021 00:00:00 UTC",
+                "end": "Wed, 24 Feb 2021 00:00:00 UTC",
+                "resolution": "day",
+                "stats": [
+                    {
+                        "time": "Mon, 22 Feb 2021 00:00:00 UTC",
+                        "accepted": {"outgoing": 10, "total": 10},
+                        "failed": {"permanent": {"bounce": 2, "total": 2}}
+                    },
+                    {"time": "Tue, 23 Feb 2021 00:00:00 UTC"}
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+        let query = StatsQuery::new([EventType::Accepted, EventType::Failed])
+            .resolution(Resolution::Day)
+            .duration("2d");
+
+        let stats = mailer.stats(&query).await.expect("Fetching stats");
+        assert_eq!(stats.resolution, Resolution::Day);
+        assert_eq!(stats.stats[0].accepted.total, 10);
+        assert_eq!(stats.stats[0].failed.permanent.bounce, 2);
+        assert_eq!(stats.stats[1].failed.permanent.total, 0);
+    }
+}
@@ -0,0 +1,296 @@
+//! A local copy of the suppression lists, checked before sending.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    email::{bare_address, split_recipients},
+    suppressions::{Bounce, Complaint, Suppression, Unsubscribe},
+    Email, Mailer, SendError,
+};
+
+/// Mailgun lists at most this many suppressions per page.
+const PAGE_LIMIT: &str = "1000";
+
+/// How long to wait before fetching again after a failed refresh, at most the ttl.
+const RETRY_AFTER: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub(crate) struct SuppressionGuard {
+    ttl: Duration,
+    cache: Mutex<Cache>,
+    /// Held while fetching, so only one send refreshes the lists at a time.
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    suppressed: Option<(Instant, Arc<Suppressed>)>,
+    failed: Option<(Instant, SendError)>,
+}
+
+/// Lowercased addresses that must not be sent to.
+#[derive(Debug, Default)]
+struct Suppressed {
+    all: HashSet<String>,
+    /// Addresses unsubscribed from some tags only.
+    tags: HashMap<String, Vec<String>>,
+}
+
+impl Suppressed {
+    fn blocks(&self, address: &str, email: &Email) -> bool {
+        self.all.contains(address)
+            || self
+                .tags
+                .get(address)
+                .is_some_and(|tags| email.tags.iter().any(|tag| tags.contains(tag)))
+    }
+}
+
+impl SuppressionGuard {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            cache: Mutex::default(),
+            refreshing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Fails with `SendError::Suppressed` for the first suppressed recipient.
+    pub(crate) async fn check(&self, mailer: &Mailer, email: &Email) -> Result<(), SendError> {
+        let suppressed = self.suppressed(mailer).await?;
+        for recipient in split_recipients(&email.to) {
+            let address = bare_address(&recipient).to_ascii_lowercase();
+            if suppressed.blocks(&address, email) {
+                return Err(SendError::Suppressed(address));
+            }
+        }
+        Ok(())
+    }
+
+    /// The cached lists, refreshed once older than the ttl. A failed refresh keeps
+    /// using the previous copy, if there is one, and is not retried for a while.
+    /// Sends arriving during a refresh use the previous copy instead of waiting.
+    async fn suppressed(&self, mailer: &Mailer) -> Result<Arc<Suppressed>, SendError> {
+        if let Some(cached) = self.cached() {
+            return cached;
+        }
+        let _refreshing = match self.refreshing.try_lock() {
+            Ok(refreshing) => refreshing,
+            Err(_) => {
+                if let Some(stale) = self.stale() {
+                    return Ok(stale);
+                }
+                let refreshing = self.refreshing.lock().await;
+                if let Some(cached) = self.cached() {
+                    return cached;
+                }
+                refreshing
+            }
+        };
+
+        let fetched = fetch(mailer).await;
+        let mut cache = self.cache.lock().expect("Suppression cache poisoned");
+        match fetched {
+            Ok(suppressed) => {
+                let suppressed = Arc::new(suppressed);
+                cache.suppressed = Some((Instant::now(), suppressed.clone()));
+                cache.failed = None;
+                Ok(suppressed)
+            }
+            Err(err) => {
+                cache.failed = Some((Instant::now(), err.clone()));
+                match &cache.suppressed {
+                    Some((_, suppressed)) => Ok(suppressed.clone()),
+                    None => Err(err),
+                }
+            }
+        }
+    }
+
+    /// The answer without fetching, while the copy is fresh or a refresh failed recently.
+    fn cached(&self) -> Option<Result<Arc<Suppressed>, SendError>> {
+        let cache = self.cache.lock().expect("Suppression cache poisoned");
+        match (&cache.suppressed, &cache.failed) {
+            (Some((fetched, suppressed)), _) if fetched.elapsed() < self.ttl => {
+                Some(Ok(suppressed.clone()))
+            }
+            (suppressed, Some((failed, err))) if failed.elapsed() < RETRY_AFTER.min(self.ttl) => {
+                Some(match suppressed {
+                    Some((_, suppressed)) => Ok(suppressed.clone()),
+                    None => Err(err.clone()),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn stale(&self) -> Option<Arc<Suppressed>> {
+        let cache = self.cache.lock().expect("Suppression cache poisoned");
+        cache
+            .suppressed
+            .as_ref()
+            .map(|(_, suppressed)| suppressed.clone())
+    }
+}
+
+async fn fetch(mailer: &Mailer) -> Result<Suppressed, SendError> {
+    let mut suppressed = Suppressed::default();
+    for bounce in fetch_list::<Bounce>(mailer).await? {
+        suppressed.all.insert(bounce.address.to_ascii_lowercase());
+    }
+    for complaint in fetch_list::<Complaint>(mailer).await? {
+        suppressed
+            .all
+            .insert(complaint.address.to_ascii_lowercase());
+    }
+    for unsubscribe in fetch_list::<Unsubscribe>(mailer).await? {
+        let address = unsubscribe.address.to_ascii_lowercase();
+        if unsubscribe.tags.is_empty() || unsubscribe.tags.iter().any(|tag| tag == "*") {
+            suppressed.all.insert(address);
+        } else {
+            suppressed
+                .tags
+                .entry(address)
+                .or_default()
+                .extend(unsubscribe.tags);
+        }
+    }
+    Ok(suppressed)
+}
+
+async fn fetch_list<T: Suppression>(mailer: &Mailer) -> Result<Vec<T>, SendError> {
+    let mut page = mailer
+        .execute_page::<T>(
+            mailer
+                .get(mailer.domain_url(&[T::LIST]))
+                .query(&[("limit", PAGE_LIMIT)]),
+        )
+        .await?;
+    let mut entries = Vec::new();
+    loop {
+        let next = page.next_page(mailer).await?;
+        entries.append(&mut page.items);
+        match next {
+            Some(next) => page = next,
+            None => return Ok(entries),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EmailBuilder, Mailer, SendError};
+    use std::time::Duration;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn blocks_suppressed_recipients() {
+        let server = MockServer::start().await;
+        let lists = [
+            (
+                "bounces",
+                serde_json::json!([{"address": "Bounced@example.com", "code": 550}]),
+            ),
+            ("complaints", serde_json::json!([])),
+            (
+                "unsubscribes",
+                serde_json::json!([
+                    {"address": "all@example.com", "tags": ["*"]},
+                    {"address": "news@example.com", "tags": ["newsletter"]}
+                ]),
+            ),
+        ];
+        for (list, items) in lists {
+            Mock::given(matchers::method("GET"))
+                .and(matchers::path(format!("/v3/fakedomain/{}", list)))
+                .and(matchers::query_param("limit", "1000"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "items": items,
+                    "paging": {"next": format!("{}/v3/fakedomain/{}?page=next", server.uri(), list)}
+                })))
+                .expect(1)
+                .mount(&server)
+                .await;
+            Mock::given(matchers::method("GET"))
+                .and(matchers::path(format!("/v3/fakedomain/{}", list)))
+                .and(matchers::query_param("page", "next"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "items": [],
+                    "paging": {}
+                })))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"id": "<1@fakedomain>"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .with_suppression_guard(Duration::from_secs(60))
+            .build()
+            .expect("Creating Mailer");
+
+        let send = |to: &str, tag: &str| {
+            EmailBuilder::default()
+                .to("ok@example.com")
+                .to(to)
+                .tag(tag)
+                .build()
+                .unwrap()
+                .send(&mailer)
+        };
+        for to in ["Someone <bounced@example.com>", "all@example.com"] {
+            let err = send(to, "receipt").await.unwrap_err();
+            assert!(matches!(err, SendError::Suppressed(_)), "{}", err);
+        }
+        assert_eq!(
+            send("news@example.com", "newsletter").await.unwrap_err(),
+            SendError::Suppressed("news@example.com".into())
+        );
+        send("news@example.com", "receipt")
+            .await
+            .expect("Sending to an address unsubscribed from other tags");
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_is_not_retried_on_every_send() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/bounces"))
+            .respond_with(ResponseTemplate::new(403))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::builder("fakedomain", "tomatotoken")
+            .base_url(server.uri())
+            .with_suppression_guard(Duration::from_secs(60))
+            .build()
+            .expect("Creating Mailer");
+        for _ in 0..3 {
+            let err = EmailBuilder::default()
+                .to("ok@example.com")
+                .build()
+                .unwrap()
+                .send(&mailer)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err.status(),
+                Some(reqwest::StatusCode::FORBIDDEN),
+                "{}",
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Managing the suppression lists of a domain: bounces, unsubscribes and complaints.
+//!
+//! ```
+//! use mailgun46::{Mailer, suppressions::Bounce};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let bounces = mailer.suppressions::<Bounce>().await?;
+//! for bounce in &bounces.items {
+//!     mailer.delete_suppression::<Bounce>(&bounce.address).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use chrono::{DateTime, Utc};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Serialize,
+};
+
+use crate::{Mailer, Page, SendError};
+
+/// An entry on one of the suppression lists.
+pub trait Suppression: Serialize + DeserializeOwned {
+    /// The list endpoint below `/v3/<domain>`.
+    const LIST: &'static str;
+
+    fn address(&self) -> &str;
+}
+
+/// An address that bounced, Mailgun stops sending to it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Bounce {
+    pub address: String,
+
+    /// SMTP error code, Mailgun reports it as a string or a number.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_code",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub code: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Set by Mailgun, defaults to now when adding.
+    #[serde(
+        default,
+        serialize_with = "crate::datetime::serialize_rfc2822",
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// An address that unsubscribed, from all mail or the given tags.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Unsubscribe {
+    pub address: String,
+
+    /// Tags unsubscribed from, `*` for all.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    #[serde(
+        default,
+        serialize_with = "crate::datetime::serialize_rfc2822",
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// An address that marked mail as spam.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Complaint {
+    pub address: String,
+
+    #[serde(
+        default,
+        serialize_with = "crate::datetime::serialize_rfc2822",
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Bounce {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            code: None,
+            error: None,
+            created_at: None,
+        }
+    }
+}
+
+impl Unsubscribe {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            tags: Vec::new(),
+            created_at: None,
+        }
+    }
+}
+
+impl Complaint {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            created_at: None,
+        }
+    }
+}
+
+impl Suppression for Bounce {
+    const LIST: &'static str = "bounces";
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+impl Suppression for Unsubscribe {
+    const LIST: &'static str = "unsubscribes";
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+impl Suppression for Complaint {
+    const LIST: &'static str = "complaints";
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+fn deserialize_code<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Code {
+        Number(u64),
+        Text(String),
+    }
+
+    Ok(
+        <Option<Code> as serde::Deserialize>::deserialize(deserializer)?.map(|code| match code {
+            Code::Number(n) => n.to_string(),
+            Code::Text(s) => s,
+        }),
+    )
+}
+
+impl Mailer {
+    /// Lists the first page of entries on a suppression list.
+    pub async fn suppressions<T: Suppression>(&self) -> Result<Page<T>, SendError> {
+        self.execute_page(self.get(self.domain_url(&[T::LIST])))
+            .await
+    }
+
+    /// Looks up a single address on a suppression list.
+    pub async fn suppression<T: Suppression>(&self, address: &str) -> Result<T, SendError> {
+        self.execute(self.get(self.domain_url(&[T::LIST, address])))
+            .await
+    }
+
+    /// Adds an entry to its suppression list.
+    pub async fn add_suppression<T: Suppression>(&self, entry: &T) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(
+                self.post(self.domain_url(&[T::LIST]))
+                    .json(std::slice::from_ref(entry)),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Removes an address from a suppression list.
+    pub async fn delete_suppression<T: Suppression>(&self, address: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.domain_url(&[T::LIST, address])))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn deserialize_bounce() {
+        let bounce: Bounce = serde_json::from_str(
+            r#"{
+                "address": "alice@example.com",
+                "code": 550,
+                "error": "No such mailbox",
+                "created_at": "Fri, 21 Oct 2011 11:02:55 UTC"
+            }"#,
+        )
+        .expect("Deserializing bounce");
+
+        assert_eq!(bounce.code.as_deref(), Some("550"));
+        assert_eq!(bounce.created_at.unwrap().timestamp(), 1319194975);
+    }
+
+    #[tokio::test]
+    async fn manage_unsubscribes() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path(
+                "/v3/fakedomain/unsubscribes/bob@example.com",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "address": "bob@example.com",
+                "tags": ["*"],
+                "created_at": "Fri, 21 Oct 2011 11:02:55 UTC"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/unsubscribes"))
+            .and(matchers::body_json(
+                serde_json::json!([{"address": "bob@example.com", "tags": ["news"]}]),
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"message": "ok"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("DELETE"))
+            .and(matchers::path(
+                "/v3/fakedomain/unsubscribes/bob@example.com",
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"message": "ok"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let unsubscribe: Unsubscribe = mailer
+            .suppression("bob@example.com")
+            .await
+            .expect("Getting unsubscribe");
+        assert_eq!(unsubscribe.tags, vec!["*"]);
+
+        let mut entry = Unsubscribe::new("bob@example.com");
+        entry.tags.push("news".into());
+        mailer.add_suppression(&entry).await.expect("Adding");
+        mailer
+            .delete_suppression::<Unsubscribe>("bob@example.com")
+            .await
+            .expect("Deleting");
+    }
+}
@@ -0,0 +1,229 @@
+//! The tags of a domain and their statistics, `/v3/<domain>/tags`. Tags are created
+//! by sending with `EmailBuilder::tag`.
+//!
+//! ```
+//! use mailgun46::{Mailer, events::EventType, stats::StatsQuery, tags::Aggregate};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let query = StatsQuery::new([EventType::Delivered, EventType::Opened]).duration("30d");
+//! let stats = mailer.tag_stats("spring-campaign", &query).await?;
+//! let opened: u64 = stats.stats.iter().map(|entry| entry.opened.total).sum();
+//!
+//! for (device, counts) in mailer.tag_aggregates("spring-campaign", Aggregate::Devices).await? {
+//!     println!("{}: {} of {} opened", device, counts.unique_opened, opened);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::de::IgnoredAny;
+
+use crate::{
+    stats::{Stats, StatsQuery},
+    Mailer, Page, SendError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Tag {
+    pub tag: String,
+
+    #[serde(default)]
+    pub description: String,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::datetime::deserialize_optional_any"
+    )]
+    pub first_seen: Option<DateTime<Utc>>,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::datetime::deserialize_optional_any"
+    )]
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// How `tag_aggregates` splits the counters of a tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    /// Keyed on two letter country codes.
+    Countries,
+    /// Keyed on the recipients' email provider, such as `gmail.com`.
+    Providers,
+    /// Keyed on `desktop`, `mobile`, `tablet` or `unknown`.
+    Devices,
+}
+
+impl Aggregate {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Countries => "countries",
+            Self::Providers => "providers",
+            Self::Devices => "devices",
+        }
+    }
+}
+
+/// Counters for one country, provider or device, not every aggregate has every counter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct AggregateCounts {
+    pub accepted: u64,
+    pub delivered: u64,
+    pub opened: u64,
+    pub unique_opened: u64,
+    pub clicked: u64,
+    pub unique_clicked: u64,
+    pub complained: u64,
+    pub unsubscribed: u64,
+    pub bounced: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct AggregatesReply {
+    #[serde(alias = "country", alias = "provider", alias = "device")]
+    counts: BTreeMap<String, AggregateCounts>,
+}
+
+impl Mailer {
+    /// Lists the first page of tags on the domain.
+    pub async fn tags(&self) -> Result<Page<Tag>, SendError> {
+        self.execute_page(self.get(self.domain_url(&["tags"])))
+            .await
+    }
+
+    pub async fn tag(&self, tag: &str) -> Result<Tag, SendError> {
+        self.execute(self.get(self.domain_url(&["tags", tag])))
+            .await
+    }
+
+    pub async fn update_tag_description(
+        &self,
+        tag: &str,
+        description: &str,
+    ) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(
+                self.put(self.domain_url(&["tags", tag]))
+                    .form(&[("description", description)]),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the tag along with its statistics.
+    pub async fn delete_tag(&self, tag: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.domain_url(&["tags", tag])))
+            .await?;
+        Ok(())
+    }
+
+    /// Same as `stats`, counting only messages sent with the tag.
+    pub async fn tag_stats(&self, tag: &str, query: &StatsQuery) -> Result<Stats, SendError> {
+        self.execute(
+            self.get(self.domain_url(&["tags", tag, "stats"]))
+                .query(&query.query_pairs()),
+        )
+        .await
+    }
+
+    /// The counters of a tag over its lifetime, split by country, provider or device.
+    pub async fn tag_aggregates(
+        &self,
+        tag: &str,
+        aggregate: Aggregate,
+    ) -> Result<BTreeMap<String, AggregateCounts>, SendError> {
+        let reply: AggregatesReply = self
+            .execute(self.get(self.domain_url(&[
+                "tags",
+                tag,
+                "stats",
+                "aggregates",
+                aggregate.as_str(),
+            ])))
+            .await?;
+        Ok(reply.counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventType;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn tag_reporting() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/tags"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "items": [{
+                    "tag": "spring-campaign",
+                    "description": "",
+                    "first-seen": "2021-02-22T00:00:00Z",
+                    "last-seen": "2021-02-24T00:00:00Z"
+                }],
+                "paging": {}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/tags/spring-campaign/stats"))
+            .and(matchers::query_param("event", "delivered"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tag": "spring-campaign",
+                "start": "Mon, 22 Feb 2021 00:00:00 UTC",
+                "end": "Wed, 24 Feb 2021 00:00:00 UTC",
+                "resolution": "day",
+                "stats": [{
+                    "time": "Mon, 22 Feb 2021 00:00:00 UTC",
+                    "delivered": {"outgoing": 8, "total": 8}
+                }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path(
+                "/v3/fakedomain/tags/spring-campaign/stats/aggregates/devices",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tag": "spring-campaign",
+                "device": {
+                    "desktop": {"clicked": 3, "opened": 5, "unique_clicked": 2, "unique_opened": 4},
+                    "mobile": {"opened": 1, "unique_opened": 1}
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let tags = mailer.tags().await.expect("Listing tags");
+        assert_eq!(tags.items[0].tag, "spring-campaign");
+        assert_eq!(
+            tags.items[0].first_seen.map(|time| time.to_rfc2822()),
+            Some("Mon, 22 Feb 2021 00:00:00 +0000".into())
+        );
+
+        let stats = mailer
+            .tag_stats("spring-campaign", &StatsQuery::new([EventType::Delivered]))
+            .await
+            .expect("Fetching tag stats");
+        assert_eq!(stats.stats[0].delivered.total, 8);
+
+        let devices = mailer
+            .tag_aggregates("spring-campaign", Aggregate::Devices)
+            .await
+            .expect("Fetching aggregates");
+        assert_eq!(devices["desktop"].unique_opened, 4);
+        assert_eq!(devices["mobile"].clicked, 0);
+    }
+}
@@ -0,0 +1,359 @@
+//! Managing stored templates and their versions, `/v3/<domain>/templates`.
+//!
+//! ```
+//! use mailgun46::{EmailBuilder, Mailer, templates::{NewTemplate, NewTemplateVersion}};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! mailer
+//!     .create_template(&NewTemplate::new("welcome").description("Sent on signup"))
+//!     .await?;
+//! mailer
+//!     .create_template_version(
+//!         "welcome",
+//!         &NewTemplateVersion::new("v2", "<h1>Welcome {{name}}</h1>").active(true),
+//!     )
+//!     .await?;
+//!
+//! EmailBuilder::default()
+//!     .to("someone@example.com")
+//!     .template("welcome")
+//!     .build()?
+//!     .send(&mailer)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+use chrono::{DateTime, Utc};
+use serde::de::IgnoredAny;
+
+use crate::{email::serialize_yes_no, Mailer, Page, SendError};
+
+/// Mailgun lists at most this many versions per request.
+const MAX_VERSIONS: &str = "100";
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    pub name: String,
+
+    #[serde(default)]
+    pub description: String,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub created_by: Option<String>,
+
+    /// The active version, for templates fetched with `Mailer::template`.
+    #[serde(default)]
+    pub version: Option<TemplateVersion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateVersion {
+    pub tag: String,
+
+    /// The content, left out when listing versions.
+    #[serde(default)]
+    pub template: Option<String>,
+
+    /// `handlebars`, or `go` for Golang templates.
+    #[serde(default)]
+    pub engine: Option<String>,
+
+    #[serde(default)]
+    pub comment: String,
+
+    #[serde(default)]
+    pub active: bool,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::datetime::deserialize_optional_rfc2822"
+    )]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A template to create, optionally with its first version.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct NewTemplate {
+    name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+
+    #[serde(flatten)]
+    version: Option<NewTemplateVersion>,
+}
+
+impl NewTemplate {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            version: None,
+        }
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Creates the template with this as its first, active version.
+    pub fn version(mut self, version: NewTemplateVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+}
+
+/// A version to create, or the new contents of an existing one.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct NewTemplateVersion {
+    tag: String,
+    template: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    engine: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_yes_no"
+    )]
+    active: Option<bool>,
+}
+
+impl NewTemplateVersion {
+    pub fn new(tag: impl Into<String>, template: impl Into<String>) -> Self {
+        Self {
+            tag: tag.into(),
+            template: template.into(),
+            engine: None,
+            comment: None,
+            active: None,
+        }
+    }
+
+    /// Defaults to `handlebars`.
+    pub fn engine(mut self, engine: impl Into<String>) -> Self {
+        self.engine = Some(engine.into());
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Makes this the version used when sending with the template.
+    pub fn active(mut self, active: bool) -> Self {
+        self.active = Some(active);
+        self
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct TemplateReply {
+    template: Template,
+}
+
+#[derive(serde::Deserialize)]
+struct VersionReply {
+    template: VersionTemplate,
+}
+
+#[derive(serde::Deserialize)]
+struct VersionTemplate {
+    version: TemplateVersion,
+}
+
+#[derive(serde::Deserialize)]
+struct VersionsReply {
+    template: VersionsTemplate,
+}
+
+#[derive(serde::Deserialize)]
+struct VersionsTemplate {
+    versions: Vec<TemplateVersion>,
+}
+
+impl Mailer {
+    /// Lists the first page of templates on the domain.
+    pub async fn templates(&self) -> Result<Page<Template>, SendError> {
+        self.execute_page(self.get(self.domain_url(&["templates"])))
+            .await
+    }
+
+    /// Fetches the template along with its active version.
+    pub async fn template(&self, name: &str) -> Result<Template, SendError> {
+        let reply: TemplateReply = self
+            .execute(
+                self.get(self.domain_url(&["templates", name]))
+                    .query(&[("active", "yes")]),
+            )
+            .await?;
+        Ok(reply.template)
+    }
+
+    pub async fn create_template(&self, template: &NewTemplate) -> Result<Template, SendError> {
+        let reply: TemplateReply = self
+            .execute(self.post(self.domain_url(&["templates"])).form(template))
+            .await?;
+        Ok(reply.template)
+    }
+
+    pub async fn update_template_description(
+        &self,
+        name: &str,
+        description: &str,
+    ) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(
+                self.put(self.domain_url(&["templates", name]))
+                    .form(&[("description", description)]),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the template along with all its versions.
+    pub async fn delete_template(&self, name: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.domain_url(&["templates", name])))
+            .await?;
+        Ok(())
+    }
+
+    /// Lists the versions of a template, up to 100, without their contents.
+    pub async fn template_versions(&self, name: &str) -> Result<Vec<TemplateVersion>, SendError> {
+        let reply: VersionsReply = self
+            .execute(
+                self.get(self.domain_url(&["templates", name, "versions"]))
+                    .query(&[("limit", MAX_VERSIONS)]),
+            )
+            .await?;
+        Ok(reply.template.versions)
+    }
+
+    pub async fn template_version(
+        &self,
+        name: &str,
+        tag: &str,
+    ) -> Result<TemplateVersion, SendError> {
+        let reply: VersionReply = self
+            .execute(self.get(self.domain_url(&["templates", name, "versions", tag])))
+            .await?;
+        Ok(reply.template.version)
+    }
+
+    pub async fn create_template_version(
+        &self,
+        name: &str,
+        version: &NewTemplateVersion,
+    ) -> Result<TemplateVersion, SendError> {
+        let reply: VersionReply = self
+            .execute(
+                self.post(self.domain_url(&["templates", name, "versions"]))
+                    .form(version),
+            )
+            .await?;
+        Ok(reply.template.version)
+    }
+
+    /// Replaces the contents, comment and active flag of the version tagged `version.tag`.
+    pub async fn update_template_version(
+        &self,
+        name: &str,
+        version: &NewTemplateVersion,
+    ) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(
+                self.put(self.domain_url(&["templates", name, "versions", &version.tag]))
+                    .form(version),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_template_version(&self, name: &str, tag: &str) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.domain_url(&["templates", name, "versions", tag])))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn manage_versions() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/fakedomain/templates/welcome/versions"))
+            .and(matchers::body_string_contains("tag=v2"))
+            .and(matchers::body_string_contains("active=yes"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "message": "new version of the template has been stored",
+                "template": {
+                    "createdAt": "Wed, 29 Aug 2018 23:31:11 UTC",
+                    "description": "Sent on signup",
+                    "name": "welcome",
+                    "version": {
+                        "createdAt": "Wed, 29 Aug 2018 23:31:21 UTC",
+                        "engine": "handlebars",
+                        "tag": "v2",
+                        "comment": "",
+                        "active": true,
+                        "template": "<h1>Welcome {{name}}</h1>"
+                    }
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/fakedomain/templates/welcome/versions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "template": {
+                    "name": "welcome",
+                    "versions": [
+                        {"tag": "v1", "engine": "handlebars", "active": false},
+                        {"tag": "v2", "engine": "handlebars", "active": true}
+                    ]
+                },
+                "paging": {}
+            })))
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let version = mailer
+            .create_template_version(
+                "welcome",
+                &NewTemplateVersion::new("v2", "<h1>Welcome {{name}}</h1>").active(true),
+            )
+            .await
+            .expect("Creating version");
+        assert!(version.active);
+        assert!(version.created_at.is_some());
+
+        let versions = mailer
+            .template_versions("welcome")
+            .await
+            .expect("Listing versions");
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].template, None);
+    }
+}
@@ -0,0 +1,361 @@
+//! Deriving a plain text body from html, see `EmailBuilder::html_body_with_text_fallback`.
+
+/// Elements whose contents are never shown.
+const HIDDEN: &[&str] = &["head", "script", "style", "template", "title"];
+
+/// Elements shown as paragraphs of their own.
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "blockquote",
+    "div",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Strips the tags, keeping the text with links written out after it.
+/// Whitespace is collapsed as a browser would, except within `pre`.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut text = Text::default();
+    let mut hidden: Option<String> = None;
+    let mut link: Option<(String, usize)> = None;
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if hidden.is_none() {
+            text.push(&decode_entities(&rest[..start]));
+        }
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = Tag::parse(&rest[1..end]);
+        rest = &rest[end + 1..];
+
+        if let Some(name) = &hidden {
+            if tag.closing && tag.name == *name {
+                hidden = None;
+            }
+            continue;
+        }
+
+        match tag.name.as_str() {
+            name if HIDDEN.contains(&name) && !tag.closing && !tag.self_closing => {
+                hidden = Some(name.to_owned());
+            }
+            "a" if tag.closing => {
+                if let Some((href, at)) = link.take() {
+                    let label = text.buf[at..].trim();
+                    if label.is_empty() {
+                        text.push(&href);
+                    } else if label != href && label != href.trim_start_matches("mailto:") {
+                        text.push(&format!(" ({})", href));
+                    }
+                }
+            }
+            "a" => {
+                link = tag
+                    .attr("href")
+                    .filter(|href| !href.is_empty())
+                    .filter(|href| !href.starts_with('#') && !href.starts_with("javascript:"))
+                    .map(|href| (href, text.buf.len()));
+            }
+            "br" => text.newlines(1),
+            "li" if !tag.closing => {
+                text.newlines(1);
+                text.push("- ");
+            }
+            "li" | "tr" => text.newlines(1),
+            "td" | "th" => text.push(" "),
+            "img" => {
+                if let Some(alt) = tag.attr("alt") {
+                    text.push(&alt);
+                }
+            }
+            name if BLOCKS.contains(&name) => {
+                text.newlines(2);
+                if name == "pre" && !tag.self_closing {
+                    if tag.closing {
+                        text.pre = text.pre.saturating_sub(1);
+                    } else {
+                        text.pre += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if hidden.is_none() {
+        text.push(&decode_entities(rest));
+    }
+
+    text.finish()
+}
+
+#[derive(Default)]
+struct Text {
+    buf: String,
+    /// Depth of `pre` elements, whitespace is kept within them.
+    pre: usize,
+    space: bool,
+}
+
+impl Text {
+    fn push(&mut self, s: &str) {
+        for c in s.chars() {
+            if self.pre > 0 {
+                self.buf.push(c);
+            } else if c.is_ascii_whitespace() {
+                self.space = true;
+            } else {
+                if self.space && !self.buf.is_empty() && !self.buf.ends_with([' ', '\n']) {
+                    self.buf.push(' ');
+                }
+                self.space = false;
+                self.buf.push(if c == '\u{a0}' { ' ' } else { c });
+            }
+        }
+    }
+
+    /// Ends the current line, leaving `count - 1` empty lines after it.
+    fn newlines(&mut self, count: usize) {
+        self.space = false;
+        if self.pre == 0 {
+            self.buf.truncate(self.buf.trim_end_matches(' ').len());
+        }
+        if self.buf.is_empty() {
+            return;
+        }
+        let trailing = self.buf.len() - self.buf.trim_end_matches('\n').len();
+        for _ in trailing..count {
+            self.buf.push('\n');
+        }
+    }
+
+    fn finish(self) -> String {
+        let lines: Vec<_> = self.buf.lines().map(str::trim_end).collect();
+        lines.join("\n").trim().to_owned()
+    }
+}
+
+struct Tag<'a> {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attrs: &'a str,
+}
+
+impl<'a> Tag<'a> {
+    fn parse(inner: &'a str) -> Self {
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let name_len = inner
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(inner.len());
+        Self {
+            name: inner[..name_len].to_ascii_lowercase(),
+            closing,
+            self_closing: inner.ends_with('/'),
+            attrs: &inner[name_len..],
+        }
+    }
+
+    /// The decoded value of an attribute, `name` in lowercase.
+    fn attr(&self, name: &str) -> Option<String> {
+        let lower = self.attrs.to_ascii_lowercase();
+        let mut from = 0;
+        while let Some(pos) = lower[from..].find(name) {
+            let at = from + pos;
+            from = at + name.len();
+            if at > 0 && !lower.as_bytes()[at - 1].is_ascii_whitespace() {
+                continue;
+            }
+            let value = match self.attrs[from..].trim_start().strip_prefix('=') {
+                Some(value) => value.trim_start(),
+                None => continue,
+            };
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+                _ => value.split_ascii_whitespace().next(),
+            };
+            return Some(decode_entities(value.unwrap_or_default()));
+        }
+        None
+    }
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        let entity = rest
+            .find(';')
+            .filter(|&end| end <= 8)
+            .and_then(|end| Some((entity(&rest[..end])?, end + 1)));
+        match entity {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            }
+            None => decoded.push('&'),
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Names of the Latin-1 entities, `&nbsp;` to `&yuml;`, in code point order from U+00A0.
+const LATIN1_ENTITIES: [&str; 96] = [
+    "nbsp", "iexcl", "cent", "pound", "curren", "yen", "brvbar", "sect", "uml", "copy", "ordf",
+    "laquo", "not", "shy", "reg", "macr", "deg", "plusmn", "sup2", "sup3", "acute", "micro",
+    "para", "middot", "cedil", "sup1", "ordm", "raquo", "frac14", "frac12", "frac34", "iquest",
+    "Agrave", "Aacute", "Acirc", "Atilde", "Auml", "Aring", "AElig", "Ccedil", "Egrave", "Eacute",
+    "Ecirc", "Euml", "Igrave", "Iacute", "Icirc", "Iuml", "ETH", "Ntilde", "Ograve", "Oacute",
+    "Ocirc", "Otilde", "Ouml", "times", "Oslash", "Ugrave", "Uacute", "Ucirc", "Uuml", "Yacute",
+    "THORN", "szlig", "agrave", "aacute", "acirc", "atilde", "auml", "aring", "aelig", "ccedil",
+    "egrave", "eacute", "ecirc", "euml", "igrave", "iacute", "icirc", "iuml", "eth", "ntilde",
+    "ograve", "oacute", "ocirc", "otilde", "ouml", "divide", "oslash", "ugrave", "uacute", "ucirc",
+    "uuml", "yacute", "thorn", "yuml",
+];
+
+/// Punctuation and letters outside Latin-1 that show up in html emails.
+const TYPOGRAPHIC_ENTITIES: &[(&str, char)] = &[
+    ("ndash", '\u{2013}'),
+    ("mdash", '\u{2014}'),
+    ("lsquo", '\u{2018}'),
+    ("rsquo", '\u{2019}'),
+    ("sbquo", '\u{201a}'),
+    ("ldquo", '\u{201c}'),
+    ("rdquo", '\u{201d}'),
+    ("bdquo", '\u{201e}'),
+    ("dagger", '\u{2020}'),
+    ("Dagger", '\u{2021}'),
+    ("bull", '\u{2022}'),
+    ("hellip", '\u{2026}'),
+    ("permil", '\u{2030}'),
+    ("prime", '\u{2032}'),
+    ("Prime", '\u{2033}'),
+    ("lsaquo", '\u{2039}'),
+    ("rsaquo", '\u{203a}'),
+    ("euro", '\u{20ac}'),
+    ("trade", '\u{2122}'),
+    ("larr", '\u{2190}'),
+    ("rarr", '\u{2192}'),
+    ("ensp", '\u{2002}'),
+    ("emsp", '\u{2003}'),
+    ("thinsp", '\u{2009}'),
+    ("zwnj", '\u{200c}'),
+    ("zwj", '\u{200d}'),
+    ("OElig", '\u{152}'),
+    ("oelig", '\u{153}'),
+    ("Scaron", '\u{160}'),
+    ("scaron", '\u{161}'),
+    ("Yuml", '\u{178}'),
+    ("fnof", '\u{192}'),
+    ("circ", '\u{2c6}'),
+    ("tilde", '\u{2dc}'),
+];
+
+fn entity(name: &str) -> Option<char> {
+    let code = match name {
+        "amp" => return Some('&'),
+        "lt" => return Some('<'),
+        "gt" => return Some('>'),
+        "quot" => return Some('"'),
+        "apos" => return Some('\''),
+        _ => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => match name.strip_prefix('#') {
+                Some(decimal) => decimal.parse().ok()?,
+                None => {
+                    if let Some(i) = LATIN1_ENTITIES.iter().position(|entity| *entity == name) {
+                        0xa0 + i as u32
+                    } else {
+                        return TYPOGRAPHIC_ENTITIES
+                            .iter()
+                            .find(|(entity, _)| *entity == name)
+                            .map(|(_, c)| *c);
+                    }
+                }
+            },
+        },
+    };
+    char::from_u32(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_html() {
+        let html = r#"<html><head><title>Ignored</title><style>p { color: red; }</style></head>
+            <body>
+              <h1>Welcome,   Niclas!</h1>
+              <!-- greeting -->
+              <p>Confirm your account by
+                 <a href="https://example.com/confirm?id=1&amp;t=2">clicking here</a>.<br>
+                 Questions? <a href="mailto:support@example.com">support@example.com</a></p>
+              <ul><li>Fish &amp; chips</li><li>Caf&eacute; &#233; &#x2603;</li>
+                  <li>&copy; 2021 &mdash; it&rsquo;s &hellip; &euro;5 &frac12;</li></ul>
+              <a href="https://example.com"><img src="logo.png" alt=""></a>
+              <pre>  keep
+    this</pre>
+            </body></html>"#;
+
+        assert_eq!(
+            html_to_text(html),
+            "Welcome, Niclas!\n\n\
+             Confirm your account by clicking here (https://example.com/confirm?id=1&t=2).\n\
+             Questions? support@example.com\n\n\
+             - Fish & chips\n\
+             - Café é ☃\n\
+             - © 2021 — it’s … €5 ½\n\n\
+             https://example.com\n\n  \
+             keep\n    this"
+        );
+    }
+
+    #[test]
+    fn builder_derives_text_body() {
+        let email = crate::EmailBuilder::default()
+            .to("someoneelse")
+            .html_body_with_text_fallback("<p>Hello <b>there</b></p>")
+            .build()
+            .unwrap();
+        let json = serde_json::to_value(&email).unwrap();
+        assert_eq!(json["html"], "<p>Hello <b>there</b></p>");
+        assert_eq!(json["text"], "Hello there");
+
+        let email = crate::EmailBuilder::default()
+            .to("someoneelse")
+            .text_body("Handwritten")
+            .html_body_with_text_fallback("<p>Hello</p>")
+            .build()
+            .unwrap();
+        assert_eq!(serde_json::to_value(&email).unwrap()["text"], "Handwritten");
+    }
+}
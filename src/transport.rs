@@ -0,0 +1,422 @@
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{Email, Mailer, MessageId, SendError, SendReceipt};
+
+/// Delivers emails prepared by a Mailer, the from address and Mailer level options
+/// are already applied. `HttpTransport` posting to Mailgun is the default.
+///
+/// Implement this to send somewhere else, or to wrap `HttpTransport`.
+#[async_trait]
+pub trait Transport: fmt::Debug + Send + Sync {
+    async fn send(&self, mailer: &Mailer, email: Email) -> Result<MessageId, SendError>;
+
+    /// Sends the email, returning what the receiving end replied.
+    /// Defaults to a receipt holding only the id returned by `send`.
+    async fn send_with_receipt(
+        &self,
+        mailer: &Mailer,
+        email: Email,
+    ) -> Result<SendReceipt, SendError> {
+        self.send(mailer, email).await.map(SendReceipt::new)
+    }
+}
+
+/// Posts emails to the Mailgun messages API, using the client, credentials and retry
+/// policy of the Mailer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HttpTransport;
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, mailer: &Mailer, email: Email) -> Result<MessageId, SendError> {
+        mailer.send_http(email).await.map(|receipt| receipt.id)
+    }
+
+    async fn send_with_receipt(
+        &self,
+        mailer: &Mailer,
+        email: Email,
+    ) -> Result<SendReceipt, SendError> {
+        mailer.send_http(email).await
+    }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    async fn send(&self, mailer: &Mailer, email: Email) -> Result<MessageId, SendError> {
+        (**self).send(mailer, email).await
+    }
+
+    async fn send_with_receipt(
+        &self,
+        mailer: &Mailer,
+        email: Email,
+    ) -> Result<SendReceipt, SendError> {
+        (**self).send_with_receipt(mailer, email).await
+    }
+}
+
+/// Sends emails, implemented by `Mailer` and the file and mock transports, for
+/// application code to depend on instead of a particular Mailer.
+///
+/// ```
+/// use std::sync::Arc;
+/// use mailgun46::{EmailBuilder, Mailer, SendEmail, SendError};
+///
+/// struct Signup {
+///     mailer: Arc<dyn SendEmail>,
+/// }
+///
+/// impl Signup {
+///     async fn welcome(&self, to: &str) -> Result<(), SendError> {
+///         let email = EmailBuilder::default()
+///             .to(to)
+///             .subject("Welcome")
+///             .build()
+///             .map_err(SendError::InvalidEmail)?;
+///         self.mailer.send(email).await?;
+///         Ok(())
+///     }
+/// }
+///
+/// # fn example() -> Result<(), mailgun46::SetupError> {
+/// let signup = Signup { mailer: Arc::new(Mailer::from_env()?) };
+/// # Ok(())
+/// # }
+/// ```
+#[async_trait]
+pub trait SendEmail: Send + Sync {
+    async fn send(&self, email: Email) -> Result<MessageId, SendError>;
+}
+
+#[async_trait]
+impl SendEmail for Mailer {
+    async fn send(&self, email: Email) -> Result<MessageId, SendError> {
+        email.send(self).await
+    }
+}
+
+#[async_trait]
+impl<T: SendEmail + ?Sized> SendEmail for Arc<T> {
+    async fn send(&self, email: Email) -> Result<MessageId, SendError> {
+        (**self).send(email).await
+    }
+}
+
+pub use file::FileTransport;
+
+#[cfg(feature = "mock")]
+pub use mock::MockTransport;
+
+mod file {
+    use std::{
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use async_trait::async_trait;
+    use chrono::Utc;
+
+    use super::{SendEmail, Transport};
+    use crate::{Email, Mailer, MessageId, SendError};
+
+    /// Numbers the emails written by this process, in case two share a timestamp.
+    static WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+    /// Writes emails to a directory instead of sending them, so local development never
+    /// sends real mail. Each email becomes an `<id>.eml` file, see `Email::to_eml`,
+    /// and an `<id>.json` file with the fields it would have been sent with.
+    /// The directory is created when missing.
+    ///
+    /// Selected by `Mailer::from_env` with `MAILER46_TRANSPORT=file:/tmp/outbox`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct FileTransport {
+        dir: PathBuf,
+    }
+
+    impl FileTransport {
+        pub fn new(dir: impl Into<PathBuf>) -> Self {
+            Self { dir: dir.into() }
+        }
+
+        pub fn dir(&self) -> &Path {
+            &self.dir
+        }
+
+        /// Parses a `TRANSPORT` variable, None for sending over http.
+        pub(crate) fn from_var(value: &str) -> Result<Option<Self>, String> {
+            match value.trim() {
+                "" | "http" => Ok(None),
+                value => match value.strip_prefix("file:") {
+                    Some(dir) if !dir.is_empty() => Ok(Some(Self::new(dir))),
+                    _ => Err(format!(
+                        "expected `http` or `file:<directory>`, got `{}`",
+                        value
+                    )),
+                },
+            }
+        }
+
+        async fn write(&self, path: PathBuf, contents: impl AsRef<[u8]>) -> Result<(), SendError> {
+            tokio::fs::write(&path, contents)
+                .await
+                .map_err(|err| SendError::Io(format!("{}: {}", path.display(), err)))
+        }
+
+        async fn write_email(&self, email: Email) -> Result<MessageId, SendError> {
+            let eml = email.to_eml().map_err(SendError::InvalidEmail)?;
+            let json =
+                serde_json::to_vec_pretty(&email).map_err(|err| SendError::Io(err.to_string()))?;
+
+            let name = format!(
+                "{}-{}",
+                Utc::now().format("%Y%m%d-%H%M%S%.6f"),
+                WRITTEN.fetch_add(1, Ordering::Relaxed) + 1
+            );
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .map_err(|err| SendError::Io(format!("{}: {}", self.dir.display(), err)))?;
+            self.write(self.dir.join(format!("{}.json", name)), json)
+                .await?;
+            self.write(self.dir.join(format!("{}.eml", name)), eml)
+                .await?;
+            Ok(MessageId(format!("<{}@file>", name)))
+        }
+    }
+
+    #[async_trait]
+    impl Transport for FileTransport {
+        async fn send(&self, _mailer: &Mailer, email: Email) -> Result<MessageId, SendError> {
+            self.write_email(email).await
+        }
+    }
+
+    /// Writes the email as given, without the from address or options of a Mailer.
+    #[async_trait]
+    impl SendEmail for FileTransport {
+        async fn send(&self, email: Email) -> Result<MessageId, SendError> {
+            self.write_email(email).await
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{EmailBuilder, MailerBuilder};
+
+        #[tokio::test]
+        async fn writes_emails() {
+            let dir = std::env::temp_dir().join(format!("mailgun46-outbox-{}", std::process::id()));
+            let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+                .transport(FileTransport::new(&dir))
+                .build()
+                .expect("Creating Mailer");
+
+            let id = EmailBuilder::default()
+                .to("someone@example.com")
+                .subject("Hello")
+                .text_body("Written to disk")
+                .tag("welcome")
+                .build()
+                .unwrap()
+                .send(&mailer)
+                .await
+                .expect("Writing email");
+            let name = id
+                .as_ref()
+                .trim_matches(['<', '>'])
+                .trim_end_matches("@file");
+
+            let eml = std::fs::read_to_string(dir.join(format!("{}.eml", name))).unwrap();
+            assert!(eml.contains("\r\nFrom: noreply@fakedomain\r\n"));
+            assert!(eml.contains("\r\n\r\nWritten to disk\r\n"));
+            let json: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(dir.join(format!("{}.json", name))).unwrap())
+                    .unwrap();
+            assert_eq!(json["o:tag"], serde_json::json!(["welcome"]));
+            std::fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(FileTransport::from_var("http"), Ok(None));
+            assert_eq!(
+                FileTransport::from_var("file:/tmp/outbox"),
+                Ok(Some(FileTransport::new("/tmp/outbox")))
+            );
+            assert!(FileTransport::from_var("smtp").is_err());
+        }
+    }
+}
+
+#[cfg(feature = "mock")]
+mod mock {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    use async_trait::async_trait;
+
+    use super::{SendEmail, Transport};
+    use crate::{Email, Mailer, MessageId, SendError};
+
+    /// Records sent emails in memory instead of sending them, for tests.
+    /// Clones share the same records.
+    ///
+    /// ```
+    /// use mailgun46::{EmailBuilder, MailerBuilder, transport::MockTransport};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error + 'static>> {
+    /// let mock = MockTransport::new();
+    /// let mailer = MailerBuilder::new("example.com", "token")
+    ///     .transport(mock.clone())
+    ///     .build()?;
+    /// EmailBuilder::default().to("someone@example.com").build()?.send(&mailer).await?;
+    /// assert_eq!(mock.sent().len(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[derive(Debug, Clone, Default)]
+    pub struct MockTransport {
+        inner: Arc<Mutex<Inner>>,
+    }
+
+    #[derive(Debug, Default)]
+    struct Inner {
+        sent: Vec<Email>,
+        replies: VecDeque<Result<MessageId, SendError>>,
+    }
+
+    impl MockTransport {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Queues the reply for the next send. Without queued replies sends succeed
+        /// with ids like `<1@mock>`.
+        pub fn reply_with_id(&self, id: impl Into<String>) -> &Self {
+            self.lock().replies.push_back(Ok(MessageId(id.into())));
+            self
+        }
+
+        /// Queues an error as the reply for the next send.
+        pub fn reply_with_error(&self, err: SendError) -> &Self {
+            self.lock().replies.push_back(Err(err));
+            self
+        }
+
+        /// All emails sent so far, in order.
+        pub fn sent(&self) -> Vec<Email> {
+            self.lock().sent.clone()
+        }
+
+        pub fn clear(&self) {
+            self.lock().sent.clear();
+        }
+
+        fn record(&self, email: Email) -> Result<MessageId, SendError> {
+            let mut inner = self.lock();
+            inner.sent.push(email);
+            let n = inner.sent.len();
+            inner
+                .replies
+                .pop_front()
+                .unwrap_or_else(|| Ok(MessageId(format!("<{}@mock>", n))))
+        }
+
+        fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+            self.inner
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    #[async_trait]
+    impl Transport for MockTransport {
+        async fn send(&self, _mailer: &Mailer, email: Email) -> Result<MessageId, SendError> {
+            self.record(email)
+        }
+    }
+
+    /// Records the email as given, without the from address or options of a Mailer.
+    #[async_trait]
+    impl SendEmail for MockTransport {
+        async fn send(&self, email: Email) -> Result<MessageId, SendError> {
+            self.record(email)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{EmailBuilder, MailerBuilder};
+
+        #[tokio::test]
+        async fn records_emails() {
+            let mock = MockTransport::new();
+            mock.reply_with_error(SendError::Http("down".into()));
+            let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+                .transport(mock.clone())
+                .build()
+                .expect("Creating Mailer");
+
+            let email = EmailBuilder::default().to("someone").build().unwrap();
+            assert!(email.clone().send(&mailer).await.is_err());
+            assert_eq!(email.send(&mailer).await, Ok(MessageId("<2@mock>".into())));
+
+            let sent = mock.sent();
+            assert_eq!(sent.len(), 2);
+            assert_eq!(sent[0].from.as_deref(), Some("noreply@fakedomain"));
+        }
+
+        #[tokio::test]
+        async fn sends_as_send_email() {
+            async fn welcome(sender: &dyn SendEmail) -> Result<MessageId, SendError> {
+                let email = EmailBuilder::default().to("someone").build().unwrap();
+                sender.send(email).await
+            }
+
+            let mock = MockTransport::new();
+            let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+                .transport(mock.clone())
+                .build()
+                .expect("Creating Mailer");
+
+            assert_eq!(welcome(&mailer).await, Ok(MessageId("<1@mock>".into())));
+            assert_eq!(welcome(&mock).await, Ok(MessageId("<2@mock>".into())));
+            let sent = mock.sent();
+            assert_eq!(sent[0].from.as_deref(), Some("noreply@fakedomain"));
+            assert_eq!(sent[1].from, None);
+        }
+
+        #[tokio::test]
+        async fn records_mime_and_resends() {
+            let mock = MockTransport::new();
+            let mailer = MailerBuilder::new("fakedomain", "tomatotoken")
+                .transport(mock.clone())
+                .build()
+                .expect("Creating Mailer");
+
+            let mime = "From: a@fakedomain\r\nSubject: Raw\r\n\r\nHello";
+            mailer
+                .send_mime("someone@example.com", mime)
+                .await
+                .expect("Sending mime");
+            let sent = mock.sent();
+            assert_eq!(sent[0].to(), "someone@example.com");
+            assert_eq!(sent[0].mime(), Some(mime.as_bytes()));
+            assert_eq!(sent[0].from, None);
+            assert_eq!(sent[0].to_eml().unwrap(), mime);
+            let rebuilt = sent[0].clone().into_builder().build().unwrap();
+            assert_eq!(rebuilt.mime(), Some(mime.as_bytes()));
+
+            let url = "https://storage-us-east4.api.mailgun.net/v3/domains/fakedomain/messages/Ag";
+            mailer
+                .resend(url, ["someone@example.com"])
+                .await
+                .expect("Resending");
+            let sent = mock.sent();
+            assert_eq!(sent[1].stored_url(), Some(url));
+            assert_eq!(sent[1].to(), "someone@example.com");
+        }
+    }
+}
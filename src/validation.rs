@@ -0,0 +1,114 @@
+//! Checking addresses before sending with the validation API, `/v4/address/validate`.
+//!
+//! ```
+//! use mailgun46::Mailer;
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let validation = mailer.validate_address("someone@gmial.com").await?;
+//! if !validation.is_valid() {
+//!     println!("Did you mean {:?}?", validation.did_you_mean);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::{Mailer, SendError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationResult {
+    Deliverable,
+    Undeliverable,
+    /// Deliverable, but likely to hurt the sender reputation.
+    DoNotSend,
+    /// The domain accepts mail for any address.
+    CatchAll,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Risk {
+    Low,
+    Medium,
+    High,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct AddressValidation {
+    pub address: String,
+    pub result: ValidationResult,
+    pub risk: Risk,
+
+    /// Why the address is not deliverable, such as `mailbox_does_not_exist`.
+    #[serde(default)]
+    pub reason: Vec<String>,
+
+    /// A suggested correction for typos, such as `gmail.com` for `gmial.com`.
+    #[serde(default)]
+    pub did_you_mean: Option<String>,
+
+    #[serde(default)]
+    pub is_disposable_address: bool,
+
+    #[serde(default)]
+    pub is_role_address: bool,
+}
+
+impl AddressValidation {
+    pub fn is_valid(&self) -> bool {
+        self.result == ValidationResult::Deliverable
+    }
+}
+
+impl Mailer {
+    /// Validates a single address. Validations are billed separately by Mailgun.
+    pub async fn validate_address(&self, address: &str) -> Result<AddressValidation, SendError> {
+        self.execute(
+            self.get(self.api_url(&["v4", "address", "validate"]))
+                .query(&[("address", address)]),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn validate_address() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v4/address/validate"))
+            .and(matchers::query_param("address", "someone@gmial.com"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "address": "someone@gmial.com",
+                "did_you_mean": "someone@gmail.com",
+                "is_disposable_address": false,
+                "is_role_address": false,
+                "reason": ["no_mx"],
+                "result": "undeliverable",
+                "risk": "high"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let validation = mailer
+            .validate_address("someone@gmial.com")
+            .await
+            .expect("Validating address");
+        assert!(!validation.is_valid());
+        assert_eq!(validation.risk, Risk::High);
+        assert_eq!(
+            validation.did_you_mean.as_deref(),
+            Some("someone@gmail.com")
+        );
+    }
+}
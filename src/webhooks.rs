@@ -0,0 +1,415 @@
+//! Configuring the webhooks of a domain, and parsing and verifying the payloads
+//! Mailgun POSTs to them.
+//!
+//! ```
+//! use mailgun46::{Mailer, webhooks::WebhookKind};
+//! # async fn example(mailer: Mailer) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! mailer
+//!     .set_webhook(WebhookKind::Delivered, &["https://staging.example.com/hooks/mailgun"])
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ```
+//! use mailgun46::webhooks::WebhookPayload;
+//! # fn example(body: &[u8], signing_key: &str) -> Result<(), Box<dyn std::error::Error + 'static>> {
+//! let payload: WebhookPayload = serde_json::from_slice(body)?;
+//! if !payload.verify(signing_key) {
+//!     return Err("Invalid or expired webhook signature".into());
+//! }
+//! // Also reject tokens seen within the last `MAX_WEBHOOK_AGE`, against replays.
+//! println!("{:?}: {:?}", payload.kind(), payload.event_data);
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use serde::de::IgnoredAny;
+use sha2::Sha256;
+
+use crate::{events::Event, Mailer, SendError};
+
+/// Mailgun recommends rejecting webhooks signed longer ago than this.
+pub const MAX_WEBHOOK_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// The webhooks that can be configured on a domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookKind {
+    Accepted,
+    Delivered,
+    PermanentFail,
+    TemporaryFail,
+    Complained,
+    Opened,
+    Clicked,
+    Unsubscribed,
+}
+
+impl WebhookKind {
+    const ALL: [Self; 8] = [
+        Self::Accepted,
+        Self::Delivered,
+        Self::PermanentFail,
+        Self::TemporaryFail,
+        Self::Complained,
+        Self::Opened,
+        Self::Clicked,
+        Self::Unsubscribed,
+    ];
+
+    /// The id of the webhook in Mailgun's API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Accepted => "accepted",
+            Self::Delivered => "delivered",
+            Self::PermanentFail => "permanent_fail",
+            Self::TemporaryFail => "temporary_fail",
+            Self::Complained => "complained",
+            Self::Opened => "opened",
+            Self::Clicked => "clicked",
+            Self::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+/// The JSON body of a webhook request.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct WebhookPayload {
+    pub signature: WebhookSignature,
+    #[serde(rename = "event-data")]
+    pub event_data: Event,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct WebhookSignature {
+    pub timestamp: String,
+    pub token: String,
+    pub signature: String,
+}
+
+impl WebhookPayload {
+    /// Verifies the payload was signed by Mailgun with the given webhook signing key
+    /// within the last `MAX_WEBHOOK_AGE`, so a captured request can not be replayed
+    /// later. Within that window it can, so callers should also remember the tokens of
+    /// accepted payloads for `MAX_WEBHOOK_AGE` and reject a token seen before.
+    pub fn verify(&self, signing_key: impl AsRef<[u8]>) -> bool {
+        self.verify_with_max_age(signing_key, MAX_WEBHOOK_AGE)
+    }
+
+    /// Same as `verify`, accepting payloads signed at most `max_age` ago.
+    pub fn verify_with_max_age(&self, signing_key: impl AsRef<[u8]>, max_age: Duration) -> bool {
+        is_fresh(&self.signature.timestamp, max_age)
+            && verify_signature(
+                signing_key,
+                &self.signature.timestamp,
+                &self.signature.token,
+                &self.signature.signature,
+            )
+    }
+
+    /// The webhook that triggered this payload, failures are split on severity.
+    pub fn kind(&self) -> Option<WebhookKind> {
+        match &self.event_data {
+            Event::Accepted(_) => Some(WebhookKind::Accepted),
+            Event::Delivered(_) => Some(WebhookKind::Delivered),
+            Event::Failed(details) if details.severity.as_deref() == Some("temporary") => {
+                Some(WebhookKind::TemporaryFail)
+            }
+            Event::Failed(_) => Some(WebhookKind::PermanentFail),
+            Event::Complained(_) => Some(WebhookKind::Complained),
+            Event::Opened(_) => Some(WebhookKind::Opened),
+            Event::Clicked(_) => Some(WebhookKind::Clicked),
+            Event::Unsubscribed(_) => Some(WebhookKind::Unsubscribed),
+            Event::Stored(_) | Event::Other => None,
+        }
+    }
+}
+
+/// Whether a webhook timestamp, in seconds since the epoch, is at most `max_age` away
+/// from now. Timestamps as far ahead are accepted too, for clocks that differ.
+pub fn is_fresh(timestamp: &str, max_age: Duration) -> bool {
+    let timestamp = match timestamp.trim().parse::<u64>() {
+        Ok(timestamp) => Duration::from_secs(timestamp),
+        Err(_) => return false,
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.abs_diff(timestamp) <= max_age
+}
+
+/// Verifies a webhook signature: the hex encoded HMAC-SHA256 of timestamp and token,
+/// keyed on the webhook signing key. The comparison is constant time.
+/// Only the signature is checked, see `is_fresh` for rejecting old timestamps.
+pub fn verify_signature(
+    signing_key: impl AsRef<[u8]>,
+    timestamp: &str,
+    token: &str,
+    signature: &str,
+) -> bool {
+    let signature = match decode_hex(signature) {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_key.as_ref())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(token.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+#[derive(serde::Deserialize)]
+struct WebhookUrls {
+    #[serde(default)]
+    urls: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct WebhooksReply {
+    webhooks: HashMap<String, WebhookUrls>,
+}
+
+#[derive(serde::Deserialize)]
+struct WebhookReply {
+    webhook: WebhookUrls,
+}
+
+impl Mailer {
+    /// The URLs of each webhook configured on the Mailer's domain.
+    /// Webhooks unknown to this crate are left out.
+    pub async fn webhooks(&self) -> Result<HashMap<WebhookKind, Vec<String>>, SendError> {
+        let reply: WebhooksReply = self.execute(self.get(self.webhooks_url(None))).await?;
+        Ok(reply
+            .webhooks
+            .into_iter()
+            .filter_map(|(name, hook)| {
+                let kind = WebhookKind::ALL.into_iter().find(|k| k.as_str() == name)?;
+                Some((kind, hook.urls))
+            })
+            .collect())
+    }
+
+    pub async fn webhook(&self, kind: WebhookKind) -> Result<Vec<String>, SendError> {
+        let reply: WebhookReply = self
+            .execute(self.get(self.webhooks_url(Some(kind))))
+            .await?;
+        Ok(reply.webhook.urls)
+    }
+
+    /// Creates a webhook, Mailgun accepts up to 3 URLs per webhook.
+    /// Fails if the webhook already exists, see `set_webhook`.
+    pub async fn create_webhook(&self, kind: WebhookKind, urls: &[&str]) -> Result<(), SendError> {
+        let mut form = vec![("id", kind.as_str())];
+        form.extend(urls.iter().map(|url| ("url", *url)));
+        let _: IgnoredAny = self
+            .execute(self.post(self.webhooks_url(None)).form(&form))
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the URLs of an existing webhook.
+    pub async fn update_webhook(&self, kind: WebhookKind, urls: &[&str]) -> Result<(), SendError> {
+        let form: Vec<_> = urls.iter().map(|url| ("url", *url)).collect();
+        let _: IgnoredAny = self
+            .execute(self.put(self.webhooks_url(Some(kind))).form(&form))
+            .await?;
+        Ok(())
+    }
+
+    /// Points the webhook at the given URLs, creating it if it does not exist.
+    pub async fn set_webhook(&self, kind: WebhookKind, urls: &[&str]) -> Result<(), SendError> {
+        match self.update_webhook(kind, urls).await {
+            Err(err) if err.status() == Some(StatusCode::NOT_FOUND) => {
+                self.create_webhook(kind, urls).await
+            }
+            result => result,
+        }
+    }
+
+    pub async fn delete_webhook(&self, kind: WebhookKind) -> Result<(), SendError> {
+        let _: IgnoredAny = self
+            .execute(self.delete(self.webhooks_url(Some(kind))))
+            .await?;
+        Ok(())
+    }
+
+    fn webhooks_url(&self, kind: Option<WebhookKind>) -> reqwest::Url {
+        let mut path = vec!["v3", "domains", &self.domain, "webhooks"];
+        path.extend(kind.map(|kind| kind.as_str()));
+        self.api_url(&path)
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    const PAYLOAD: &str = r#"{
+        "signature": {
+            "timestamp": "1614172276",
+            "token": "abcdefghijklmnopqrstuvwxyz0123456789abcdefghijklmn",
+            "signature": "dbcdfe6c20dc5717d0e65ceaf2ad253256b8e5f7de23131fed2286d384e1e393"
+        },
+        "event-data": {
+            "event": "failed",
+            "id": "czsjqFATSlC3QtAK-C80nw",
+            "timestamp": 1614172276.1,
+            "severity": "permanent",
+            "recipient": "someone@example.com"
+        }
+    }"#;
+
+    fn sign(signing_key: &str, timestamp: &str, token: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(signing_key.as_bytes()).unwrap();
+        mac.update(timestamp.as_bytes());
+        mac.update(token.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    #[test]
+    fn parse_and_verify() {
+        let mut payload: WebhookPayload = serde_json::from_str(PAYLOAD).expect("Parsing payload");
+
+        assert_eq!(payload.kind(), Some(WebhookKind::PermanentFail));
+        let WebhookSignature {
+            timestamp,
+            token,
+            signature,
+        } = &payload.signature;
+        assert!(verify_signature("key-signing", timestamp, token, signature));
+        assert!(!verify_signature(
+            "another-key",
+            timestamp,
+            token,
+            signature
+        ));
+        // Signed in 2021, long expired.
+        assert!(!payload.verify("key-signing"));
+        let token = token.clone();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        payload.signature.timestamp = now.to_string();
+        payload.signature.signature = sign("key-signing", &payload.signature.timestamp, &token);
+        assert!(payload.verify("key-signing"));
+        assert!(!payload.verify("another-key"));
+
+        payload.signature.timestamp = (now - 600).to_string();
+        payload.signature.signature = sign("key-signing", &payload.signature.timestamp, &token);
+        assert!(!payload.verify("key-signing"));
+        assert!(payload.verify_with_max_age("key-signing", Duration::from_secs(900)));
+        assert!(!is_fresh("soon", MAX_WEBHOOK_AGE));
+    }
+
+    #[test]
+    fn rejects_malformed_signature() {
+        assert!(!verify_signature(
+            "key-signing",
+            "1614172276",
+            "token",
+            "zz"
+        ));
+        assert!(!verify_signature(
+            "key-signing",
+            "1614172276",
+            "token",
+            "abc"
+        ));
+    }
+
+    #[tokio::test]
+    async fn manage_webhooks() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v3/domains/fakedomain/webhooks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "webhooks": {
+                    "delivered": {"urls": ["https://old.example.com/hooks"]},
+                    "permanent_fail": {"urls": ["https://old.example.com/hooks"]},
+                    "something_new": {"urls": ["https://old.example.com/hooks"]}
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path(
+                "/v3/domains/fakedomain/webhooks/permanent_fail",
+            ))
+            .and(matchers::body_string(
+                "url=https%3A%2F%2Fa.example.com&url=https%3A%2F%2Fb.example.com",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "message": "Webhook has been updated"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path("/v3/domains/fakedomain/webhooks/opened"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "message": "Webhook not found"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/v3/domains/fakedomain/webhooks"))
+            .and(matchers::body_string(
+                "id=opened&url=https%3A%2F%2Fa.example.com",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "message": "Webhook has been created"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mailer = Mailer::new_with_mg_url(server.uri(), "fakedomain", "tomatotoken")
+            .expect("Creating Mailer");
+
+        let hooks = mailer.webhooks().await.expect("Listing webhooks");
+        assert_eq!(hooks.len(), 2);
+        assert_eq!(
+            hooks[&WebhookKind::Delivered],
+            ["https://old.example.com/hooks"]
+        );
+
+        mailer
+            .set_webhook(
+                WebhookKind::PermanentFail,
+                &["https://a.example.com", "https://b.example.com"],
+            )
+            .await
+            .expect("Updating webhook");
+        mailer
+            .set_webhook(WebhookKind::Opened, &["https://a.example.com"])
+            .await
+            .expect("Creating webhook");
+    }
+}
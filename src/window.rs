@@ -0,0 +1,110 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+
+/// A daily window of local time in the recipient's timezone, for `EmailBuilder::deliver_during`.
+/// Windows may wrap midnight, such as 22:00 to 06:00.
+///
+/// Any `chrono::TimeZone` works, such as `FixedOffset` or `chrono_tz::Tz` for
+/// daylight saving aware zones.
+///
+/// ```
+/// use chrono::{FixedOffset, NaiveTime};
+/// use mailgun46::{DeliveryWindow, EmailBuilder};
+///
+/// let stockholm = FixedOffset::east_opt(3600).unwrap();
+/// let window = DeliveryWindow::new(
+///     stockholm,
+///     NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+///     NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+/// );
+/// let email = EmailBuilder::default()
+///     .to("someone@example.com")
+///     .deliver_during(window)
+///     .build();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryWindow<Tz: TimeZone> {
+    timezone: Tz,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl<Tz: TimeZone> DeliveryWindow<Tz> {
+    /// From `start` until, not including, `end`.
+    pub fn new(timezone: Tz, start: NaiveTime, end: NaiveTime) -> Self {
+        Self {
+            timezone,
+            start,
+            end,
+        }
+    }
+
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// When to deliver a message created at `now`, None if `now` is within the window.
+    pub(crate) fn next_delivery(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = now.with_timezone(&self.timezone).naive_local();
+        if self.contains(local.time()) {
+            return None;
+        }
+
+        let day = if local.time() < self.start {
+            local.date()
+        } else {
+            local.date() + Duration::days(1)
+        };
+        Some(self.resolve(day))
+    }
+
+    /// The start of the window on the given day. Starts skipped by a daylight saving
+    /// change move forward until the clock exists again.
+    fn resolve(&self, day: NaiveDate) -> DateTime<Utc> {
+        let mut start = day.and_time(self.start);
+        loop {
+            if let Some(at) = self.timezone.from_local_datetime(&start).earliest() {
+                return at.with_timezone(&Utc);
+            }
+            start += Duration::minutes(15);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn next_delivery() {
+        let hour = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+        let plus_two = FixedOffset::east_opt(2 * 3600).unwrap();
+        let morning = DeliveryWindow::new(plus_two, hour(9), hour(10));
+
+        assert_eq!(
+            morning.next_delivery(at("2022-05-01T05:00:00Z")),
+            Some(at("2022-05-01T07:00:00Z"))
+        );
+        assert_eq!(morning.next_delivery(at("2022-05-01T07:30:00Z")), None);
+        assert_eq!(
+            morning.next_delivery(at("2022-05-01T08:00:00Z")),
+            Some(at("2022-05-02T07:00:00Z"))
+        );
+
+        let night = DeliveryWindow::new(Utc, hour(22), hour(6));
+        assert_eq!(night.next_delivery(at("2022-05-01T23:00:00Z")), None);
+        assert_eq!(night.next_delivery(at("2022-05-01T03:00:00Z")), None);
+        assert_eq!(
+            night.next_delivery(at("2022-05-01T12:00:00Z")),
+            Some(at("2022-05-01T22:00:00Z"))
+        );
+    }
+}